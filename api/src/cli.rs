@@ -0,0 +1,129 @@
+use clap::{Parser, Subcommand};
+use futures::TryStreamExt;
+use lib::{
+    db::{self, user::Role},
+    export::{
+        self,
+        dataset::{self, DatasetFormat},
+        ExportFormat,
+    },
+};
+use sea_orm::DatabaseConnection;
+use std::path::PathBuf;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+#[derive(Parser, Debug)]
+#[command(about = "Collects and analyzes your listening history")]
+pub struct Cli {
+    /// The TOML config file, defaults to UNWRAPPED_CONFIG or unwrapped.toml if either exists
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the API and the poller, the default when no command is given
+    Serve,
+    /// Export the full listening history of a user
+    Export {
+        /// The ID of the user to export
+        #[arg(long)]
+        user: String,
+        /// One of csv, json or jsonl
+        #[arg(long, default_value = "csv")]
+        format: ExportFormat,
+        /// The file to write the export to, defaults to stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Export the full listening history of a user as a dataset partitioned by year and month
+    ExportDataset {
+        /// The ID of the user to export
+        #[arg(long)]
+        user: String,
+        /// Either parquet or arrow
+        #[arg(long, default_value = "parquet")]
+        format: DatasetFormat,
+        /// The directory to write the partitions to
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Make a user an admin or a member, the way the first admin of an instance is made
+    SetRole {
+        /// The ID of the user
+        #[arg(long)]
+        user: String,
+        /// Either admin or member
+        #[arg(long)]
+        role: Role,
+    },
+}
+
+/// Make sure a user exists before exporting their listening history
+async fn check_user(conn: &DatabaseConnection, user_id: &str) -> Result<(), String> {
+    db::user::get_user(conn, user_id)
+        .await
+        .map_err(|db_err| db_err.to_string())?
+        .ok_or(format!("User {} not found", user_id))?;
+    Ok(())
+}
+
+/// Write the listening history of a user to a file or stdout
+pub async fn export(
+    conn: DatabaseConnection,
+    user_id: String,
+    format: ExportFormat,
+    output: Option<PathBuf>,
+) -> Result<(), String> {
+    check_user(&conn, &user_id).await?;
+    let mut writer: Box<dyn AsyncWrite + Unpin> = match output {
+        Some(path) => Box::new(
+            tokio::fs::File::create(&path)
+                .await
+                .map_err(|io_err| format!("Failed to create {}: {}", path.display(), io_err))?,
+        ),
+        None => Box::new(tokio::io::stdout()),
+    };
+    let mut plays = Box::pin(export::export_plays(conn, user_id, format));
+    while let Some(chunk) = plays
+        .try_next()
+        .await
+        .map_err(|db_err| db_err.to_string())?
+    {
+        writer
+            .write_all(chunk.as_bytes())
+            .await
+            .map_err(|io_err| format!("Failed to write export: {}", io_err))?;
+    }
+    writer
+        .flush()
+        .await
+        .map_err(|io_err| format!("Failed to write export: {}", io_err))
+}
+
+/// Write the listening history of a user to a directory of partitions, listing the files written
+pub async fn export_dataset(
+    conn: DatabaseConnection,
+    user_id: String,
+    format: DatasetFormat,
+    output: PathBuf,
+) -> Result<(), String> {
+    check_user(&conn, &user_id).await?;
+    let files = dataset::write_dataset(conn, user_id, &output, format).await?;
+    for file in files {
+        println!("{}", file.display());
+    }
+    Ok(())
+}
+
+/// Set the role of a user
+pub async fn set_role(conn: DatabaseConnection, user_id: String, role: Role) -> Result<(), String> {
+    let user = db::user::set_user_role(&conn, &user_id, role)
+        .await
+        .map_err(|db_err| db_err.to_string())?
+        .ok_or(format!("User {} not found", user_id))?;
+    println!("{} is now {}", user.id, user.role);
+    Ok(())
+}
@@ -1,8 +1,13 @@
 mod assets;
+mod cli;
+mod poller;
 mod routes;
 
-use lib::db;
+use clap::Parser;
+use cli::{Cli, Command};
+use lib::{config::Config, db};
 use migration::{Migrator, MigratorTrait};
+use std::sync::Arc;
 use tokio::net::TcpListener;
 use tower_http::{
     trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer},
@@ -13,7 +18,8 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
 async fn main() {
-    // Initialize trace subscriber
+    let cli = Cli::parse();
+    // Initialize trace subscriber, logging to stderr so exports can be written to stdout
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
@@ -22,17 +28,76 @@ async fn main() {
                 "unwrapped=debug,tower_http=debug,axum::rejection=trace".into()
             }),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .init();
+    // Load and check every setting before anything else is started
+    let config = Config::load(cli.config.as_deref()).unwrap_or_else(|config_err| {
+        eprintln!("{}", config_err);
+        std::process::exit(1);
+    });
+    let keyring = config
+        .security
+        .keyring()
+        .expect("Token encryption keys were validated with the config");
+    // Installed before migrating, since migrations encrypt the tokens of existing accounts
+    crypto::install_keyring(keyring);
     // Initialize database connection and migrate
-    let connection = db::get_connection()
+    let connection = db::get_connection(&config.database)
         .await
         .expect("Failed to connect to database");
     Migrator::up(&connection, None)
         .await
         .expect("Failed to migrate database");
+    // Move account tokens off master keys that are no longer active
+    db::user::rotate_token_keys(&connection)
+        .await
+        .expect("Failed to rotate token encryption keys");
+    // Commands other than serving run once and exit
+    let ran = match cli.command {
+        Some(Command::Export {
+            user,
+            format,
+            output,
+        }) => Some(
+            cli::export(connection.clone(), user, format, output)
+                .await
+                .map_err(|message| format!("Export failed: {}", message)),
+        ),
+        Some(Command::ExportDataset {
+            user,
+            format,
+            output,
+        }) => Some(
+            cli::export_dataset(connection.clone(), user, format, output)
+                .await
+                .map_err(|message| format!("Export failed: {}", message)),
+        ),
+        Some(Command::SetRole { user, role }) => Some(
+            cli::set_role(connection.clone(), user, role)
+                .await
+                .map_err(|message| format!("Setting the role failed: {}", message)),
+        ),
+        Some(Command::Serve) | None => None,
+    };
+    if let Some(result) = ran {
+        if let Err(message) = result {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // Start polling the currently playing tracks in the background
+    tokio::spawn(poller::run(
+        connection.clone(),
+        config.providers.spotify.credentials(),
+        config.scheduler.poll_interval(),
+    ));
     // Construct shared app state
-    let state = routes::AppState { connection };
+    let bind = config.server.bind;
+    let state = routes::AppState {
+        connection,
+        config: Arc::new(config),
+    };
     // Initialize the API
     let app = routes::router(state).layer(
         TraceLayer::new_for_http()
@@ -45,7 +110,10 @@ async fn main() {
             ),
     );
     // Start the API
-    let listener = TcpListener::bind("0.0.0.0:8000").await.unwrap();
+    let listener = TcpListener::bind(bind).await.unwrap_or_else(|io_err| {
+        eprintln!("Failed to listen on {}: {}", bind, io_err);
+        std::process::exit(1);
+    });
     tracing::debug!("listening on http://{}", listener.local_addr().unwrap());
     axum::serve(listener, app).await.unwrap();
 }
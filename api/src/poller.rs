@@ -0,0 +1,160 @@
+use entity::{account, album, artist, play_session, track};
+use lib::{
+    db::{self, user::Provider, DBError},
+    music::{
+        playback::{PlaySession, PlaybackTracker},
+        spotify::{CurrentlyPlaying, SpotifyClient, SpotifyCredentials, SpotifyError, Track},
+    },
+};
+use sea_orm::{sqlx::types::chrono::Utc, ActiveValue::NotSet, DatabaseConnection, Set};
+use std::{collections::HashMap, time::Duration};
+use tracing::{debug, error};
+
+#[derive(Debug)]
+enum PollError {
+    Spotify(SpotifyError),
+    Database(DBError),
+}
+
+impl std::fmt::Display for PollError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PollError::Spotify(err) => write!(f, "Spotify Error {}: {}", err.status, err.message),
+            PollError::Database(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<SpotifyError> for PollError {
+    fn from(err: SpotifyError) -> Self {
+        PollError::Spotify(err)
+    }
+}
+
+impl From<DBError> for PollError {
+    fn from(err: DBError) -> Self {
+        PollError::Database(err)
+    }
+}
+
+/// Polls the currently playing track of every Spotify account on an interval,
+/// saving a play session every time a track finishes playing
+pub async fn run(conn: DatabaseConnection, credentials: SpotifyCredentials, interval: Duration) {
+    // Each account has its own tracker, holding the track that is currently playing
+    let mut trackers: HashMap<i32, PlaybackTracker> = HashMap::new();
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let accounts =
+            match db::user::get_accounts_by_provider(&conn, Provider::Spotify.as_str()).await {
+                Ok(accounts) => accounts,
+                Err(db_err) => {
+                    error!("Error getting accounts to poll: {:?}", db_err);
+                    continue;
+                }
+            };
+        for account in accounts {
+            let tracker = trackers.entry(account.id).or_default();
+            if let Err(poll_err) = poll_account(&conn, &credentials, account, tracker).await {
+                error!("Error polling currently playing: {}", poll_err);
+            }
+        }
+    }
+}
+
+/// Fetch the currently playing track of an account and save the previous play if it finished
+async fn poll_account(
+    conn: &DatabaseConnection,
+    credentials: &SpotifyCredentials,
+    account: account::Model,
+    tracker: &mut PlaybackTracker,
+) -> Result<(), PollError> {
+    let user_id = account.user_id.clone();
+    let current = get_currently_playing(conn, credentials, account).await?;
+    let now = Utc::now().naive_utc();
+    if let Some(session) = tracker.observe(now, current.as_ref()) {
+        debug!(
+            "Play session finished for {}: {} ({}ms)",
+            user_id, session.track.name, session.ms_played
+        );
+        save_play_session(conn, user_id, session).await?;
+    }
+    Ok(())
+}
+
+/// Fetch the currently playing track, refreshing and saving the access token if it is invalid
+async fn get_currently_playing(
+    conn: &DatabaseConnection,
+    credentials: &SpotifyCredentials,
+    account: account::Model,
+) -> Result<Option<CurrentlyPlaying>, PollError> {
+    let client = SpotifyClient::new(account.access_token.clone())
+        .set_refresh_token(Some(account.refresh_token.clone()))
+        .set_credentials(credentials.clone());
+    match client.get_currently_playing().await {
+        Ok(current) => Ok(current),
+        Err(spotify_err) if spotify_err.status == 401 => {
+            debug!("Invalid Token error, Attempting to get a new access token");
+            let new_token = client.refresh_access_token().await?;
+            db::user::update_account_tokens(conn, account, new_token.access_token.clone(), None)
+                .await?;
+            Ok(client
+                .set_access_token(new_token.access_token)
+                .get_currently_playing()
+                .await?)
+        }
+        Err(spotify_err) => Err(spotify_err.into()),
+    }
+}
+
+/// Save a play session, along with its artists, album and track unless the track was already saved
+async fn save_play_session(
+    conn: &DatabaseConnection,
+    user_id: String,
+    session: PlaySession,
+) -> Result<(), PollError> {
+    // Most sessions are of tracks that were saved before, which only need looking up by their URI
+    let saved_track = match session.track.uri() {
+        Some(uri) => db::spotify::get_track_by_uri(&uri, conn).await?,
+        None => None,
+    };
+    let db_track = match saved_track {
+        Some(db_track) => db_track,
+        None => save_track(conn, &session.track).await?,
+    };
+    db::play_session::insert_play_session(
+        play_session::ActiveModel {
+            id: NotSet,
+            user_id: Set(user_id),
+            track_id: Set(db_track.id),
+            started_at: Set(session.started_at),
+            ended_at: Set(session.ended_at),
+            ms_played: Set(session.ms_played as i64),
+            skipped: Set(session.skipped),
+        },
+        conn,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Save the artists, album and track of a play session
+async fn save_track(conn: &DatabaseConnection, track: &Track) -> Result<track::Model, PollError> {
+    // Top to bottom, artists -> album -> track
+    let album = &track.album;
+    let db_artists = db::spotify::upsert_artists(
+        album.artists.iter().map(|artist| artist.model()).collect(),
+        conn,
+    )
+    .await?;
+    let album_artists: Vec<artist::Model> = db_artists
+        .into_iter()
+        .filter(|db_artist| album.artists.iter().any(|a| a.name == db_artist.name))
+        .collect();
+    let db_albums: Vec<album::Model> =
+        db::spotify::upsert_albums_with_artists(vec![(album.model(), album_artists)], conn).await?;
+    let db_album = db_albums.into_iter().next().ok_or(DBError)?;
+    let db_tracks =
+        db::spotify::upsert_tracks_with_albums(vec![(track.model(), db_album)], conn).await?;
+    Ok(db_tracks.into_iter().next().ok_or(DBError)?)
+}
@@ -0,0 +1,170 @@
+use crate::routes::{
+    auth::{require_admin, Admin},
+    collect, AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    middleware,
+    routing::{get, post, put},
+    Extension, Json, Router,
+};
+use lib::db::{
+    self,
+    admin::{AccountStatus, InstanceStats, UserListing},
+    user::Role,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+/// The routes for managing the instance, nested under `/api/admin` and only open to admins
+pub fn get_admin_router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/users", get(list_users))
+        .route("/users/:id/role", put(set_role))
+        .route("/accounts", get(list_accounts))
+        .route("/accounts/:id/collect", post(collect_account))
+        .route("/accounts/:id/disable", post(disable_account))
+        .route("/accounts/:id/enable", post(enable_account))
+        .route("/stats", get(stats))
+        .route_layer(middleware::from_fn_with_state(state, require_admin))
+}
+
+/// Lists every user of the instance, oldest first
+async fn list_users(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<UserListing>>, (StatusCode, String)> {
+    db::admin::list_users(&state.connection)
+        .await
+        .map(Json)
+        .map_err(|db_err| {
+            error!("Error listing users: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })
+}
+
+#[derive(Deserialize, Debug)]
+struct SetRole {
+    role: Role,
+}
+
+#[derive(Serialize, Debug)]
+struct RoleChanged {
+    id: String,
+    role: String,
+}
+
+/// Makes a user an admin or a member
+/// Admins can't change their own role, so an instance is never left without an admin by accident
+async fn set_role(
+    State(state): State<AppState>,
+    Extension(Admin(admin)): Extension<Admin>,
+    Path(id): Path<String>,
+    Json(request): Json<SetRole>,
+) -> Result<Json<RoleChanged>, (StatusCode, String)> {
+    if admin.id == id {
+        return Err((
+            StatusCode::CONFLICT,
+            "Admins can't change their own role".to_string(),
+        ));
+    }
+    let user = db::user::set_user_role(&state.connection, &id, request.role)
+        .await
+        .map_err(|db_err| {
+            error!("Error setting user role: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))?;
+    info!("{} made {} {}", admin.id, user.id, user.role);
+    Ok(Json(RoleChanged {
+        id: user.id,
+        role: user.role,
+    }))
+}
+
+/// Lists every linked account of the instance with how collecting it last went
+async fn list_accounts(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<AccountStatus>>, (StatusCode, String)> {
+    db::admin::list_accounts(&state.connection)
+        .await
+        .map(Json)
+        .map_err(|db_err| {
+            error!("Error listing accounts: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })
+}
+
+/// Collects the recent tracks of an account right away, instead of waiting for its user to
+async fn collect_account(
+    State(state): State<AppState>,
+    Extension(Admin(admin)): Extension<Admin>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let account = db::user::get_account(&state.connection, id)
+        .await
+        .map_err(|db_err| {
+            error!("Error looking up account: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?
+        .ok_or((StatusCode::NOT_FOUND, "Account not found".to_string()))?;
+    if account.disabled_at.is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            "The account is disabled, enable it to collect it".to_string(),
+        ));
+    }
+    info!("{} started collecting account {}", admin.id, id);
+    collect::collect_account(&state, account).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Disables an account, so nothing is collected from it and it can't be used to log in until it's enabled again
+async fn disable_account(
+    State(state): State<AppState>,
+    Extension(Admin(admin)): Extension<Admin>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    set_disabled(&state, id, true).await?;
+    info!("{} disabled account {}", admin.id, id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Enables an account that was disabled, so it's collected and can be logged in with again
+async fn enable_account(
+    State(state): State<AppState>,
+    Extension(Admin(admin)): Extension<Admin>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    set_disabled(&state, id, false).await?;
+    info!("{} enabled account {}", admin.id, id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn set_disabled(
+    state: &AppState,
+    id: i32,
+    disabled: bool,
+) -> Result<(), (StatusCode, String)> {
+    let updated = db::user::set_account_disabled(&state.connection, id, disabled)
+        .await
+        .map_err(|db_err| {
+            error!("Error disabling account: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    if !updated {
+        return Err((StatusCode::NOT_FOUND, "Account not found".to_string()));
+    }
+    Ok(())
+}
+
+/// Gets the totals across every user of the instance
+async fn stats(State(state): State<AppState>) -> Result<Json<InstanceStats>, (StatusCode, String)> {
+    db::admin::get_instance_stats(&state.connection)
+        .await
+        .map(Json)
+        .map_err(|db_err| {
+            error!("Error getting instance stats: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })
+}
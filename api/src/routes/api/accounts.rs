@@ -0,0 +1,147 @@
+use crate::routes::{auth::CurrentUser, AppState};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use lib::{
+    db::{
+        self,
+        user::{Provider, UnlinkOutcome},
+    },
+    music::listenbrainz::ListenBrainzClient,
+};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+/// A linked account as it's listed, without its tokens
+#[derive(Serialize, Debug)]
+pub struct AccountListing {
+    pub id: i32,
+    pub provider: String,
+    /// The ID of the account on the provider
+    pub provider_id: String,
+    /// The number of plays collected from the account
+    pub plays: u64,
+}
+
+/// Lists the accounts linked to the logged in user, in the order they were linked
+/// More Spotify accounts are linked by logging in with them while already logged in, Last.fm accounts
+/// through `/auth/lastfm`, and ListenBrainz accounts with `link_listenbrainz`
+pub async fn list(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+) -> Result<Json<Vec<AccountListing>>, (StatusCode, String)> {
+    let (accounts, plays) = tokio::try_join!(
+        db::user::get_user_accounts(&state.connection, &user.id),
+        db::play_log::count_account_plays(&state.connection, &user.id),
+    )
+    .map_err(|db_err| {
+        error!("Error listing accounts: {:?}", db_err);
+        (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+    })?;
+    Ok(Json(
+        accounts
+            .into_iter()
+            .map(|account| AccountListing {
+                plays: plays.get(&account.id).copied().unwrap_or(0),
+                id: account.id,
+                provider: account.provider,
+                provider_id: account.provider_id,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LinkListenBrainz {
+    /// The user token from the ListenBrainz settings page
+    token: String,
+}
+
+/// Links a ListenBrainz account to the logged in user with its user token, which is checked with ListenBrainz
+/// Linking an account the user already linked saves the new token
+pub async fn link_listenbrainz(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Json(request): Json<LinkListenBrainz>,
+) -> Result<(StatusCode, Json<AccountListing>), (StatusCode, String)> {
+    let token = request.token.trim().to_string();
+    if token.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "A token is required".to_string()));
+    }
+    let user_name =
+        ListenBrainzClient::new(&state.config.providers.listenbrainz.api_url, token.clone())
+            .validate_token()
+            .await
+            .map_err(|listenbrainz_err| {
+                error!(
+                    "Error validating ListenBrainz token: {:?}",
+                    listenbrainz_err
+                );
+                match listenbrainz_err.status {
+                    401 => (
+                        StatusCode::BAD_REQUEST,
+                        "The ListenBrainz token isn't valid".to_string(),
+                    ),
+                    _ => (StatusCode::BAD_GATEWAY, listenbrainz_err.message),
+                }
+            })?;
+    let account = db::user::link_account(
+        &state.connection,
+        &user.id,
+        db::user::LinkAccountOptions {
+            access_token: token,
+            // User tokens don't expire, they're only ever reset by the user
+            refresh_token: String::new(),
+            provider: Provider::ListenBrainz.to_string(),
+            provider_id: user_name,
+        },
+    )
+    .await
+    .map_err(|db_err| {
+        error!("Error linking account: {:?}", db_err);
+        (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+    })?
+    .ok_or((
+        StatusCode::CONFLICT,
+        "This ListenBrainz account is already linked to another user".to_string(),
+    ))?;
+    let plays = db::play_log::count_account_plays(&state.connection, &user.id)
+        .await
+        .map_err(|db_err| {
+            error!("Error counting account plays: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    Ok((
+        StatusCode::CREATED,
+        Json(AccountListing {
+            plays: plays.get(&account.id).copied().unwrap_or(0),
+            id: account.id,
+            provider: account.provider,
+            provider_id: account.provider_id,
+        }),
+    ))
+}
+
+/// Unlinks an account from the logged in user, the plays collected from it are kept
+pub async fn unlink(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let outcome = db::user::unlink_account(&state.connection, &user.id, id)
+        .await
+        .map_err(|db_err| {
+            error!("Error unlinking account: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    match outcome {
+        UnlinkOutcome::Unlinked => Ok(StatusCode::NO_CONTENT),
+        UnlinkOutcome::NotFound => Err((StatusCode::NOT_FOUND, "Account not found".to_string())),
+        UnlinkOutcome::LastAccount => Err((
+            StatusCode::CONFLICT,
+            "The last account can't be unlinked, delete the user instead".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,117 @@
+use crate::routes::{auth::CurrentUser, AppState};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use lib::db::{
+    self,
+    catalog::{
+        AlbumListing, AlbumPage, ArtistListing, ArtistPage, CatalogQuery, TrackListing, TrackPage,
+    },
+    DBError,
+};
+use serde::Deserialize;
+use tracing::error;
+
+/// Query parameters for listing and searching the catalog
+#[derive(Deserialize, Debug)]
+pub struct ListQuery {
+    q: Option<String>,
+    limit: Option<u64>,
+    offset: Option<u64>,
+}
+
+impl From<ListQuery> for CatalogQuery {
+    fn from(query: ListQuery) -> Self {
+        CatalogQuery {
+            search: query.q.filter(|q| !q.is_empty()),
+            limit: query.limit.unwrap_or(50),
+            offset: query.offset.unwrap_or_default(),
+        }
+    }
+}
+
+fn internal_error(db_err: DBError) -> (StatusCode, String) {
+    error!("Error reading catalog: {:?}", db_err);
+    (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+}
+
+fn not_found(kind: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("{} not found", kind))
+}
+
+/// Lists or searches artists
+pub async fn list_artists(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<ArtistListing>>, (StatusCode, String)> {
+    db::catalog::list_artists(&state.connection, &user.id, &query.into())
+        .await
+        .map(Json)
+        .map_err(internal_error)
+}
+
+/// Gets an artist with their albums
+pub async fn get_artist(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<Json<ArtistPage>, (StatusCode, String)> {
+    db::catalog::get_artist(&state.connection, &user.id, id)
+        .await
+        .map_err(internal_error)?
+        .map(Json)
+        .ok_or(not_found("Artist"))
+}
+
+/// Lists or searches albums
+pub async fn list_albums(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<AlbumListing>>, (StatusCode, String)> {
+    db::catalog::list_albums(&state.connection, &user.id, &query.into())
+        .await
+        .map(Json)
+        .map_err(internal_error)
+}
+
+/// Gets an album with its artists and tracklist
+pub async fn get_album(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<Json<AlbumPage>, (StatusCode, String)> {
+    db::catalog::get_album(&state.connection, &user.id, id)
+        .await
+        .map_err(internal_error)?
+        .map(Json)
+        .ok_or(not_found("Album"))
+}
+
+/// Lists or searches tracks
+pub async fn list_tracks(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<TrackListing>>, (StatusCode, String)> {
+    db::catalog::list_tracks(&state.connection, &user.id, &query.into())
+        .await
+        .map(Json)
+        .map_err(internal_error)
+}
+
+/// Gets a track with its albums and artists
+pub async fn get_track(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<Json<TrackPage>, (StatusCode, String)> {
+    db::catalog::get_track(&state.connection, &user.id, id)
+        .await
+        .map_err(internal_error)?
+        .map(Json)
+        .ok_or(not_found("Track"))
+}
@@ -0,0 +1,90 @@
+use crate::routes::{api::stats::WindowQuery, auth::CurrentUser, AppState};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use lib::{
+    analytics::{self, compare::Comparison, TimeWindow},
+    db,
+};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+/// The most items of each user's top lists that can be compared
+const MAX_COMPARE_LIMIT: u64 = 200;
+
+#[derive(Deserialize, Debug)]
+pub struct CompareQuery {
+    #[serde(flatten)]
+    window: WindowQuery,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OtherUser {
+    id: String,
+    name: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CompareResponse {
+    window: TimeWindow,
+    other_user: OtherUser,
+    #[serde(flatten)]
+    comparison: Comparison,
+}
+
+/// Compares the listening of the logged in user with another user of the instance,
+/// who has to have allowed comparisons in their settings
+pub async fn compare(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(other_user_id): Path<String>,
+    Query(query): Query<CompareQuery>,
+) -> Result<Json<CompareResponse>, (StatusCode, String)> {
+    let window = query.window.window()?;
+    let limit = query.limit.unwrap_or(50).clamp(1, MAX_COMPARE_LIMIT);
+    let other_user = db::user::get_user(&state.connection, &other_user_id)
+        .await
+        .map_err(|db_err| {
+            error!("Error looking up user to compare: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?
+        .ok_or((StatusCode::NOT_FOUND, "User not found".to_string()))?;
+    // Comparing shows the other user's top lists, so they have to have allowed it
+    if other_user.id != user.id {
+        let other_settings = db::settings::get_user_settings(&state.connection, &other_user.id)
+            .await
+            .map_err(|db_err| {
+                error!("Error looking up settings of user to compare: {:?}", db_err);
+                (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+            })?;
+        if !other_settings.allow_comparisons {
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!("{} doesn't allow comparisons", other_user.name),
+            ));
+        }
+    }
+    let comparison = analytics::compare::compare_users(
+        &state.connection,
+        &user.id,
+        &other_user.id,
+        window,
+        limit,
+    )
+    .await
+    .map_err(|db_err| {
+        error!("Error comparing users: {:?}", db_err);
+        (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+    })?;
+    Ok(Json(CompareResponse {
+        window,
+        other_user: OtherUser {
+            id: other_user.id,
+            name: other_user.name,
+        },
+        comparison,
+    }))
+}
@@ -0,0 +1,39 @@
+use crate::routes::{auth::CurrentUser, AppState};
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::header,
+    response::IntoResponse,
+};
+use lib::export::{self, ExportFormat};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// Downloads the full listening history of the logged in user
+/// The plays are streamed as they're read, so the response is sent before the export is finished
+pub async fn export(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<ExportQuery>,
+) -> impl IntoResponse {
+    let format = query.format;
+    let plays = export::export_plays(state.connection, user.id, format);
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"unwrapped-plays.{}\"",
+                    format.extension()
+                ),
+            ),
+        ],
+        Body::from_stream(plays),
+    )
+}
@@ -0,0 +1,123 @@
+use crate::routes::{auth::CurrentUser, AppState};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use entity::api_key;
+use lib::db::{self, api_key::ApiScope};
+use sea_orm::prelude::DateTime;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+/// The longest name a key can be given
+const MAX_KEY_NAME_LENGTH: usize = 100;
+
+/// An API key as it's listed, without the key itself
+#[derive(Serialize, Debug)]
+pub struct ApiKeyListing {
+    pub id: i32,
+    pub name: String,
+    /// The start of the key, to tell keys apart
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime,
+    pub last_used_at: Option<DateTime>,
+}
+
+impl From<api_key::Model> for ApiKeyListing {
+    fn from(model: api_key::Model) -> Self {
+        ApiKeyListing {
+            id: model.id,
+            name: model.name,
+            prefix: model.prefix,
+            scopes: model.scopes,
+            created_at: model.created_at,
+            last_used_at: model.last_used_at,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateApiKey {
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+}
+
+/// A key that was just created, the only time the key itself is returned
+#[derive(Serialize, Debug)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub listing: ApiKeyListing,
+    pub key: String,
+}
+
+/// Lists the API keys of the logged in user
+pub async fn list(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+) -> Result<Json<Vec<ApiKeyListing>>, (StatusCode, String)> {
+    db::api_key::get_user_api_keys(&state.connection, &user.id)
+        .await
+        .map(|keys| Json(keys.into_iter().map(ApiKeyListing::from).collect()))
+        .map_err(|db_err| {
+            error!("Error listing API keys: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })
+}
+
+/// Creates an API key for the logged in user with the scopes it's allowed to use
+pub async fn create(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Json(request): Json<CreateApiKey>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), (StatusCode, String)> {
+    let name = request.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_KEY_NAME_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "The name has to be between 1 and {} characters",
+                MAX_KEY_NAME_LENGTH
+            ),
+        ));
+    }
+    if request.scopes.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "A key needs at least one scope".to_string(),
+        ));
+    }
+    let (model, key) =
+        db::api_key::create_api_key(&state.connection, user.id, name, &request.scopes)
+            .await
+            .map_err(|db_err| {
+                error!("Error creating API key: {:?}", db_err);
+                (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+            })?;
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedApiKey {
+            listing: model.into(),
+            key,
+        }),
+    ))
+}
+
+/// Revokes an API key of the logged in user, so it can't be used anymore
+pub async fn revoke(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let deleted = db::api_key::delete_api_key(&state.connection, &user.id, id)
+        .await
+        .map_err(|db_err| {
+            error!("Error revoking API key: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    if !deleted {
+        return Err((StatusCode::NOT_FOUND, "API key not found".to_string()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
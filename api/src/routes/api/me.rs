@@ -0,0 +1,54 @@
+use crate::routes::{
+    auth::{CurrentUser, SESSION_COOKIE},
+    AppState,
+};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar};
+use lib::db::{self, user::DeletedUser};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+/// Where the user can remove Unwrapped's access on Spotify's side, since Spotify has no API to
+/// revoke tokens
+const SPOTIFY_APPS_URL: &str = "https://www.spotify.com/account/apps/";
+
+#[derive(Deserialize, Debug)]
+pub struct DeleteQuery {
+    /// Also remove the tracks, albums, artists and genres no other user has played
+    #[serde(default)]
+    prune_catalog: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeleteResponse {
+    #[serde(flatten)]
+    deleted: DeletedUser,
+    revoke_access_url: &'static str,
+}
+
+/// Deletes the logged in user and all of their data, then logs them out
+pub async fn delete(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<DeleteQuery>,
+    jar: CookieJar,
+) -> Result<(CookieJar, Json<DeleteResponse>), (StatusCode, String)> {
+    let deleted = db::user::delete_user(&state.connection, &user.id, query.prune_catalog)
+        .await
+        .map_err(|db_err| {
+            error!("Error deleting user: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    info!("Deleted user {}: {:?}", user.id, deleted);
+    Ok((
+        jar.remove(Cookie::build(SESSION_COOKIE).path("/")),
+        Json(DeleteResponse {
+            deleted,
+            revoke_access_url: SPOTIFY_APPS_URL,
+        }),
+    ))
+}
@@ -0,0 +1,84 @@
+mod accounts;
+mod catalog;
+mod compare;
+mod export;
+mod keys;
+mod me;
+mod playlists;
+mod plays;
+mod recommendations;
+pub(super) mod reports;
+mod settings;
+mod stats;
+
+use crate::routes::AppState;
+use axum::{
+    routing::{delete, get, post, put, MethodRouter},
+    Extension, Router,
+};
+use lib::db::api_key::ApiScope;
+
+/// Allow API keys with the scope to use a route, routes without a scope are only for logged in users
+pub(crate) fn scoped(scope: ApiScope, route: MethodRouter<AppState>) -> MethodRouter<AppState> {
+    route.route_layer(Extension(scope))
+}
+
+/// The routes for reading back the data of the logged in user, nested under `/api`
+pub fn get_api_router() -> Router<AppState> {
+    use ApiScope::*;
+    Router::new()
+        .route("/me", delete(me::delete))
+        .route("/keys", get(keys::list).post(keys::create))
+        .route("/keys/:id", delete(keys::revoke))
+        .route("/accounts", get(accounts::list))
+        .route("/accounts/listenbrainz", post(accounts::link_listenbrainz))
+        .route("/accounts/:id", delete(accounts::unlink))
+        .route(
+            "/settings",
+            scoped(ReadSettings, get(settings::get))
+                .merge(scoped(WriteSettings, put(settings::update))),
+        )
+        .route("/plays", scoped(ReadPlays, get(plays::list)))
+        .route("/export", scoped(ReadPlays, get(export::export)))
+        .route("/artists", scoped(ReadPlays, get(catalog::list_artists)))
+        .route("/artists/:id", scoped(ReadPlays, get(catalog::get_artist)))
+        .route("/albums", scoped(ReadPlays, get(catalog::list_albums)))
+        .route("/albums/:id", scoped(ReadPlays, get(catalog::get_album)))
+        .route("/tracks", scoped(ReadPlays, get(catalog::list_tracks)))
+        .route("/tracks/:id", scoped(ReadPlays, get(catalog::get_track)))
+        .route("/stats/top", scoped(ReadPlays, get(stats::top)))
+        .route("/stats/sessions", scoped(ReadPlays, get(stats::sessions)))
+        .route("/stats/clock", scoped(ReadPlays, get(stats::clock)))
+        .route("/stats/discovery", scoped(ReadPlays, get(stats::discovery)))
+        .route("/stats/streaks", scoped(ReadPlays, get(stats::streaks)))
+        .route(
+            "/stats/obsessions",
+            scoped(ReadPlays, get(stats::obsessions)),
+        )
+        .route("/stats/forgotten", scoped(ReadPlays, get(stats::forgotten)))
+        .route("/stats/diversity", scoped(ReadPlays, get(stats::diversity)))
+        .route("/stats/genres", scoped(ReadPlays, get(stats::genres)))
+        .route("/stats/skips", scoped(ReadPlays, get(stats::skips)))
+        .route(
+            "/recommendations",
+            scoped(ReadPlays, get(recommendations::list)),
+        )
+        .route(
+            "/compare/:user_id",
+            scoped(ReadPlays, get(compare::compare)),
+        )
+        .route(
+            "/playlists",
+            scoped(ReadPlays, get(playlists::list))
+                .merge(scoped(WritePlaylists, post(playlists::generate))),
+        )
+        .route(
+            "/stats/calendar/:year",
+            scoped(ReadPlays, get(stats::calendar)),
+        )
+        .route(
+            "/reports/:year",
+            scoped(ReadPlays, get(reports::get))
+                .merge(scoped(WriteReports, post(reports::regenerate))),
+        )
+}
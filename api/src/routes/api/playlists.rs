@@ -0,0 +1,336 @@
+use crate::routes::{
+    api::stats::{WindowQuery, MAX_FORGOTTEN_MONTHS},
+    auth::CurrentUser,
+    AppState,
+};
+use axum::{extract::State, http::StatusCode, Json};
+use entity::{account, playlist};
+use lib::{
+    analytics::{self, top::TopMetric, Period, TimeWindow},
+    db::{self, playlist::SavePlaylistOptions, user::Provider},
+    music::spotify::{self, SpotifyClient, SpotifyCredentials, SpotifyError},
+};
+use sea_orm::{prelude::DateTime, DatabaseConnection};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+/// The most tracks a generated playlist can have
+const MAX_PLAYLIST_TRACKS: u64 = 100;
+
+/// The analytics query a playlist is generated from
+#[derive(Deserialize, Debug)]
+#[serde(tag = "source", rename_all = "lowercase")]
+pub enum PlaylistSource {
+    /// The most played tracks in a window
+    Top {
+        #[serde(flatten)]
+        window: WindowQuery,
+        limit: Option<u64>,
+    },
+    /// The tracks that used to be played a lot but haven't been played in a while
+    Forgotten {
+        months: Option<u32>,
+        min_plays: Option<u64>,
+        limit: Option<u64>,
+    },
+}
+
+/// A request to generate a playlist
+#[derive(Deserialize, Debug)]
+pub struct GenerateRequest {
+    #[serde(flatten)]
+    source: PlaylistSource,
+    /// The linked Spotify account the playlist is created on, the first one linked if not given
+    account_id: Option<i32>,
+}
+
+/// The tracks of a playlist along with how it is named
+/// The kind identifies the playlist, so generating the same kind again updates the same playlist
+struct PlaylistTracks {
+    kind: String,
+    name: String,
+    description: String,
+    track_ids: Vec<i32>,
+}
+
+impl PlaylistSource {
+    /// Run the analytics query for the tracks of the playlist
+    async fn tracks(
+        &self,
+        conn: &DatabaseConnection,
+        user_id: &str,
+    ) -> Result<PlaylistTracks, (StatusCode, String)> {
+        match self {
+            PlaylistSource::Top { window, limit } => {
+                let period = window.period();
+                let window = window.window()?;
+                let limit = limit.unwrap_or(50).clamp(1, MAX_PLAYLIST_TRACKS);
+                let tracks =
+                    analytics::top::top_tracks(conn, user_id, window, TopMetric::Plays, limit)
+                        .await
+                        .map_err(|db_err| {
+                            error!("Error getting top tracks for playlist: {:?}", db_err);
+                            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+                        })?;
+                let (kind, title) = top_title(period, window);
+                Ok(PlaylistTracks {
+                    kind,
+                    name: format!("Unwrapped: {}", title),
+                    description: format!("Your {} most played tracks, by Unwrapped", limit),
+                    track_ids: tracks.into_iter().map(|top| top.item.track.id).collect(),
+                })
+            }
+            PlaylistSource::Forgotten {
+                months,
+                min_plays,
+                limit,
+            } => {
+                let settings = db::settings::get_user_settings(conn, user_id)
+                    .await
+                    .map_err(|db_err| {
+                        error!("Error getting user settings: {:?}", db_err);
+                        (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+                    })?;
+                let months = months.unwrap_or(6).clamp(1, MAX_FORGOTTEN_MONTHS);
+                let min_plays = min_plays.unwrap_or(10).max(1);
+                let limit = limit.unwrap_or(50).clamp(1, MAX_PLAYLIST_TRACKS);
+                let tracks = analytics::forgotten::forgotten_tracks(
+                    conn,
+                    user_id,
+                    analytics::forgotten::forgotten_since(months),
+                    min_plays,
+                    settings.timezone,
+                    limit,
+                )
+                .await
+                .map_err(|db_err| {
+                    error!("Error getting forgotten tracks for playlist: {:?}", db_err);
+                    (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+                })?;
+                Ok(PlaylistTracks {
+                    kind: "forgotten".to_string(),
+                    name: "Unwrapped: Forgotten favorites".to_string(),
+                    description: format!(
+                        "Tracks you played at least {} times but not in the past {} months, by Unwrapped",
+                        min_plays, months
+                    ),
+                    track_ids: tracks
+                        .into_iter()
+                        .map(|forgotten| forgotten.item.track.id)
+                        .collect(),
+                })
+            }
+        }
+    }
+}
+
+/// The kind and title of a top tracks playlist for a period
+/// Custom windows are part of the kind, so each range gets its own playlist
+fn top_title(period: Period, window: TimeWindow) -> (String, String) {
+    match period {
+        Period::Week => ("top-week".into(), "Top tracks of the past week".into()),
+        Period::Month => ("top-month".into(), "Top tracks of the past month".into()),
+        Period::Year => ("top-year".into(), "Top tracks of the past year".into()),
+        Period::All => ("top-all".into(), "Top tracks of all time".into()),
+        Period::Custom => {
+            let kind = format!(
+                "top-custom-{}-{}",
+                window.from.format("%Y%m%dT%H%M%S"),
+                window.to.format("%Y%m%dT%H%M%S")
+            );
+            let title = format!(
+                "Top tracks from {} to {}",
+                window.from.format("%Y-%m-%d"),
+                window.to.format("%Y-%m-%d")
+            );
+            (kind, title)
+        }
+    }
+}
+
+/// A playlist generated for the logged in user
+#[derive(Serialize, Debug)]
+pub struct GeneratedPlaylist {
+    id: i32,
+    kind: String,
+    name: String,
+    /// The ID of the playlist on Spotify
+    provider_id: String,
+    url: String,
+    track_count: i32,
+    created_at: DateTime,
+    updated_at: DateTime,
+}
+
+impl From<playlist::Model> for GeneratedPlaylist {
+    fn from(playlist: playlist::Model) -> Self {
+        GeneratedPlaylist {
+            id: playlist.id,
+            url: format!("https://open.spotify.com/playlist/{}", playlist.provider_id),
+            kind: playlist.kind,
+            name: playlist.name,
+            provider_id: playlist.provider_id,
+            track_count: playlist.track_count,
+            created_at: playlist.created_at,
+            updated_at: playlist.updated_at,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct GenerateResponse {
+    #[serde(flatten)]
+    playlist: GeneratedPlaylist,
+    /// The number of tracks left out because they have no Spotify URI, like local files and tracks only scrobbled elsewhere
+    skipped_tracks: usize,
+}
+
+/// Turn an error from Spotify into a response
+fn spotify_error(spotify_err: SpotifyError) -> (StatusCode, String) {
+    error!("Error generating playlist on Spotify: {:?}", spotify_err);
+    match spotify_err.status {
+        // Accounts connected before playlists were supported don't have the scopes to write them
+        403 => (
+            StatusCode::FORBIDDEN,
+            "Log in with Spotify again to allow Unwrapped to create playlists".to_string(),
+        ),
+        _ => (StatusCode::BAD_GATEWAY, spotify_err.message),
+    }
+}
+
+/// Create a client for an account, refreshing and saving the access token if it is invalid
+async fn spotify_client(
+    conn: &DatabaseConnection,
+    credentials: SpotifyCredentials,
+    account: &account::Model,
+) -> Result<SpotifyClient, SpotifyError> {
+    let client = SpotifyClient::new(account.access_token.clone())
+        .set_refresh_token(Some(account.refresh_token.clone()))
+        .set_credentials(credentials);
+    match client.get_current_user().await {
+        Ok(_) => Ok(client),
+        Err(spotify_err) if spotify_err.status == 401 => {
+            debug!("Invalid Token error, Attempting to get a new access token");
+            let new_token = client.refresh_access_token().await?;
+            db::user::update_account_tokens(
+                conn,
+                account.clone(),
+                new_token.access_token.clone(),
+                None,
+            )
+            .await
+            .map_err(|db_err| SpotifyError {
+                status: 500,
+                message: db_err.to_string(),
+            })?;
+            Ok(client.set_access_token(new_token.access_token))
+        }
+        Err(spotify_err) => Err(spotify_err),
+    }
+}
+
+/// Lists the playlists generated for the logged in user
+pub async fn list(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+) -> Result<Json<Vec<GeneratedPlaylist>>, (StatusCode, String)> {
+    let playlists = db::playlist::get_user_playlists(&state.connection, &user.id)
+        .await
+        .map_err(|db_err| {
+            error!("Error listing playlists: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    Ok(Json(playlists.into_iter().map(Into::into).collect()))
+}
+
+/// Generates a Spotify playlist for the logged in user from an analytics query
+/// If a playlist of the same kind was generated before, it is updated instead of creating another
+pub async fn generate(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Json(request): Json<GenerateRequest>,
+) -> Result<Json<GenerateResponse>, (StatusCode, String)> {
+    let conn = &state.connection;
+    let account = match request.account_id {
+        Some(account_id) => db::user::get_user_account_by_id(conn, &user.id, account_id).await,
+        None => db::user::get_user_account(conn, &user.id, Provider::Spotify.as_str()).await,
+    }
+    .map_err(|db_err| {
+        error!("Error looking up account: {:?}", db_err);
+        (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+    })?
+    .filter(|account| account.provider == Provider::Spotify.as_str())
+    .ok_or((
+        StatusCode::BAD_REQUEST,
+        "No Spotify account connected".to_string(),
+    ))?;
+    let tracks = request.source.tracks(conn, &user.id).await?;
+    let uris = db::playlist::get_track_uris(conn, tracks.track_ids.clone())
+        .await
+        .map_err(|db_err| {
+            error!("Error looking up track URIs: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    let (track_uris, skipped_tracks) = spotify::playlist_uris(
+        tracks
+            .track_ids
+            .iter()
+            .map(|id| uris.get(id).cloned())
+            .collect(),
+    );
+    let client = spotify_client(conn, state.config.providers.spotify.credentials(), &account)
+        .await
+        .map_err(spotify_error)?;
+    let existing = db::playlist::get_playlist(conn, account.id, &tracks.kind)
+        .await
+        .map_err(|db_err| {
+            error!("Error looking up playlist: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    // Update the playlist that was generated before, unless it was deleted on Spotify
+    let updated = match existing {
+        Some(existing) => match client
+            .update_playlist_details(&existing.provider_id, &tracks.name, &tracks.description)
+            .await
+        {
+            Ok(()) => Some(existing.provider_id),
+            Err(spotify_err) if spotify_err.status == 404 => None,
+            Err(spotify_err) => return Err(spotify_error(spotify_err)),
+        },
+        None => None,
+    };
+    let provider_id = match updated {
+        Some(provider_id) => provider_id,
+        None => {
+            client
+                .create_playlist(&account.provider_id, &tracks.name, &tracks.description)
+                .await
+                .map_err(spotify_error)?
+                .id
+        }
+    };
+    client
+        .replace_playlist_items(&provider_id, &track_uris)
+        .await
+        .map_err(spotify_error)?;
+    let playlist = db::playlist::save_playlist(
+        conn,
+        SavePlaylistOptions {
+            user_id: user.id,
+            account_id: account.id,
+            kind: tracks.kind,
+            provider_id,
+            name: tracks.name,
+            track_count: track_uris.len() as i32,
+        },
+    )
+    .await
+    .map_err(|db_err| {
+        error!("Error saving playlist: {:?}", db_err);
+        (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+    })?;
+    Ok(Json(GenerateResponse {
+        playlist: playlist.into(),
+        skipped_tracks,
+    }))
+}
@@ -0,0 +1,56 @@
+use crate::routes::{auth::CurrentUser, AppState};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use lib::db::{
+    self,
+    play_log::{PlayFilter, PlayPage},
+};
+use sea_orm::prelude::DateTime;
+use serde::Deserialize;
+use tracing::error;
+
+/// Query parameters for listing plays
+#[derive(Deserialize, Debug)]
+pub struct PlaysQuery {
+    /// The `next_cursor` of the previous page
+    cursor: Option<DateTime>,
+    from: Option<DateTime>,
+    to: Option<DateTime>,
+    artist_id: Option<i32>,
+    album_id: Option<i32>,
+    track_id: Option<i32>,
+    account_id: Option<i32>,
+    limit: Option<u64>,
+}
+
+/// Lists the plays of the logged in user, newest first
+pub async fn list(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<PlaysQuery>,
+) -> Result<Json<PlayPage>, (StatusCode, String)> {
+    let filter = PlayFilter {
+        before: query.cursor,
+        from: query.from,
+        to: query.to,
+        artist_id: query.artist_id,
+        album_id: query.album_id,
+        track_id: query.track_id,
+        account_id: query.account_id,
+    };
+    db::play_log::get_plays(
+        &state.connection,
+        &user.id,
+        &filter,
+        query.limit.unwrap_or(50),
+    )
+    .await
+    .map(Json)
+    .map_err(|db_err| {
+        error!("Error listing plays: {:?}", db_err);
+        (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+    })
+}
@@ -0,0 +1,59 @@
+use crate::routes::{api::stats::WindowQuery, auth::CurrentUser, AppState};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use lib::{
+    analytics::TimeWindow,
+    recommend::{self, Recommendations, Scope},
+};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+/// The most tracks and artists that can be recommended at once
+const MAX_RECOMMENDATIONS_LIMIT: u64 = 100;
+
+#[derive(Deserialize, Debug)]
+pub struct RecommendationsQuery {
+    /// The window of the favorites the recommendations are based on
+    #[serde(flatten)]
+    window: WindowQuery,
+    #[serde(default)]
+    scope: Scope,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RecommendationsResponse {
+    window: TimeWindow,
+    scope: Scope,
+    #[serde(flatten)]
+    recommendations: Recommendations,
+}
+
+/// Recommends tracks and artists to the logged in user, from what they and the rest of the instance
+/// have listened to
+pub async fn list(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<RecommendationsQuery>,
+) -> Result<Json<RecommendationsResponse>, (StatusCode, String)> {
+    let window = query.window.window()?;
+    let limit = query
+        .limit
+        .unwrap_or(20)
+        .clamp(1, MAX_RECOMMENDATIONS_LIMIT);
+    let recommendations =
+        recommend::recommend(&state.connection, &user.id, window, query.scope, limit)
+            .await
+            .map_err(|db_err| {
+                error!("Error getting recommendations: {:?}", db_err);
+                (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+            })?;
+    Ok(Json(RecommendationsResponse {
+        window,
+        scope: query.scope,
+        recommendations,
+    }))
+}
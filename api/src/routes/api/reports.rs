@@ -0,0 +1,65 @@
+use crate::routes::{auth::CurrentUser, AppState};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use chrono_tz::Tz;
+use entity::report;
+use lib::{analytics, db};
+use sea_orm::DatabaseConnection;
+use tracing::error;
+
+/// Get the saved report of a user for a year, generating and saving it if there isn't one yet
+pub(crate) async fn get_or_create_report(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    year: i32,
+) -> Result<report::Model, (StatusCode, String)> {
+    if analytics::TimeWindow::for_year(year, Tz::UTC).is_none() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid year".to_string()));
+    }
+    let existing = db::report::get_report(conn, user_id, year)
+        .await
+        .map_err(|db_err| {
+            error!("Error looking up report: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    match existing {
+        Some(report) => Ok(report),
+        None => analytics::report::create_snapshot(conn, user_id, year)
+            .await
+            .map_err(|db_err| {
+                error!("Error creating report: {:?}", db_err);
+                (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+            }),
+    }
+}
+
+/// Gets the year in review of the logged in user
+pub async fn get(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(year): Path<i32>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let report = get_or_create_report(&state.connection, &user.id, year).await?;
+    Ok(Json(report.data))
+}
+
+/// Regenerates the year in review of the logged in user from their latest plays
+pub async fn regenerate(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(year): Path<i32>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    if analytics::TimeWindow::for_year(year, Tz::UTC).is_none() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid year".to_string()));
+    }
+    let report = analytics::report::create_snapshot(&state.connection, &user.id, year)
+        .await
+        .map_err(|db_err| {
+            error!("Error creating report: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    Ok(Json(report.data))
+}
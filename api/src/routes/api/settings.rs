@@ -0,0 +1,44 @@
+use crate::routes::{auth::CurrentUser, AppState};
+use axum::{extract::State, http::StatusCode, Json};
+use lib::db::{
+    self,
+    settings::{SettingsUpdate, UserSettings},
+};
+use tracing::error;
+
+/// Gets the settings of the logged in user
+pub async fn get(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+) -> Result<Json<UserSettings>, (StatusCode, String)> {
+    db::settings::get_user_settings(&state.connection, &user.id)
+        .await
+        .map(Json)
+        .map_err(|db_err| {
+            error!("Error getting user settings: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })
+}
+
+/// Updates the settings of the logged in user, leaving out settings keeps them as they are
+pub async fn update(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Json(update): Json<SettingsUpdate>,
+) -> Result<Json<UserSettings>, (StatusCode, String)> {
+    let settings = db::settings::get_user_settings(&state.connection, &user.id)
+        .await
+        .map_err(|db_err| {
+            error!("Error getting user settings: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?
+        .apply(update)
+        .map_err(|message| (StatusCode::BAD_REQUEST, message))?;
+    db::settings::save_user_settings(&state.connection, &user.id, &settings)
+        .await
+        .map(Json)
+        .map_err(|db_err| {
+            error!("Error saving user settings: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })
+}
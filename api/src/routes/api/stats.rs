@@ -0,0 +1,606 @@
+use crate::routes::{auth::CurrentUser, AppState};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use lib::{
+    analytics::{
+        self,
+        calendar::{ClockCount, DayCount},
+        discovery::{Discovery, DiscoveryRatio, MonthDiscoveries},
+        diversity::{MonthDiversity, MonthGenres},
+        forgotten::{ForgottenAlbum, ForgottenTrack},
+        listening::{SessionStats, DEFAULT_SESSION_GAP_MINUTES},
+        skips::{SkipStats, SkippedTrack},
+        streaks::{ArtistStreak, Obsession, Streak, TrackStreak},
+        top::{TopAlbum, TopArtist, TopGenre, TopMetric, TopTrack},
+        Period, TimeWindow,
+    },
+    db::{
+        self,
+        catalog::{ArtistSummary, TrackDetails},
+        settings::UserSettings,
+    },
+};
+use sea_orm::{prelude::DateTime, DatabaseConnection};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+/// The most items that can be requested for each top list
+const MAX_TOP_LIMIT: u64 = 100;
+/// The longest gap between plays, in minutes, that can be used to split sessions
+const MAX_SESSION_GAP_MINUTES: i64 = 24 * 60;
+/// The most days an obsession can be spread over
+const MAX_OBSESSION_DAYS: u32 = 365;
+/// The most months something can have gone unplayed for to be forgotten
+pub(super) const MAX_FORGOTTEN_MONTHS: u32 = 120;
+
+/// Query parameters selecting the window of time to analyze
+#[derive(Deserialize, Debug)]
+pub struct WindowQuery {
+    #[serde(default)]
+    period: Period,
+    from: Option<DateTime>,
+    to: Option<DateTime>,
+}
+
+impl WindowQuery {
+    pub fn period(&self) -> Period {
+        self.period
+    }
+
+    pub fn window(&self) -> Result<TimeWindow, (StatusCode, String)> {
+        TimeWindow::from_period(self.period, self.from, self.to)
+            .map_err(|message| (StatusCode::BAD_REQUEST, message))
+    }
+}
+
+/// Query parameters selecting the timezone to bucket plays in
+#[derive(Deserialize, Debug)]
+pub struct TimezoneQuery {
+    /// An IANA timezone name like `Europe/Amsterdam`, defaults to the user's timezone
+    tz: Option<String>,
+}
+
+impl TimezoneQuery {
+    /// Get the settings of a user, with the timezone of the query taking over their saved timezone
+    async fn settings(
+        &self,
+        conn: &DatabaseConnection,
+        user_id: &str,
+    ) -> Result<UserSettings, (StatusCode, String)> {
+        let mut settings = db::settings::get_user_settings(conn, user_id)
+            .await
+            .map_err(|db_err| {
+                error!("Error getting user settings: {:?}", db_err);
+                (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+            })?;
+        if let Some(tz) = &self.tz {
+            settings.timezone = tz
+                .parse::<Tz>()
+                .map_err(|_| (StatusCode::BAD_REQUEST, format!("Unknown timezone {}", tz)))?;
+        }
+        Ok(settings)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TopQuery {
+    #[serde(flatten)]
+    window: WindowQuery,
+    #[serde(default)]
+    by: TopMetric,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TopResponse {
+    window: TimeWindow,
+    by: TopMetric,
+    artists: Vec<TopArtist>,
+    albums: Vec<TopAlbum>,
+    tracks: Vec<TopTrack>,
+}
+
+/// Gets the top artists, albums and tracks of the logged in user
+pub async fn top(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<TopQuery>,
+) -> Result<Json<TopResponse>, (StatusCode, String)> {
+    let window = query.window.window()?;
+    let limit = query.limit.unwrap_or(10).clamp(1, MAX_TOP_LIMIT);
+    let conn = &state.connection;
+    let (artists, albums, tracks) = tokio::try_join!(
+        analytics::top::top_artists(conn, &user.id, window, query.by, limit),
+        analytics::top::top_albums(conn, &user.id, window, query.by, limit),
+        analytics::top::top_tracks(conn, &user.id, window, query.by, limit),
+    )
+    .map_err(|db_err| {
+        error!("Error getting top items: {:?}", db_err);
+        (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+    })?;
+    Ok(Json(TopResponse {
+        window,
+        by: query.by,
+        artists,
+        albums,
+        tracks,
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SessionsQuery {
+    #[serde(flatten)]
+    window: WindowQuery,
+    #[serde(flatten)]
+    timezone: TimezoneQuery,
+    /// The minutes between plays that start a new session
+    gap: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionsResponse {
+    window: TimeWindow,
+    timezone: Tz,
+    gap_minutes: i64,
+    #[serde(flatten)]
+    stats: SessionStats,
+}
+
+/// Gets how the listening of the logged in user splits into sessions
+pub async fn sessions(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<SessionsQuery>,
+) -> Result<Json<SessionsResponse>, (StatusCode, String)> {
+    let window = query.window.window()?;
+    let settings = query.timezone.settings(&state.connection, &user.id).await?;
+    let gap_minutes = query
+        .gap
+        .unwrap_or(DEFAULT_SESSION_GAP_MINUTES)
+        .clamp(1, MAX_SESSION_GAP_MINUTES);
+    let sessions = analytics::listening::listening_sessions(
+        &state.connection,
+        &user.id,
+        window,
+        Duration::minutes(gap_minutes),
+    )
+    .await
+    .map_err(|db_err| {
+        error!("Error getting listening sessions: {:?}", db_err);
+        (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+    })?;
+    Ok(Json(SessionsResponse {
+        window,
+        timezone: settings.timezone,
+        gap_minutes,
+        stats: analytics::listening::session_stats(&sessions, settings.timezone),
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ClockQuery {
+    #[serde(flatten)]
+    window: WindowQuery,
+    #[serde(flatten)]
+    timezone: TimezoneQuery,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ClockResponse {
+    window: TimeWindow,
+    #[serde(flatten)]
+    settings: UserSettings,
+    buckets: Vec<ClockCount>,
+}
+
+/// Gets the plays of the logged in user by hour of the day and day of the week
+pub async fn clock(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<ClockQuery>,
+) -> Result<Json<ClockResponse>, (StatusCode, String)> {
+    let window = query.window.window()?;
+    let settings = query.timezone.settings(&state.connection, &user.id).await?;
+    let buckets = analytics::calendar::clock_plays(&state.connection, &user.id, window, &settings)
+        .await
+        .map_err(|db_err| {
+            error!("Error getting listening clock: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    Ok(Json(ClockResponse {
+        window,
+        settings,
+        buckets,
+    }))
+}
+
+#[derive(Serialize, Debug)]
+pub struct CalendarResponse {
+    year: i32,
+    #[serde(flatten)]
+    settings: UserSettings,
+    total_plays: i64,
+    /// Every day of the year, including the days without plays
+    days: Vec<DayCount>,
+}
+
+/// Gets the plays of the logged in user on each day of a year
+pub async fn calendar(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(year): Path<i32>,
+    Query(query): Query<TimezoneQuery>,
+) -> Result<Json<CalendarResponse>, (StatusCode, String)> {
+    let settings = query.settings(&state.connection, &user.id).await?;
+    let tz = settings.timezone;
+    let window = TimeWindow::for_year(year, tz)
+        .ok_or((StatusCode::BAD_REQUEST, "Invalid year".to_string()))?;
+    let counts = analytics::calendar::daily_plays(&state.connection, &user.id, window, tz)
+        .await
+        .map_err(|db_err| {
+            error!("Error getting calendar: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    let (Some(first), Some(last)) = (
+        NaiveDate::from_ymd_opt(year, 1, 1),
+        NaiveDate::from_ymd_opt(year, 12, 31),
+    ) else {
+        return Err((StatusCode::BAD_REQUEST, "Invalid year".to_string()));
+    };
+    let days = analytics::calendar::fill_days(&counts, first, last);
+    Ok(Json(CalendarResponse {
+        year,
+        settings,
+        total_plays: counts.iter().map(|count| count.play_count).sum(),
+        days,
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DiscoveryQuery {
+    #[serde(flatten)]
+    window: WindowQuery,
+    #[serde(flatten)]
+    timezone: TimezoneQuery,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FavoritesTimeline {
+    artists: Vec<Discovery<ArtistSummary>>,
+    tracks: Vec<Discovery<TrackDetails>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DiscoveryResponse {
+    window: TimeWindow,
+    timezone: Tz,
+    new_artist_count: u64,
+    new_track_count: u64,
+    /// The most played of the artists discovered in the window
+    new_artists: Vec<Discovery<ArtistSummary>>,
+    #[serde(flatten)]
+    ratio: DiscoveryRatio,
+    per_month: Vec<MonthDiscoveries>,
+    /// When the most played artists and tracks of the window were first heard
+    favorites: FavoritesTimeline,
+}
+
+/// Gets what the logged in user discovered and when they first heard their favorites
+pub async fn discovery(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<DiscoveryQuery>,
+) -> Result<Json<DiscoveryResponse>, (StatusCode, String)> {
+    let window = query.window.window()?;
+    let settings = query.timezone.settings(&state.connection, &user.id).await?;
+    let limit = query.limit.unwrap_or(10).clamp(1, MAX_TOP_LIMIT);
+    let conn = &state.connection;
+    let (new_artists, new_tracks, ratio, per_month, favorite_artists, favorite_tracks) =
+        tokio::try_join!(
+            analytics::discovery::new_artists(conn, &user.id, window),
+            analytics::discovery::new_tracks(conn, &user.id, window),
+            analytics::discovery::discovery_ratio(conn, &user.id, window),
+            analytics::discovery::discoveries_per_month(conn, &user.id, window, settings.timezone),
+            analytics::discovery::favorite_artists_timeline(conn, &user.id, window, limit),
+            analytics::discovery::favorite_tracks_timeline(conn, &user.id, window, limit),
+        )
+        .map_err(|db_err| {
+            error!("Error getting discoveries: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    Ok(Json(DiscoveryResponse {
+        window,
+        timezone: settings.timezone,
+        new_artist_count: new_artists.len() as u64,
+        new_track_count: new_tracks.len() as u64,
+        new_artists: new_artists.into_iter().take(limit as usize).collect(),
+        ratio,
+        per_month,
+        favorites: FavoritesTimeline {
+            artists: favorite_artists,
+            tracks: favorite_tracks,
+        },
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreaksQuery {
+    #[serde(flatten)]
+    window: WindowQuery,
+    #[serde(flatten)]
+    timezone: TimezoneQuery,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct StreaksResponse {
+    window: TimeWindow,
+    timezone: Tz,
+    /// The longest streak within the window
+    longest: Option<Streak>,
+    /// The streak that is still going, whatever the window
+    current: Option<Streak>,
+    artists: Vec<ArtistStreak>,
+    tracks: Vec<TrackStreak>,
+}
+
+/// Gets the days in a row the logged in user listened, overall and to each artist and track
+pub async fn streaks(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<StreaksQuery>,
+) -> Result<Json<StreaksResponse>, (StatusCode, String)> {
+    let window = query.window.window()?;
+    let tz = query
+        .timezone
+        .settings(&state.connection, &user.id)
+        .await?
+        .timezone;
+    let limit = query.limit.unwrap_or(10).clamp(1, MAX_TOP_LIMIT);
+    let conn = &state.connection;
+    let (days, all_days, artists, tracks) = tokio::try_join!(
+        analytics::calendar::daily_plays(conn, &user.id, window, tz),
+        analytics::calendar::daily_plays(conn, &user.id, TimeWindow::all(), tz),
+        analytics::streaks::artist_streaks(conn, &user.id, window, tz, limit),
+        analytics::streaks::track_streaks(conn, &user.id, window, tz, limit),
+    )
+    .map_err(|db_err| {
+        error!("Error getting streaks: {:?}", db_err);
+        (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+    })?;
+    let today = Utc::now().with_timezone(&tz).date_naive();
+    Ok(Json(StreaksResponse {
+        window,
+        timezone: tz,
+        longest: analytics::streaks::longest_streak(days.iter().map(|day| day.day)),
+        current: analytics::streaks::current_streak(all_days.iter().map(|day| day.day), today),
+        artists,
+        tracks,
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SkipsQuery {
+    #[serde(flatten)]
+    window: WindowQuery,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SkipsResponse {
+    window: TimeWindow,
+    #[serde(flatten)]
+    stats: SkipStats,
+    tracks: Vec<SkippedTrack>,
+}
+
+/// Gets how often the logged in user skips tracks, and the tracks they skip the most
+/// Skips are only known for play sessions, which are tracked while the poller is running
+pub async fn skips(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<SkipsQuery>,
+) -> Result<Json<SkipsResponse>, (StatusCode, String)> {
+    let window = query.window.window()?;
+    let limit = query.limit.unwrap_or(10).clamp(1, MAX_TOP_LIMIT);
+    let conn = &state.connection;
+    let (stats, tracks) = tokio::try_join!(
+        analytics::skips::skip_stats(conn, &user.id, window),
+        analytics::skips::most_skipped_tracks(conn, &user.id, window, limit),
+    )
+    .map_err(|db_err| {
+        error!("Error getting skips: {:?}", db_err);
+        (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+    })?;
+    Ok(Json(SkipsResponse {
+        window,
+        stats,
+        tracks,
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ObsessionsQuery {
+    #[serde(flatten)]
+    window: WindowQuery,
+    /// The least plays within the days to count as an obsession
+    plays: Option<u64>,
+    /// The number of days the plays have to be within
+    days: Option<u32>,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ObsessionsResponse {
+    window: TimeWindow,
+    plays: u64,
+    days: u32,
+    tracks: Vec<Obsession>,
+}
+
+/// Gets the tracks the logged in user played over and over within a few days
+pub async fn obsessions(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<ObsessionsQuery>,
+) -> Result<Json<ObsessionsResponse>, (StatusCode, String)> {
+    let window = query.window.window()?;
+    let plays = query.plays.unwrap_or(10).max(2);
+    let days = query.days.unwrap_or(7).clamp(1, MAX_OBSESSION_DAYS);
+    let limit = query.limit.unwrap_or(10).clamp(1, MAX_TOP_LIMIT);
+    let tracks =
+        analytics::streaks::obsessions(&state.connection, &user.id, window, plays, days, limit)
+            .await
+            .map_err(|db_err| {
+                error!("Error getting obsessions: {:?}", db_err);
+                (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+            })?;
+    Ok(Json(ObsessionsResponse {
+        window,
+        plays,
+        days,
+        tracks,
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ForgottenQuery {
+    #[serde(flatten)]
+    timezone: TimezoneQuery,
+    /// The months something has to have gone unplayed for
+    months: Option<u32>,
+    /// The least plays something has to have had to be a favorite
+    min_plays: Option<u64>,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ForgottenResponse {
+    /// Nothing has been played since this time
+    since: DateTime,
+    months: u32,
+    min_plays: u64,
+    timezone: Tz,
+    tracks: Vec<ForgottenTrack>,
+    albums: Vec<ForgottenAlbum>,
+}
+
+/// Gets the tracks and albums the logged in user used to play a lot but hasn't played in a while
+pub async fn forgotten(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<ForgottenQuery>,
+) -> Result<Json<ForgottenResponse>, (StatusCode, String)> {
+    let tz = query
+        .timezone
+        .settings(&state.connection, &user.id)
+        .await?
+        .timezone;
+    let months = query.months.unwrap_or(6).clamp(1, MAX_FORGOTTEN_MONTHS);
+    let min_plays = query.min_plays.unwrap_or(10).max(1);
+    let limit = query.limit.unwrap_or(10).clamp(1, MAX_TOP_LIMIT);
+    let since = analytics::forgotten::forgotten_since(months);
+    let conn = &state.connection;
+    let (tracks, albums) = tokio::try_join!(
+        analytics::forgotten::forgotten_tracks(conn, &user.id, since, min_plays, tz, limit),
+        analytics::forgotten::forgotten_albums(conn, &user.id, since, min_plays, tz, limit),
+    )
+    .map_err(|db_err| {
+        error!("Error getting forgotten favorites: {:?}", db_err);
+        (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+    })?;
+    Ok(Json(ForgottenResponse {
+        since,
+        months,
+        min_plays,
+        timezone: tz,
+        tracks,
+        albums,
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MonthlyQuery {
+    #[serde(flatten)]
+    window: WindowQuery,
+    #[serde(flatten)]
+    timezone: TimezoneQuery,
+    limit: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DiversityResponse {
+    window: TimeWindow,
+    timezone: Tz,
+    months: Vec<MonthDiversity>,
+}
+
+/// Gets how diverse the listening of the logged in user was in each month
+pub async fn diversity(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<MonthlyQuery>,
+) -> Result<Json<DiversityResponse>, (StatusCode, String)> {
+    let window = query.window.window()?;
+    let tz = query
+        .timezone
+        .settings(&state.connection, &user.id)
+        .await?
+        .timezone;
+    let months = analytics::diversity::monthly_diversity(&state.connection, &user.id, window, tz)
+        .await
+        .map_err(|db_err| {
+            error!("Error getting diversity: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?;
+    Ok(Json(DiversityResponse {
+        window,
+        timezone: tz,
+        months,
+    }))
+}
+
+#[derive(Serialize, Debug)]
+pub struct GenresResponse {
+    window: TimeWindow,
+    timezone: Tz,
+    /// The most played genres of the window, which the months are broken down by
+    genres: Vec<TopGenre>,
+    months: Vec<MonthGenres>,
+}
+
+/// Gets the share of the most played genres of the logged in user in each month
+pub async fn genres(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Query(query): Query<MonthlyQuery>,
+) -> Result<Json<GenresResponse>, (StatusCode, String)> {
+    let window = query.window.window()?;
+    let tz = query
+        .timezone
+        .settings(&state.connection, &user.id)
+        .await?
+        .timezone;
+    let limit = query.limit.unwrap_or(10).clamp(1, MAX_TOP_LIMIT);
+    let conn = &state.connection;
+    let (genres, months) = tokio::try_join!(
+        analytics::top::top_genres(conn, &user.id, window, limit),
+        analytics::diversity::monthly_genre_shares(conn, &user.id, window, tz, limit),
+    )
+    .map_err(|db_err| {
+        error!("Error getting genres: {:?}", db_err);
+        (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+    })?;
+    Ok(Json(GenresResponse {
+        window,
+        timezone: tz,
+        genres,
+        months,
+    }))
+}
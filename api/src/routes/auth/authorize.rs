@@ -0,0 +1,27 @@
+use super::CurrentUser;
+use axum::{
+    extract::Request,
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use entity::user;
+use lib::db::user::Role;
+
+/// The admin that is logged in, added to the request once `require_admin` lets it through
+#[derive(Clone)]
+pub struct Admin(pub user::Model);
+
+/// Only let the request through if an admin is logged in, rejecting anyone else
+/// API keys are rejected as well, since the admin routes don't have an `ApiScope`
+pub async fn require_admin(
+    CurrentUser(user): CurrentUser,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if Role::of(&user) != Role::Admin {
+        return (StatusCode::FORBIDDEN, "Only admins can do this".to_string()).into_response();
+    }
+    request.extensions_mut().insert(Admin(user));
+    next.run(request).await
+}
@@ -0,0 +1,173 @@
+use crate::assets::Assets;
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use lib::db::user::Provider;
+use serde::Serialize;
+use tracing::error;
+
+/// The placeholders in the login error page replaced with the details of the error
+const ERROR_TITLE_PLACEHOLDER: &str = "{{title}}";
+const ERROR_MESSAGE_PLACEHOLDER: &str = "{{message}}";
+const ERROR_RETRY_PLACEHOLDER: &str = "{{retry_url}}";
+
+/// The ways logging in with a provider can fail
+#[derive(Debug)]
+pub enum AuthError {
+    /// The user declined to give access on the provider's consent page
+    AccessDenied,
+    /// The provider sent back an error other than the user declining
+    Provider(String),
+    /// The state sent back didn't match the login started in this browser, or the login expired
+    InvalidState,
+    /// The provider sent back neither a code nor an error
+    MissingCode,
+    /// The code couldn't be exchanged for tokens
+    TokenExchange,
+    /// The profile of whoever logged in couldn't be fetched
+    Profile,
+    /// The account is already linked to another user, so it can't be linked to the one logged in
+    AccountInUse,
+    /// An admin disabled the account, so it can't be used to log in
+    AccountDisabled,
+    /// The provider can only be linked to a user that's logged in, and nobody is
+    NotLoggedIn,
+    /// The provider isn't set up on this instance
+    Disabled,
+    /// The user or their session couldn't be saved
+    Database,
+    /// Something on our end went wrong while building a request
+    Internal,
+}
+
+/// The body of an error when JSON was asked for
+#[derive(Serialize)]
+struct AuthErrorBody {
+    error: &'static str,
+    message: String,
+}
+
+impl AuthError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AuthError::AccessDenied | AuthError::AccountDisabled => StatusCode::FORBIDDEN,
+            AuthError::InvalidState | AuthError::MissingCode => StatusCode::BAD_REQUEST,
+            AuthError::AccountInUse => StatusCode::CONFLICT,
+            AuthError::NotLoggedIn => StatusCode::UNAUTHORIZED,
+            AuthError::Disabled => StatusCode::NOT_FOUND,
+            AuthError::Provider(_) | AuthError::TokenExchange | AuthError::Profile => {
+                StatusCode::BAD_GATEWAY
+            }
+            AuthError::Database | AuthError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// A short code for the error, used when it's returned as JSON
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthError::AccessDenied => "access_denied",
+            AuthError::Provider(_) => "provider_error",
+            AuthError::InvalidState => "invalid_state",
+            AuthError::MissingCode => "missing_code",
+            AuthError::TokenExchange => "token_exchange_failed",
+            AuthError::Profile => "profile_failed",
+            AuthError::AccountInUse => "account_in_use",
+            AuthError::AccountDisabled => "account_disabled",
+            AuthError::NotLoggedIn => "not_logged_in",
+            AuthError::Disabled => "provider_disabled",
+            AuthError::Database => "database_error",
+            AuthError::Internal => "internal_error",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            AuthError::AccessDenied => "Login cancelled",
+            AuthError::InvalidState => "Login expired",
+            AuthError::AccountInUse | AuthError::NotLoggedIn | AuthError::Disabled => {
+                "Account not linked"
+            }
+            _ => "Login failed",
+        }
+    }
+
+    /// A message explaining the error to the user, without any internal details
+    pub fn message(&self, provider: Provider) -> String {
+        let name = provider.name();
+        match self {
+            AuthError::AccessDenied => {
+                format!("{} wasn't connected, since access wasn't allowed", name)
+            }
+            AuthError::Provider(provider_error) => {
+                format!("{} couldn't log you in ({})", name, provider_error)
+            }
+            AuthError::InvalidState => {
+                "The login couldn't be verified, it may have taken too long or been started elsewhere"
+                    .to_string()
+            }
+            AuthError::MissingCode => format!("{} didn't send back a code", name),
+            AuthError::TokenExchange => format!("{} didn't accept the login", name),
+            AuthError::Profile => format!("Your {} profile couldn't be loaded", name),
+            AuthError::AccountInUse => format!(
+                "This {} account is already linked to another user, log out to log in with it",
+                name
+            ),
+            AuthError::AccountDisabled => format!(
+                "This {} account was disabled by an admin of this instance",
+                name
+            ),
+            AuthError::NotLoggedIn => format!(
+                "{} accounts can only be linked once you're logged in with Spotify",
+                name
+            ),
+            AuthError::Disabled => format!("{} isn't set up on this instance", name),
+            AuthError::Database | AuthError::Internal => {
+                "Something went wrong on our end, please try again later".to_string()
+            }
+        }
+    }
+
+    /// Render the error as JSON if the request asked for it, otherwise as a page with a link to try again
+    pub fn render(self, provider: Provider, headers: &HeaderMap) -> Response {
+        let wants_json = headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/json"));
+        if wants_json {
+            let body = AuthErrorBody {
+                error: self.code(),
+                message: self.message(provider),
+            };
+            return (self.status(), Json(body)).into_response();
+        }
+        let Some(template) = Assets::get("auth_error.html")
+            .and_then(|file| String::from_utf8(file.data.into()).ok())
+        else {
+            error!("Login error template is missing");
+            return (
+                self.status(),
+                format!("{}: {}", self.title(), self.message(provider)),
+            )
+                .into_response();
+        };
+        let page = template
+            .replace(ERROR_TITLE_PLACEHOLDER, &escape_html(self.title()))
+            .replace(
+                ERROR_MESSAGE_PLACEHOLDER,
+                &escape_html(&self.message(provider)),
+            )
+            .replace(ERROR_RETRY_PLACEHOLDER, &format!("/auth/{}", provider));
+        (self.status(), Html(page)).into_response()
+    }
+}
+
+/// Escape text so it can be put into a page as-is
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
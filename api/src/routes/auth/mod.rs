@@ -1,48 +1,80 @@
+mod authorize;
+mod error;
+mod oauth;
+mod session;
+
+pub use authorize::{require_admin, Admin};
+pub use session::{CurrentUser, SESSION_COOKIE};
+
+use crate::routes::AppState;
 use axum::{
-    extract::Query,
-    response::{IntoResponse, Redirect},
+    extract::{Query, State},
+    http::HeaderMap,
+    response::{IntoResponse, Redirect, Response},
     routing::get,
-    Json, Router,
+    Router,
 };
+use axum_extra::extract::cookie::{Cookie, CookieJar};
 use base64::prelude::*;
+use entity::user;
+use error::AuthError;
+use lib::{
+    config::SpotifyConfig,
+    db::{self, user::Provider},
+    music::{lastfm::LastfmClient, spotify::SpotifyClient},
+};
+use oauth::OAuthState;
 use serde::{Deserialize, Serialize};
 use surf::{http::mime, Body, Url};
 use tracing::error;
 
-pub fn get_auth_router() -> Router {
+pub fn get_auth_router() -> Router<AppState> {
     let spotify_routes = get_spotify_auth_router();
+    let lastfm_routes = get_lastfm_auth_router();
     Router::new()
         .route("/login", get(login))
+        .route("/logout", get(logout))
         .merge(spotify_routes)
+        .merge(lastfm_routes)
 }
 
-pub fn get_spotify_auth_router() -> Router {
+pub fn get_spotify_auth_router() -> Router<AppState> {
     Router::new()
         .route("/auth/spotify", get(spotify_auth))
         .route("/auth/spotify/callback", get(spotify_auth_callback))
 }
 
+pub fn get_lastfm_auth_router() -> Router<AppState> {
+    Router::new()
+        .route("/auth/lastfm", get(lastfm_auth))
+        .route("/auth/lastfm/callback", get(lastfm_auth_callback))
+}
+
 /// Redirects to the Spotify login page using the appropriate scopes
-async fn spotify_auth() -> impl IntoResponse {
-    const BASE_URL: &'static str = "https://accounts.spotify.com/authorize?";
-    let creds = SpotifyOAuthSettings::from_env();
-    let redirect_url = Url::parse_with_params(
-        BASE_URL,
-        &[
-            ("response_type", "code"),
-            ("client_id", &creds.client_id),
-            ("scope", &creds.scopes),
-            ("redirect_uri", &creds.redirect_uri),
-        ],
-    )
-    .expect("Failed to construct Spotify OAuth URL");
-    Redirect::to(&redirect_url.to_string())
+/// The login is bound to the browser with a state nonce in a cookie, checked by the callback
+async fn spotify_auth(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Response {
+    let spotify = &state.config.providers.spotify;
+    let login = OAuthState::new(spotify.pkce);
+    match authorize_url(spotify, &login) {
+        Ok(redirect_url) => {
+            let cookie = login.cookie(state.config.security.secure_cookies);
+            (jar.add(cookie), Redirect::to(redirect_url.as_ref())).into_response()
+        }
+        Err(auth_err) => auth_err.render(Provider::Spotify, &headers),
+    }
 }
 
 /// Query parameters from the Spotify callback
+/// Spotify sends either a code, or an error if the login didn't go through
 #[derive(Deserialize, Debug)]
 struct SpotifyCallbackQuery {
-    code: String,
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -50,6 +82,9 @@ struct SpotifyTokenRequest {
     code: String,
     redirect_uri: String,
     grant_type: String,
+    /// The PKCE code verifier, if the login was started with a code challenge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code_verifier: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -61,60 +96,306 @@ struct SpotifyTokenResponse {
     pub refresh_token: String,
 }
 
-impl Into<Body> for SpotifyTokenRequest {
-    fn into(self) -> Body {
-        Body::from_form(&self).expect("Failed to convert SpotifyTokenRequest to Body")
-    }
+/// What finishing a login did
+enum LoginOutcome {
+    /// Whoever logged in wasn't logged in before, so a session was started, holding its ID
+    LoggedIn(String),
+    /// The account was linked to the user that was already logged in, keeping their session
+    Linked,
 }
 
 /// Callback from Spotify after the user has logged in
-/// This functions upserts the user into the database
-async fn spotify_auth_callback(query: Query<SpotifyCallbackQuery>) -> impl IntoResponse {
+/// Any error is shown as a page, or returned as JSON if that's what was asked for
+async fn spotify_auth_callback(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Query(query): Query<SpotifyCallbackQuery>,
+) -> Response {
+    // The login in progress is only good for a single callback
+    let login = OAuthState::from_jar(&jar);
+    let jar = jar.remove(oauth::removal_cookie());
+    // Logging in while already logged in links the account to the logged in user
+    let current_user = match jar.get(session::SESSION_COOKIE) {
+        Some(cookie) => {
+            match db::session::get_session_user(&state.connection, cookie.value()).await {
+                Ok(current_user) => current_user,
+                Err(db_err) => {
+                    error!("Error looking up session: {:?}", db_err);
+                    return (jar, AuthError::Database.render(Provider::Spotify, &headers))
+                        .into_response();
+                }
+            }
+        }
+        None => None,
+    };
+    match finish_login(&state, login, query, current_user).await {
+        Ok(LoginOutcome::LoggedIn(session_id)) => (
+            jar.add(session::session_cookie(&state.config.security, session_id)),
+            Redirect::to("/"),
+        )
+            .into_response(),
+        Ok(LoginOutcome::Linked) => (jar, Redirect::to("/")).into_response(),
+        Err(auth_err) => (jar, auth_err.render(Provider::Spotify, &headers)).into_response(),
+    }
+}
+
+/// Check the callback belongs to the login started in this browser, then exchange the code for tokens
+/// If a user is logged in, the account is linked to them, otherwise this upserts the user into the database
+/// and starts a session for them
+async fn finish_login(
+    state: &AppState,
+    login: Option<OAuthState>,
+    query: SpotifyCallbackQuery,
+    current_user: Option<user::Model>,
+) -> Result<LoginOutcome, AuthError> {
+    if let Some(spotify_error) = query.error {
+        return Err(match spotify_error.as_str() {
+            "access_denied" => AuthError::AccessDenied,
+            _ => {
+                error!(
+                    "Spotify returned an error to the callback: {}",
+                    spotify_error
+                );
+                AuthError::Provider(spotify_error)
+            }
+        });
+    }
+    // The state has to match the one in the cookie, so a login can't be finished in another browser
+    let login = match (login, &query.state) {
+        (Some(login), Some(returned)) if login.verify(returned) => login,
+        _ => return Err(AuthError::InvalidState),
+    };
+    let code = query.code.ok_or(AuthError::MissingCode)?;
     // Using the code from the query, request an access token from Spotify
-    // If successful, upsert the user into the database
-    const BASE_URL: &'static str = "https://accounts.spotify.com/api/token";
-    let creds = SpotifyOAuthSettings::from_env();
+    const BASE_URL: &str = "https://accounts.spotify.com/api/token";
+    let creds = &state.config.providers.spotify;
     let auth_header =
         BASE64_STANDARD.encode(format!("{}:{}", creds.client_id, creds.client_secret));
-    let res: SpotifyTokenResponse = surf::post(BASE_URL)
+    let body = Body::from_form(&SpotifyTokenRequest {
+        code,
+        redirect_uri: creds.redirect_uri.clone(),
+        grant_type: "authorization_code".to_string(),
+        code_verifier: login.verifier,
+    })
+    .map_err(|form_err| {
+        error!("Failed to build the token request: {}", form_err);
+        AuthError::Internal
+    })?;
+    let mut res = surf::post(BASE_URL)
         .header("Authorization", format!("Basic {}", auth_header))
         .content_type(mime::FORM)
-        .body(SpotifyTokenRequest {
-            code: query.code.clone(),
-            redirect_uri: creds.redirect_uri.clone(),
-            grant_type: "authorization_code".to_string(),
-        })
-        .recv_json()
+        .body(body)
+        .await
+        .map_err(|surf_err| {
+            error!("Failed to request access token from Spotify: {}", surf_err);
+            AuthError::TokenExchange
+        })?;
+    if !res.status().is_success() {
+        let reason = res.body_string().await.unwrap_or_default();
+        error!(
+            "Spotify rejected the code with {}: {}",
+            res.status(),
+            reason
+        );
+        return Err(AuthError::TokenExchange);
+    }
+    let tokens: SpotifyTokenResponse = res.body_json().await.map_err(|surf_err| {
+        error!("Failed to read access token from Spotify: {}", surf_err);
+        AuthError::TokenExchange
+    })?;
+    // Look up who the tokens belong to, and save them as a user with a connected account
+    let profile = SpotifyClient::new(tokens.access_token.clone())
+        .get_current_user()
         .await
-        .map_err(|e| error!("Failed to request access token from Spotify: {}", e))
-        .unwrap();
-    Json(res)
+        .map_err(|spotify_err| {
+            error!("Error fetching Spotify profile: {:?}", spotify_err);
+            AuthError::Profile
+        })?;
+    if let Some(current_user) = current_user {
+        return db::user::link_account(
+            &state.connection,
+            &current_user.id,
+            db::user::LinkAccountOptions {
+                access_token: tokens.access_token,
+                refresh_token: tokens.refresh_token,
+                provider: Provider::Spotify.to_string(),
+                provider_id: profile.id,
+            },
+        )
+        .await
+        .map_err(|db_err| {
+            error!("Error linking account: {:?}", db_err);
+            AuthError::Database
+        })?
+        .map(|_| LoginOutcome::Linked)
+        .ok_or(AuthError::AccountInUse);
+    }
+    let (user, _) = db::user::upsert_user_with_account(
+        &state.connection,
+        db::user::CreateUserOptions {
+            email: profile.email.unwrap_or_default(),
+            name: profile.display_name.unwrap_or(profile.id.clone()),
+            access_token: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+            provider: Provider::Spotify.to_string(),
+            provider_id: profile.id,
+        },
+    )
+    .await
+    .map_err(|db_err| {
+        error!("Error saving user: {:?}", db_err);
+        AuthError::Database
+    })?
+    .ok_or(AuthError::AccountDisabled)?;
+    // Log the user in
+    let session = db::session::create_session(
+        &state.connection,
+        user.id,
+        state.config.security.session_ttl_days,
+    )
+    .await
+    .map_err(|db_err| {
+        error!("Error creating session: {:?}", db_err);
+        AuthError::Database
+    })?;
+    Ok(LoginOutcome::LoggedIn(session.id))
+}
+
+/// Redirects to the Last.fm page allowing access, to link the account to the logged in user
+/// Last.fm accounts have no email to create a user with, so they can only be linked, not logged in with
+async fn lastfm_auth(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    current_user: Option<CurrentUser>,
+) -> Response {
+    let lastfm = &state.config.providers.lastfm;
+    if !lastfm.enabled() {
+        return AuthError::Disabled.render(Provider::Lastfm, &headers);
+    }
+    if current_user.is_none() {
+        return AuthError::NotLoggedIn.render(Provider::Lastfm, &headers);
+    }
+    // Last.fm has no state parameter, but sends back whatever query the callback has
+    let login = OAuthState::new(false);
+    let callback_url = match Url::parse_with_params(&lastfm.callback_url, [("state", &login.state)])
+    {
+        Ok(callback_url) => callback_url,
+        Err(url_err) => {
+            error!("Failed to construct Last.fm callback URL: {}", url_err);
+            return AuthError::Internal.render(Provider::Lastfm, &headers);
+        }
+    };
+    match LastfmClient::new(lastfm.credentials()).auth_url(callback_url.as_str()) {
+        Ok(redirect_url) => {
+            let cookie = login.cookie(state.config.security.secure_cookies);
+            (jar.add(cookie), Redirect::to(redirect_url.as_ref())).into_response()
+        }
+        Err(_) => AuthError::Internal.render(Provider::Lastfm, &headers),
+    }
+}
+
+/// Query parameters from the Last.fm callback, the token is missing if access wasn't allowed
+#[derive(Deserialize, Debug)]
+struct LastfmCallbackQuery {
+    token: Option<String>,
+    state: Option<String>,
+}
+
+/// Callback from Last.fm after the user allowed access, linking the account to the logged in user
+async fn lastfm_auth_callback(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    current_user: Option<CurrentUser>,
+    Query(query): Query<LastfmCallbackQuery>,
+) -> Response {
+    let login = OAuthState::from_jar(&jar);
+    let jar = jar.remove(oauth::removal_cookie());
+    match finish_lastfm_link(&state, login, query, current_user).await {
+        Ok(()) => (jar, Redirect::to("/")).into_response(),
+        Err(auth_err) => (jar, auth_err.render(Provider::Lastfm, &headers)).into_response(),
+    }
+}
+
+/// Check the callback belongs to the link started in this browser, then exchange the token for a session
+/// The session key is saved as the account's access token, Last.fm sessions don't expire so there's no refresh token
+async fn finish_lastfm_link(
+    state: &AppState,
+    login: Option<OAuthState>,
+    query: LastfmCallbackQuery,
+    current_user: Option<CurrentUser>,
+) -> Result<(), AuthError> {
+    let lastfm = &state.config.providers.lastfm;
+    if !lastfm.enabled() {
+        return Err(AuthError::Disabled);
+    }
+    let CurrentUser(current_user) = current_user.ok_or(AuthError::NotLoggedIn)?;
+    match (login, &query.state) {
+        (Some(login), Some(returned)) if login.verify(returned) => {}
+        _ => return Err(AuthError::InvalidState),
+    };
+    let token = query.token.ok_or(AuthError::MissingCode)?;
+    let session = LastfmClient::new(lastfm.credentials())
+        .get_session(&token)
+        .await
+        .map_err(|lastfm_err| {
+            error!("Error getting Last.fm session: {:?}", lastfm_err);
+            AuthError::TokenExchange
+        })?;
+    db::user::link_account(
+        &state.connection,
+        &current_user.id,
+        db::user::LinkAccountOptions {
+            access_token: session.key,
+            refresh_token: String::new(),
+            provider: Provider::Lastfm.to_string(),
+            provider_id: session.name,
+        },
+    )
+    .await
+    .map_err(|db_err| {
+        error!("Error linking account: {:?}", db_err);
+        AuthError::Database
+    })?
+    .map(|_| ())
+    .ok_or(AuthError::AccountInUse)
 }
 
 async fn login() -> impl IntoResponse {
     "Login"
 }
 
-struct SpotifyOAuthSettings {
-    client_id: String,
-    client_secret: String,
-    scopes: String,
-    redirect_uri: String,
+/// Ends the current session, if there is one
+async fn logout(State(state): State<AppState>, jar: CookieJar) -> impl IntoResponse {
+    if let Some(cookie) = jar.get(session::SESSION_COOKIE) {
+        if let Err(db_err) = db::session::delete_session(&state.connection, cookie.value()).await {
+            error!("Error deleting session: {:?}", db_err);
+        }
+    }
+    (
+        jar.remove(Cookie::build(session::SESSION_COOKIE).path("/")),
+        Redirect::to("/"),
+    )
 }
 
-impl SpotifyOAuthSettings {
-    fn from_env() -> Self {
-        // Get the values from environment variables
-        let client_id = std::env::var("SPOTIFY_ID").expect("SPOTIFY_CLIENT_ID not set");
-        let client_secret = std::env::var("SPOTIFY_SECRET").expect("SPOTIFY_CLIENT_SECRET not set");
-        let scopes = std::env::var("SPOTIFY_SCOPES").expect("SPOTIFY_SCOPES not set");
-        let redirect_uri =
-            std::env::var("SPOTIFY_REDIRECT_URI").expect("SPOTIFY_REDIRECT_URI not set");
-        Self {
-            client_id,
-            client_secret,
-            scopes,
-            redirect_uri,
-        }
+/// Build the Spotify login page URL for a login in progress
+fn authorize_url(spotify: &SpotifyConfig, login: &OAuthState) -> Result<Url, AuthError> {
+    const BASE_URL: &str = "https://accounts.spotify.com/authorize?";
+    let mut params = vec![
+        ("response_type", "code".to_string()),
+        ("client_id", spotify.client_id.clone()),
+        ("scope", spotify.scopes.join(" ")),
+        ("redirect_uri", spotify.redirect_uri.clone()),
+        ("state", login.state.clone()),
+    ];
+    if let Some(challenge) = login.challenge() {
+        params.push(("code_challenge_method", "S256".to_string()));
+        params.push(("code_challenge", challenge));
     }
+    Url::parse_with_params(BASE_URL, &params).map_err(|url_err| {
+        error!("Failed to construct Spotify OAuth URL: {}", url_err);
+        AuthError::Internal
+    })
 }
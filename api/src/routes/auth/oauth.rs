@@ -0,0 +1,94 @@
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use base64::prelude::*;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use time::Duration;
+
+/// The name of the cookie binding a login in progress to the browser that started it
+pub const OAUTH_COOKIE: &str = "unwrapped_oauth";
+
+/// The path the cookie is scoped to, so it's only sent while logging in or linking an account
+const OAUTH_COOKIE_PATH: &str = "/auth";
+
+/// How long a login can take before it has to be started again
+const OAUTH_TTL_MINUTES: i64 = 10;
+
+/// The number of random bytes in the state nonce
+const STATE_LENGTH: usize = 32;
+
+/// The number of random bytes in the PKCE code verifier, encoding to 86 characters
+const VERIFIER_LENGTH: usize = 64;
+
+/// Generate random bytes encoded as URL safe base64
+fn random_token(length: usize) -> String {
+    let mut bytes = vec![0u8; length];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE64_URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The state of a login in progress, kept in a cookie until the provider redirects back
+pub struct OAuthState {
+    /// The nonce sent as the `state` parameter, that the provider sends back to the callback
+    pub state: String,
+    /// The PKCE code verifier, if PKCE is enabled
+    pub verifier: Option<String>,
+}
+
+impl OAuthState {
+    pub fn new(pkce: bool) -> Self {
+        OAuthState {
+            state: random_token(STATE_LENGTH),
+            verifier: pkce.then(|| random_token(VERIFIER_LENGTH)),
+        }
+    }
+
+    /// The PKCE code challenge derived from the verifier, using the S256 method
+    pub fn challenge(&self) -> Option<String> {
+        self.verifier
+            .as_ref()
+            .map(|verifier| BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())))
+    }
+
+    /// Read the login in progress from the cookies, if there is one
+    pub fn from_jar(jar: &CookieJar) -> Option<Self> {
+        let value = jar.get(OAUTH_COOKIE)?.value().to_string();
+        // The tokens are URL safe base64, so they never contain the separator
+        let (state, verifier) = match value.split_once('.') {
+            Some((state, verifier)) => (state.to_string(), Some(verifier.to_string())),
+            None => (value, None),
+        };
+        Some(OAuthState { state, verifier })
+    }
+
+    /// Build the cookie storing the login in progress, only sent over HTTPS when `secure` is set
+    pub fn cookie(&self, secure: bool) -> Cookie<'static> {
+        let value = match &self.verifier {
+            Some(verifier) => format!("{}.{}", self.state, verifier),
+            None => self.state.clone(),
+        };
+        Cookie::build((OAUTH_COOKIE, value))
+            .path(OAUTH_COOKIE_PATH)
+            .http_only(true)
+            .secure(secure)
+            .same_site(SameSite::Lax)
+            .max_age(Duration::minutes(OAUTH_TTL_MINUTES))
+            .build()
+    }
+
+    /// Check the state sent back to the callback is the one this login started with
+    pub fn verify(&self, state: &str) -> bool {
+        // Compared in constant time, so the nonce can't be guessed from how long the check takes
+        self.state.len() == state.len()
+            && self
+                .state
+                .bytes()
+                .zip(state.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+/// The cookie removing the login in progress, once the callback has used it
+pub fn removal_cookie() -> Cookie<'static> {
+    Cookie::build(OAUTH_COOKIE).path(OAUTH_COOKIE_PATH).build()
+}
@@ -0,0 +1,108 @@
+use crate::routes::AppState;
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, StatusCode},
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use entity::user;
+use lib::{
+    config::SecurityConfig,
+    db::{self, api_key::ApiScope},
+};
+use time::Duration;
+use tracing::error;
+
+/// The name of the cookie storing the session ID
+pub const SESSION_COOKIE: &str = "unwrapped_session";
+
+/// Build the cookie that keeps a user logged in, lasting as long as the session
+/// It's only sent over HTTPS unless secure cookies are turned off for local development
+pub fn session_cookie(security: &SecurityConfig, session_id: String) -> Cookie<'static> {
+    Cookie::build((SESSION_COOKIE, session_id))
+        .path("/")
+        .http_only(true)
+        .secure(security.secure_cookies)
+        .same_site(SameSite::Lax)
+        .max_age(Duration::days(security.session_ttl_days))
+        .build()
+}
+
+/// The user that is logged in, rejecting the request if nobody is
+/// Instead of the session cookie, an API key can be sent as a bearer token to routes with an `ApiScope`,
+/// as long as the key was given that scope
+pub struct CurrentUser(pub user::Model);
+
+/// Get the bearer token from the authorization header, if there is one
+fn bearer_token(parts: &Parts) -> Result<Option<&str>, (StatusCode, String)> {
+    let Some(authorization) = parts.headers.get(header::AUTHORIZATION) else {
+        return Ok(None);
+    };
+    authorization
+        .to_str()
+        .ok()
+        .and_then(|authorization| authorization.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, token)| Some(token.trim()))
+        .ok_or((
+            StatusCode::UNAUTHORIZED,
+            "Only bearer tokens are supported".to_string(),
+        ))
+}
+
+/// Get the user an API key belongs to, if the key has the scope the route needs
+async fn api_key_user(
+    parts: &Parts,
+    state: &AppState,
+    key: &str,
+) -> Result<user::Model, (StatusCode, String)> {
+    let scope = parts.extensions.get::<ApiScope>().copied().ok_or((
+        StatusCode::FORBIDDEN,
+        "API keys can't be used for this route".to_string(),
+    ))?;
+    let (api_key, user) = db::api_key::get_api_key_user(&state.connection, key)
+        .await
+        .map_err(|db_err| {
+            error!("Error looking up API key: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?
+        .ok_or((StatusCode::UNAUTHORIZED, "Invalid API key".to_string()))?;
+    if !api_key
+        .scopes
+        .iter()
+        .any(|granted| granted == scope.as_str())
+    {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("API key is missing the {} scope", scope),
+        ));
+    }
+    Ok(user)
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for CurrentUser {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(key) = bearer_token(parts)? {
+            return api_key_user(parts, state, key).await.map(CurrentUser);
+        }
+        let jar = CookieJar::from_headers(&parts.headers);
+        let session_id = jar
+            .get(SESSION_COOKIE)
+            .map(|cookie| cookie.value().to_string())
+            .ok_or((StatusCode::UNAUTHORIZED, "Not logged in".to_string()))?;
+        db::session::get_session_user(&state.connection, &session_id)
+            .await
+            .map_err(|db_err| {
+                error!("Error looking up session: {:?}", db_err);
+                (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+            })?
+            .map(CurrentUser)
+            .ok_or((StatusCode::UNAUTHORIZED, "Session expired".to_string()))
+    }
+}
@@ -1,24 +1,25 @@
-use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-};
-use entity::{album, artist, play_log, track};
+use crate::routes::auth::CurrentUser;
+use axum::{extract::State, http::StatusCode};
+use entity::{account, album, artist, play_log, track};
 use lib::{
-    db::{self, DBError},
-    music::spotify::{RecentTrack, RecentTrackExt, SpotifyClient, SpotifyError},
+    db::{self, user::Provider, DBError},
+    music::{
+        lastfm::LastfmClient,
+        listenbrainz::ListenBrainzClient,
+        scrobble::Scrobble,
+        spotify::{RecentTrack, RecentTrackExt, SpotifyClient, SpotifyCredentials, SpotifyError},
+    },
 };
 use sea_orm::{sqlx::types::chrono::DateTime, ActiveValue::NotSet, DatabaseConnection, Set};
-use serde::Deserialize;
+use std::collections::HashMap;
 use tracing::{debug, error};
 
-#[derive(Deserialize, Debug)]
-pub struct Tokens {
-    access_token: String,
-    refresh_token: Option<String>,
-}
-
 struct Collection {
+    user_id: String,
+    /// The linked account the tracks are collected from
+    account_id: i32,
     recent_tracks: Option<Vec<RecentTrack>>,
+    access_token: Option<String>,
     updated_token: Option<String>,
     db_artists: Option<Vec<artist::Model>>,
     db_albums: Option<Vec<album::Model>>,
@@ -26,9 +27,12 @@ struct Collection {
 }
 
 impl Collection {
-    fn new() -> Self {
+    fn new(user_id: String, account_id: i32) -> Self {
         Self {
+            user_id,
+            account_id,
             recent_tracks: None,
+            access_token: None,
             updated_token: None,
             db_artists: None,
             db_albums: None,
@@ -42,13 +46,17 @@ impl Collection {
         &mut self,
         access_token: String,
         refresh_token: Option<String>,
+        credentials: SpotifyCredentials,
     ) -> Result<&mut Self, SpotifyError> {
         // Generate a client for interacting with Spotify
-        let client = SpotifyClient::new(access_token).set_refresh_token(refresh_token);
+        let client = SpotifyClient::new(access_token.clone())
+            .set_refresh_token(refresh_token)
+            .set_credentials(credentials);
         // Fetch the recent tracks from Spotify
         match client.get_recent_tracks().await {
             Ok(recent_tracks) => {
                 self.recent_tracks = Some(recent_tracks.items.unwrap_or_default());
+                self.access_token = Some(access_token);
                 Ok(self)
             }
             Err(spotify_err) => {
@@ -65,6 +73,7 @@ impl Collection {
                             .await?;
                         // Return the recent tracks and the new access token
                         self.recent_tracks = Some(recent_tracks.items.unwrap_or_default());
+                        self.access_token = Some(new_token.access_token.clone());
                         self.updated_token = Some(new_token.access_token);
                         Ok(self)
                     }
@@ -80,65 +89,93 @@ impl Collection {
         // We store Artists, Albums, and Tracks separately, then use those ID's to craft a "PlayLog" entry
         // Top to bottom, artists -> albums -> tracks -> playlog
         debug!("Parsing artists from recent tracks");
-        let artist_models = self
-            .recent_tracks
-            .as_ref()
-            .expect("No recent tracks found, cannot upsert artists")
+        let artist_models = collected(&self.recent_tracks, "recent tracks")?
             .artists()
             .into_iter()
             .map(|artist| artist.model())
             .collect();
         // Upsert all the artists, returning the artists with their ID's
         debug!("Upserting artists into database");
-        let db_artists = db::spotify::upsert_artists(artist_models, conn)
-            .await
-            .expect("Error upserting artists");
+        let db_artists = db::spotify::upsert_artists(artist_models, conn).await?;
 
         self.db_artists = Some(db_artists);
         Ok(self)
     }
+    /// Upsert the genres of the artists from the recent tracks into the database
+    /// Genres are extra details, so failing to fetch them from Spotify doesn't fail the collection
+    async fn upsert_genres(&mut self, conn: &DatabaseConnection) -> Result<&mut Self, DBError> {
+        // The recent tracks only include simplified artists, so fetch the full artists for their genres
+        let mut artist_ids: Vec<String> = collected(&self.recent_tracks, "recent tracks")?
+            .artists()
+            .into_iter()
+            .filter_map(|artist| artist.id)
+            .collect();
+        artist_ids.sort();
+        artist_ids.dedup();
+        let client = SpotifyClient::new(collected(&self.access_token, "access token")?.clone());
+        let full_artists = match client.get_artists(&artist_ids).await {
+            Ok(full_artists) => full_artists,
+            Err(spotify_err) => {
+                error!("Error fetching artist genres: {:?}", spotify_err);
+                return Ok(self);
+            }
+        };
+        // Find the db artists by their name, the same way albums find their artists
+        let db_artists = collected(&self.db_artists, "artists")?;
+        let artist_genres = full_artists
+            .into_iter()
+            .filter_map(|full_artist| {
+                let db_artist = db_artists
+                    .iter()
+                    .find(|db_artist| db_artist.name == full_artist.name)?
+                    .to_owned();
+                Some((db_artist, full_artist.genres))
+            })
+            .collect();
+        debug!("Upserting genres into database");
+        db::spotify::upsert_artist_genres(artist_genres, conn).await?;
+        Ok(self)
+    }
     /// Upsert the albums from the recent tracks into the database
     async fn upsert_albums(&mut self, conn: &DatabaseConnection) -> Result<&mut Self, DBError> {
         // Next, convert the recent track albums to their models, using our databases artist IDs and save the albums/album artists
         debug!("Parsing albums from recent tracks");
-        let raw_albums_with_artists: Vec<(album::ActiveModel, Vec<artist::Model>)> = self
-            .recent_tracks
-            .as_ref()
-            .expect("No recent tracks found, cannot upsert albums")
-            // Get the raw spotify albums
-            .albums()
-            .into_iter()
-            // Convert to Album and AlbumArtist models using the artist ID's
-            .map(|album| {
-                // Get the album active model
-                let db_album = album.model();
-                // Find the artists for the album
-                let album_artists: Vec<artist::Model> = album
-                    // Iterate over all the album artists from spotify
-                    .artists
-                    .iter()
-                    // Find the relative db artists based on the recent tracks album artists
-                    .map(|alb_artist| {
-                        self.db_artists
-                            .as_ref()
-                            .expect("No artists found, cannot parse artist for upserting album")
-                            .iter()
-                            // Find the artist by their name
-                            .find(|db_artist| alb_artist.name == db_artist.name)
-                            .expect("Artist not found")
-                            .to_owned()
-                    })
-                    .collect();
+        let db_artists = collected(&self.db_artists, "artists")?;
+        let raw_albums_with_artists: Vec<(album::ActiveModel, Vec<artist::Model>)> =
+            collected(&self.recent_tracks, "recent tracks")?
+                // Get the raw spotify albums
+                .albums()
+                .into_iter()
+                // Convert to Album and AlbumArtist models using the artist ID's
+                .map(|album| {
+                    // Get the album active model
+                    let db_album = album.model();
+                    // Find the artists for the album
+                    let album_artists: Vec<artist::Model> = album
+                        // Iterate over all the album artists from spotify
+                        .artists
+                        .iter()
+                        // Find the relative db artists based on the recent tracks album artists
+                        .map(|alb_artist| {
+                            db_artists
+                                .iter()
+                                // Find the artist by their name
+                                .find(|db_artist| alb_artist.name == db_artist.name)
+                                .cloned()
+                                .ok_or_else(|| {
+                                    error!("Artist {} was not saved", alb_artist.name);
+                                    DBError
+                                })
+                        })
+                        .collect::<Result<_, DBError>>()?;
 
-                (db_album, album_artists)
-            })
-            .collect();
+                    Ok((db_album, album_artists))
+                })
+                .collect::<Result<_, DBError>>()?;
         // Upsert the albums with their artists, returning the albums with their ID's
         debug!("Upserting albums into database");
         let db_albums_with_artists =
-            db::spotify::upsert_albums_with_artists(raw_albums_with_artists, conn)
-                .await
-                .expect("Error upserting albums");
+            db::spotify::upsert_albums_with_artists(raw_albums_with_artists, conn).await?;
 
         self.db_albums = Some(db_albums_with_artists);
         Ok(self)
@@ -146,31 +183,20 @@ impl Collection {
     /// Upsert the tracks from the recent tracks into the database
     async fn upsert_tracks(&mut self, conn: &DatabaseConnection) -> Result<&mut Self, DBError> {
         // Each track should reference an artist and an album, and then use the album to also create an album track
-        let raw_tracks_with_albums: Vec<(track::ActiveModel, album::Model)> = self
-            .recent_tracks
-            .as_ref()
-            .expect("No recent tracks found, cannot upsert tracks")
-            .into_iter()
-            .map(|recent_track| {
-                // Get the track active model
-                let db_track = recent_track.track.model();
-                // Find the album
-                let db_album = self
-                    .db_albums
-                    .as_ref()
-                    .expect("No albums found, cannot parse album for upserting track")
-                    .iter()
-                    .find(|album| album.title == recent_track.track.album.name)
-                    .expect("Album not found")
-                    .to_owned();
-                (db_track, db_album)
-            })
-            .collect();
+        let raw_tracks_with_albums: Vec<(track::ActiveModel, album::Model)> =
+            collected(&self.recent_tracks, "recent tracks")?
+                .iter()
+                // The albums were saved in the order of the recent tracks
+                .zip(collected(&self.db_albums, "albums")?)
+                .map(|(recent_track, db_album)| {
+                    // Get the track active model
+                    let db_track = recent_track.track.model();
+                    (db_track, db_album.to_owned())
+                })
+                .collect();
         // Upsert the tracks with their albums, returning the tracks with their ID's
         let db_tracks_with_albums =
-            db::spotify::upsert_tracks_with_albums(raw_tracks_with_albums, conn)
-                .await
-                .expect("Error upserting tracks");
+            db::spotify::upsert_tracks_with_albums(raw_tracks_with_albums, conn).await?;
 
         self.db_tracks = Some(db_tracks_with_albums);
         Ok(self)
@@ -178,54 +204,136 @@ impl Collection {
     /// Upsert the playlogs from the recent tracks into the database
     async fn upsert_playlogs(&mut self, conn: &DatabaseConnection) -> Result<&mut Self, DBError> {
         // Finally, create the playlogs from the recent tracks
-        let raw_playlogs: Vec<play_log::ActiveModel> = self
-            .recent_tracks
-            .as_ref()
-            .expect("No recent tracks found, cannot upsert playlogs")
-            .into_iter()
-            .map(|recent_track| {
-                // Get the track
-                let db_track = self
-                    .db_tracks
-                    .as_ref()
-                    .expect("No tracks found, cannot parse track for upserting playlog")
-                    .iter()
-                    .find(|track| track.title == recent_track.track.name)
-                    .expect("Track not found")
-                    .to_owned();
-                // Parse the played_at into a DateTime timestamp. Should be up to seconds
-                let timestamp = DateTime::parse_from_rfc3339(&recent_track.played_at)
-                    .expect("Error parsing played_at from track")
-                    .naive_utc();
-                // Create the playlog
-                play_log::ActiveModel {
-                    id: NotSet,
-                    track_id: Set(db_track.id),
-                    played_at: Set(timestamp),
-                }
-            })
-            .collect();
+        let raw_playlogs: Vec<play_log::ActiveModel> =
+            collected(&self.recent_tracks, "recent tracks")?
+                .iter()
+                // The tracks were saved in the order of the recent tracks
+                .zip(collected(&self.db_tracks, "tracks")?)
+                .map(|(recent_track, db_track)| {
+                    // Parse the played_at into a DateTime timestamp. Should be up to seconds
+                    let timestamp = DateTime::parse_from_rfc3339(&recent_track.played_at)
+                        .map_err(|parse_err| {
+                            error!(
+                                "Error parsing played_at {} from track: {:?}",
+                                recent_track.played_at, parse_err
+                            );
+                            DBError
+                        })?
+                        .naive_utc();
+                    // Create the playlog
+                    Ok(play_log::ActiveModel {
+                        id: NotSet,
+                        track_id: Set(db_track.id),
+                        played_at: Set(timestamp),
+                        user_id: Set(Some(self.user_id.clone())),
+                        account_id: Set(Some(self.account_id)),
+                    })
+                })
+                .collect::<Result<_, DBError>>()?;
 
-        db::spotify::upsert_playlogs(raw_playlogs, conn)
-            .await
-            .expect("Error upserting playlogs");
+        db::spotify::upsert_playlogs(raw_playlogs, conn).await?;
 
         Ok(self)
     }
 }
 
-/// Collect goes to each of the configured providers, collects the relative data, and saves it to the DB
+/// Get what an earlier step of a collection saved, which is an error if the steps ran out of order
+fn collected<'a, T>(step: &'a Option<T>, name: &str) -> Result<&'a T, DBError> {
+    step.as_ref().ok_or_else(|| {
+        error!("No {} found, they have to be collected first", name);
+        DBError
+    })
+}
+
+/// Collect goes to each of the user's linked accounts, collects the relative data, and saves it to the DB
+/// The plays of every account are merged into the user's history, attributed to the account they came from
 pub async fn route(
     State(state): State<crate::routes::AppState>,
-    tokens: Query<Tokens>,
+    CurrentUser(user): CurrentUser,
+) -> Result<(), (StatusCode, String)> {
+    // Disabled accounts are left alone
+    let accounts: Vec<account::Model> = db::user::get_user_accounts(&state.connection, &user.id)
+        .await
+        .map_err(|db_err| {
+            error!("Error looking up accounts: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?
+        .into_iter()
+        .filter(|account| account.disabled_at.is_none())
+        .collect();
+    if accounts.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No account connected".to_string()));
+    }
+    // An account failing, like one whose access was revoked, doesn't stop the others from being collected
+    let mut failed = None;
+    for account in accounts {
+        let account_id = account.id;
+        if let Err(collect_err) = collect_account(&state, account).await {
+            error!("Error collecting account {}: {:?}", account_id, collect_err);
+            failed = Some(collect_err);
+        }
+    }
+    match failed {
+        Some(collect_err) => Err(collect_err),
+        // Return Ok if everything was successful
+        None => {
+            debug!("Successfully collected and upserted recent tracks");
+            Ok(())
+        }
+    }
+}
+
+/// Collect the recent tracks of a single linked account, saving how it went on the account
+pub(crate) async fn collect_account(
+    state: &crate::routes::AppState,
+    account: account::Model,
 ) -> Result<(), (StatusCode, String)> {
-    // Collect the tokens from the query
-    let access_token = tokens.access_token.to_owned();
-    let refresh_token = tokens.refresh_token.to_owned();
+    let account_id = account.id;
+    let collected = collect_recent(state, account).await;
+    let collection_error = collected.as_ref().err().map(|(_, message)| message.clone());
+    if let Err(db_err) =
+        db::user::record_collection(&state.connection, account_id, collection_error).await
+    {
+        error!(
+            "Error recording collection of account {}: {:?}",
+            account_id, db_err
+        );
+    }
+    collected
+}
+
+/// Collect the recent tracks of an account from its provider and save everything that was played
+async fn collect_recent(
+    state: &crate::routes::AppState,
+    account: account::Model,
+) -> Result<(), (StatusCode, String)> {
+    let provider = account
+        .provider
+        .parse()
+        .map_err(|message| (StatusCode::BAD_REQUEST, message))?;
+    match provider {
+        Provider::Spotify => collect_spotify(state, account).await,
+        Provider::Lastfm => collect_lastfm(state, account).await,
+        Provider::ListenBrainz => collect_listenbrainz(state, account).await,
+    }
+}
+
+/// Collect the recent tracks of a Spotify account, refreshing its access token if it expired
+async fn collect_spotify(
+    state: &crate::routes::AppState,
+    account: account::Model,
+) -> Result<(), (StatusCode, String)> {
+    let access_token = account.access_token.to_owned();
+    let refresh_token = Some(account.refresh_token.to_owned());
     // Initialize the collection
-    Collection::new()
+    let mut collection = Collection::new(account.user_id.clone(), account.id);
+    collection
         // Collect tracks from spotify
-        .collect_recent_tracks(access_token, refresh_token)
+        .collect_recent_tracks(
+            access_token,
+            refresh_token,
+            state.config.providers.spotify.credentials(),
+        )
         .await
         .map_err(|spotify_err| {
             error!("Error collecting recent tracks: {:?}", spotify_err);
@@ -238,6 +346,13 @@ pub async fn route(
             error!("Error upserting artists: {:?}", db_err);
             (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
         })?
+        // Save the genres of the artists
+        .upsert_genres(&state.connection)
+        .await
+        .map_err(|db_err| {
+            error!("Error upserting genres: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })?
         // Save albums
         .upsert_albums(&state.connection)
         .await
@@ -259,7 +374,162 @@ pub async fn route(
             error!("Error upserting playlogs: {:?}", db_err);
             (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
         })?;
-    // Return Ok if everything was successful
-    debug!("Successfully collected and upserted recent tracks");
+    // Save the new access token if it had to be refreshed
+    if let Some(updated_token) = collection.updated_token.take() {
+        db::user::update_account_tokens(&state.connection, account, updated_token, None)
+            .await
+            .map_err(|db_err| {
+                error!("Error saving refreshed token: {:?}", db_err);
+                (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+            })?;
+    }
     Ok(())
 }
+
+/// Collect the recent scrobbles of a Last.fm account, the account's access token is its session key
+async fn collect_lastfm(
+    state: &crate::routes::AppState,
+    account: account::Model,
+) -> Result<(), (StatusCode, String)> {
+    let lastfm = &state.config.providers.lastfm;
+    if !lastfm.enabled() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Last.fm isn't set up on this instance".to_string(),
+        ));
+    }
+    let scrobbles = LastfmClient::new(lastfm.credentials())
+        .get_recent_tracks(&account.provider_id, &account.access_token)
+        .await
+        .map_err(|lastfm_err| {
+            error!("Error collecting recent tracks: {:?}", lastfm_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, lastfm_err.message)
+        })?
+        .iter()
+        .filter_map(|recent_track| recent_track.scrobble())
+        .collect();
+    save_scrobbles(&state.connection, &account, scrobbles)
+        .await
+        .map_err(|db_err| {
+            error!("Error saving scrobbles: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })
+}
+
+/// Collect the recent listens of a ListenBrainz account, the account's access token is its user token
+async fn collect_listenbrainz(
+    state: &crate::routes::AppState,
+    account: account::Model,
+) -> Result<(), (StatusCode, String)> {
+    let scrobbles = ListenBrainzClient::new(
+        &state.config.providers.listenbrainz.api_url,
+        account.access_token.clone(),
+    )
+    .get_listens(&account.provider_id)
+    .await
+    .map_err(|listenbrainz_err| {
+        error!("Error collecting listens: {:?}", listenbrainz_err);
+        (StatusCode::INTERNAL_SERVER_ERROR, listenbrainz_err.message)
+    })?
+    .iter()
+    .filter_map(|listen| listen.scrobble())
+    .collect();
+    save_scrobbles(&state.connection, &account, scrobbles)
+        .await
+        .map_err(|db_err| {
+            error!("Error saving listens: {:?}", db_err);
+            (StatusCode::INTERNAL_SERVER_ERROR, db_err.to_string())
+        })
+}
+
+/// Save the scrobbles of an account the same way as Spotify's recent tracks, artists -> albums -> tracks -> playlog
+/// Scrobbles are matched to tracks already saved on their URIs, and on their title, artist and album without one
+async fn save_scrobbles(
+    conn: &DatabaseConnection,
+    account: &account::Model,
+    scrobbles: Vec<Scrobble>,
+) -> Result<(), DBError> {
+    if scrobbles.is_empty() {
+        debug!("No scrobbles to save for account {}", account.id);
+        return Ok(());
+    }
+    // Tracks that were already saved, like Spotify tracks played through another player, keep their album
+    let mut saved_tracks: HashMap<String, i32> = HashMap::new();
+    for uri in scrobbles
+        .iter()
+        .filter_map(|scrobble| scrobble.track_uri.as_ref())
+    {
+        if saved_tracks.contains_key(uri) {
+            continue;
+        }
+        if let Some(db_track) = db::spotify::get_track_by_uri(uri, conn).await? {
+            saved_tracks.insert(uri.clone(), db_track.id);
+        }
+    }
+    let saved_track = |scrobble: &Scrobble| {
+        scrobble
+            .track_uri
+            .as_ref()
+            .and_then(|uri| saved_tracks.get(uri).copied())
+    };
+    let unsaved: Vec<&Scrobble> = scrobbles
+        .iter()
+        .filter(|scrobble| saved_track(scrobble).is_none())
+        .collect();
+    // The tracks are saved in the order of the unsaved scrobbles
+    let mut db_tracks = if unsaved.is_empty() {
+        vec![]
+    } else {
+        save_scrobbled_tracks(conn, &unsaved).await?
+    }
+    .into_iter();
+    let playlogs = scrobbles
+        .iter()
+        .map(|scrobble| {
+            let track_id = match saved_track(scrobble) {
+                Some(track_id) => track_id,
+                None => db_tracks.next().ok_or(DBError)?.id,
+            };
+            Ok(play_log::ActiveModel {
+                id: NotSet,
+                track_id: Set(track_id),
+                played_at: Set(scrobble.played_at),
+                user_id: Set(Some(account.user_id.clone())),
+                account_id: Set(Some(account.id)),
+            })
+        })
+        .collect::<Result<_, DBError>>()?;
+    db::spotify::upsert_playlogs(playlogs, conn).await
+}
+
+/// Save the artists, albums and tracks of scrobbles whose tracks weren't saved yet, returning the track of each scrobble
+async fn save_scrobbled_tracks(
+    conn: &DatabaseConnection,
+    scrobbles: &[&Scrobble],
+) -> Result<Vec<track::Model>, DBError> {
+    let artist_models = scrobbles
+        .iter()
+        .map(|scrobble| scrobble.artist_model())
+        .collect();
+    let db_artists = db::spotify::upsert_artists(artist_models, conn).await?;
+    let artist_of = |scrobble: &Scrobble| {
+        db_artists
+            .iter()
+            .find(|db_artist| db_artist.name == scrobble.artist)
+            .cloned()
+            .ok_or(DBError)
+    };
+    let albums_with_artists = scrobbles
+        .iter()
+        .map(|scrobble| Ok((scrobble.album_model(), vec![artist_of(scrobble)?])))
+        .collect::<Result<_, DBError>>()?;
+    // Each album is saved with its artist, so albums of different artists sharing a title stay apart
+    let db_albums = db::spotify::upsert_albums_with_artists(albums_with_artists, conn).await?;
+    // And each track is saved on its album, so tracks sharing a title only merge with the same artist and album
+    let tracks_with_albums = scrobbles
+        .iter()
+        .zip(db_albums)
+        .map(|(scrobble, db_album)| (scrobble.track_model(), db_album))
+        .collect();
+    db::spotify::upsert_tracks_with_albums(tracks_with_albums, conn).await
+}
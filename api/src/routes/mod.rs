@@ -1,24 +1,36 @@
+mod admin;
+mod api;
 mod auth;
 mod collect;
+mod report;
 
 use crate::assets::Assets;
 use axum::{response::Html, routing::get, Router};
+use lib::{config::Config, db::api_key::ApiScope};
 use sea_orm::DatabaseConnection;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct AppState {
     pub connection: DatabaseConnection,
+    pub config: Arc<Config>,
 }
 
 pub fn router(state: AppState) -> Router {
     let auth_router = auth::get_auth_router();
     let collect_router = Router::new()
         .route("/", get(index))
-        .route("/collect", get(collect::route));
+        .route(
+            "/collect",
+            api::scoped(ApiScope::WriteCollect, get(collect::route)),
+        )
+        .route("/unwrapped/:year", get(report::page));
     Router::new()
         .merge(collect_router)
-        .with_state(state)
         .merge(auth_router)
+        .nest("/api/admin", admin::get_admin_router(state.clone()))
+        .nest("/api", api::get_api_router())
+        .with_state(state)
 }
 
 async fn index() -> Html<String> {
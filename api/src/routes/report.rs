@@ -0,0 +1,34 @@
+use crate::{
+    assets::Assets,
+    routes::{api::reports, auth::CurrentUser, AppState},
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Html,
+};
+use tracing::error;
+
+/// The placeholder in the report page replaced with the report data
+const REPORT_PLACEHOLDER: &str = "{{report}}";
+
+/// Renders the year in review of the logged in user as a page
+pub async fn page(
+    State(state): State<AppState>,
+    CurrentUser(user): CurrentUser,
+    Path(year): Path<i32>,
+) -> Result<Html<String>, (StatusCode, String)> {
+    let report = reports::get_or_create_report(&state.connection, &user.id, year).await?;
+    let template = Assets::get("report.html")
+        .and_then(|file| String::from_utf8(file.data.into()).ok())
+        .ok_or_else(|| {
+            error!("Report template is missing");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Report template is missing".to_string(),
+            )
+        })?;
+    // The report is embedded in a script tag, so it can't be allowed to close the tag
+    let data = report.data.to_string().replace("</", "<\\/");
+    Ok(Html(template.replace(REPORT_PLACEHOLDER, &data)))
+}
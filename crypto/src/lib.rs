@@ -0,0 +1,337 @@
+//! Envelope encryption for account tokens: each account's tokens are encrypted by its own data key,
+//! which is stored wrapped by a master key. It's shared by the app and the migrations encrypting existing rows.
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::prelude::*;
+use std::{collections::HashMap, sync::OnceLock};
+
+/// The environment variable holding the master keys, as comma separated `id:base64` pairs
+/// The first key encrypts new data keys, the others are only used to decrypt until they're rotated out
+pub const KEYS_VAR: &str = "TOKEN_ENCRYPTION_KEYS";
+
+/// The length of the nonce put in front of every ciphertext
+const NONCE_LENGTH: usize = 12;
+
+#[derive(Debug)]
+pub struct CryptoError(pub String);
+
+impl std::fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Encryption Error: {}", self.0)
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+/// Encrypt a value with a random nonce, returning the nonce and ciphertext encoded as base64
+/// The associated data isn't stored, the same has to be given to decrypt the value
+fn encrypt(cipher: &Aes256Gcm, plaintext: &[u8], aad: &[u8]) -> Result<String, CryptoError> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| CryptoError("Failed to encrypt".to_string()))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(BASE64_STANDARD.encode(sealed))
+}
+
+/// Decrypt a value encrypted by `encrypt`
+fn decrypt(cipher: &Aes256Gcm, sealed: &str, aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let sealed = BASE64_STANDARD
+        .decode(sealed)
+        .map_err(|_| CryptoError("Ciphertext is not valid base64".to_string()))?;
+    if sealed.len() < NONCE_LENGTH {
+        return Err(CryptoError("Ciphertext is too short".to_string()));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| {
+            CryptoError("Failed to decrypt, the key or where it's stored may be wrong".to_string())
+        })
+}
+
+/// The columns of an account holding an encrypted token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenColumn {
+    AccessToken,
+    RefreshToken,
+}
+
+impl TokenColumn {
+    fn name(&self) -> &'static str {
+        match self {
+            TokenColumn::AccessToken => "access_token",
+            TokenColumn::RefreshToken => "refresh_token",
+        }
+    }
+}
+
+/// The associated data binding a token to the account and column it's stored in
+fn token_aad(account_id: i32, column: TokenColumn) -> Vec<u8> {
+    format!("account:{}:{}", account_id, column.name()).into_bytes()
+}
+
+/// The key encrypting the tokens of a single account
+pub struct DataKey(Aes256Gcm);
+
+impl DataKey {
+    /// Encrypt a token of an account, bound to the account and column it's stored in,
+    /// so it can't be decrypted once it's moved to another account or column
+    pub fn encrypt(
+        &self,
+        account_id: i32,
+        column: TokenColumn,
+        token: &str,
+    ) -> Result<String, CryptoError> {
+        encrypt(&self.0, token.as_bytes(), &token_aad(account_id, column))
+    }
+
+    /// Decrypt a token of an account encrypted by `encrypt` for the same account and column
+    pub fn decrypt(
+        &self,
+        account_id: i32,
+        column: TokenColumn,
+        sealed: &str,
+    ) -> Result<String, CryptoError> {
+        into_token(decrypt(&self.0, sealed, &token_aad(account_id, column))?)
+    }
+}
+
+fn into_token(decrypted: Vec<u8>) -> Result<String, CryptoError> {
+    String::from_utf8(decrypted)
+        .map_err(|_| CryptoError("Decrypted token is not valid UTF-8".to_string()))
+}
+
+/// A data key wrapped by a master key, as it is stored on the account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrappedKey {
+    /// The ID of the master key that wrapped the data key
+    pub key_id: String,
+    pub data_key: String,
+}
+
+/// The master keys that wrap the data keys
+pub struct Keyring {
+    active: String,
+    keys: HashMap<String, Aes256Gcm>,
+}
+
+impl Keyring {
+    /// Read the master keys from the environment
+    pub fn from_env() -> Result<Self, CryptoError> {
+        let keys =
+            std::env::var(KEYS_VAR).map_err(|_| CryptoError(format!("{} is not set", KEYS_VAR)))?;
+        Self::parse(&keys)
+    }
+
+    /// Parse master keys from comma separated `id:base64` pairs, where each key is 32 bytes
+    pub fn parse(keys: &str) -> Result<Self, CryptoError> {
+        let mut active = None;
+        let mut parsed = HashMap::new();
+        for pair in keys
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let (id, key) = pair
+                .split_once(':')
+                .ok_or(CryptoError(format!("Key {} is missing an ID", pair)))?;
+            let key = BASE64_STANDARD
+                .decode(key)
+                .map_err(|_| CryptoError(format!("Key {} is not valid base64", id)))?;
+            if key.len() != 32 {
+                return Err(CryptoError(format!("Key {} must be 32 bytes", id)));
+            }
+            // A repeated ID would replace the earlier key, leaving the tokens it wrapped undecryptable
+            if parsed.contains_key(id) {
+                return Err(CryptoError(format!("Key {} is listed more than once", id)));
+            }
+            active.get_or_insert_with(|| id.to_string());
+            parsed.insert(
+                id.to_string(),
+                Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+            );
+        }
+        Ok(Keyring {
+            active: active.ok_or(CryptoError(format!("{} has no keys", KEYS_VAR)))?,
+            keys: parsed,
+        })
+    }
+
+    /// The ID of the master key new data keys are wrapped with
+    pub fn active_key_id(&self) -> &str {
+        &self.active
+    }
+
+    /// Generate a data key for a new account, along with the data key wrapped by the active master key
+    pub fn new_data_key(&self) -> Result<(DataKey, WrappedKey), CryptoError> {
+        let key = Aes256Gcm::generate_key(OsRng);
+        let wrapped = self.wrap(&key)?;
+        Ok((DataKey(Aes256Gcm::new(&key)), wrapped))
+    }
+
+    /// Unwrap the data key of an account
+    pub fn unwrap(&self, wrapped: &WrappedKey) -> Result<DataKey, CryptoError> {
+        Ok(DataKey(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(
+            &self.unwrap_raw(wrapped)?,
+        ))))
+    }
+
+    /// Wrap a data key again with the active master key, used when rotating master keys
+    pub fn rewrap(&self, wrapped: &WrappedKey) -> Result<WrappedKey, CryptoError> {
+        let key = self.unwrap_raw(wrapped)?;
+        self.wrap(Key::<Aes256Gcm>::from_slice(&key))
+    }
+
+    fn wrap(&self, key: &Key<Aes256Gcm>) -> Result<WrappedKey, CryptoError> {
+        let master = &self.keys[&self.active];
+        Ok(WrappedKey {
+            key_id: self.active.clone(),
+            data_key: encrypt(master, key, &[])?,
+        })
+    }
+
+    fn unwrap_raw(&self, wrapped: &WrappedKey) -> Result<Vec<u8>, CryptoError> {
+        let master = self.keys.get(&wrapped.key_id).ok_or(CryptoError(format!(
+            "Master key {} is not configured",
+            wrapped.key_id
+        )))?;
+        let key = decrypt(master, &wrapped.data_key, &[])?;
+        if key.len() != 32 {
+            return Err(CryptoError("Data key must be 32 bytes".to_string()));
+        }
+        Ok(key)
+    }
+}
+
+/// The master keys of the process, installed once at startup
+static KEYRING: OnceLock<Keyring> = OnceLock::new();
+
+/// Install the master keys the process encrypts tokens with, before anything encrypts or decrypts them
+/// Only the first keys installed are used, returns false if keys were already installed
+pub fn install_keyring(keyring: Keyring) -> bool {
+    KEYRING.set(keyring).is_ok()
+}
+
+/// Get the master keys the process was started with
+pub fn keyring() -> Result<&'static Keyring, CryptoError> {
+    KEYRING
+        .get()
+        .ok_or_else(|| CryptoError("Token encryption keys have not been installed".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRST: &str = "first:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+    const SECOND: &str = "second:AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+
+    #[test]
+    fn tokens_round_trip() {
+        let keyring = Keyring::parse(FIRST).unwrap();
+        let (key, wrapped) = keyring.new_data_key().unwrap();
+        assert_eq!(wrapped.key_id, "first");
+        let sealed = key
+            .encrypt(7, TokenColumn::AccessToken, "access-token")
+            .unwrap();
+        assert_ne!(sealed, "access-token");
+        // The data key is stored wrapped, so the token has to decrypt with the unwrapped copy
+        let key = keyring.unwrap(&wrapped).unwrap();
+        assert_eq!(
+            key.decrypt(7, TokenColumn::AccessToken, &sealed).unwrap(),
+            "access-token"
+        );
+    }
+
+    #[test]
+    fn tokens_are_bound_to_their_account_and_column() {
+        let keyring = Keyring::parse(FIRST).unwrap();
+        let (key, _) = keyring.new_data_key().unwrap();
+        let sealed = key
+            .encrypt(7, TokenColumn::AccessToken, "access-token")
+            .unwrap();
+        assert!(key.decrypt(7, TokenColumn::RefreshToken, &sealed).is_err());
+        assert!(key.decrypt(8, TokenColumn::AccessToken, &sealed).is_err());
+    }
+
+    #[test]
+    fn wrong_data_key_fails_to_decrypt() {
+        let keyring = Keyring::parse(FIRST).unwrap();
+        let (key, _) = keyring.new_data_key().unwrap();
+        let (other_key, _) = keyring.new_data_key().unwrap();
+        let sealed = key
+            .encrypt(7, TokenColumn::AccessToken, "access-token")
+            .unwrap();
+        assert!(other_key
+            .decrypt(7, TokenColumn::AccessToken, &sealed)
+            .is_err());
+    }
+
+    #[test]
+    fn wrong_master_key_fails_to_unwrap() {
+        let (_, wrapped) = Keyring::parse(FIRST).unwrap().new_data_key().unwrap();
+        // A master key with the same ID but different bytes
+        let wrong = Keyring::parse("first:AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=").unwrap();
+        assert!(wrong.unwrap(&wrapped).is_err());
+        // And a keyring without the master key at all
+        assert!(Keyring::parse(SECOND).unwrap().unwrap(&wrapped).is_err());
+    }
+
+    #[test]
+    fn rotating_rewraps_with_the_active_key() {
+        let old = Keyring::parse(FIRST).unwrap();
+        let (key, wrapped) = old.new_data_key().unwrap();
+        let sealed = key
+            .encrypt(7, TokenColumn::RefreshToken, "refresh-token")
+            .unwrap();
+        // The new key goes first, the old one is kept until everything is rewrapped
+        let rotating = Keyring::parse(&format!("{},{}", SECOND, FIRST)).unwrap();
+        assert_eq!(rotating.active_key_id(), "second");
+        assert!(rotating.unwrap(&wrapped).is_ok());
+        let rewrapped = rotating.rewrap(&wrapped).unwrap();
+        assert_eq!(rewrapped.key_id, "second");
+        // Once rewrapped the old key can be removed, and the tokens don't need encrypting again
+        let rotated = Keyring::parse(SECOND).unwrap();
+        let key = rotated.unwrap(&rewrapped).unwrap();
+        assert_eq!(
+            key.decrypt(7, TokenColumn::RefreshToken, &sealed).unwrap(),
+            "refresh-token"
+        );
+        assert!(rotated.unwrap(&wrapped).is_err());
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        assert!(Keyring::parse("").is_err());
+        assert!(Keyring::parse("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=").is_err());
+        assert!(Keyring::parse("short:AAAA").is_err());
+        assert!(Keyring::parse("invalid:not base64!").is_err());
+    }
+
+    #[test]
+    fn duplicate_key_ids_are_rejected() {
+        let keys = format!(
+            "{},first:AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=",
+            FIRST
+        );
+        assert!(Keyring::parse(&keys).is_err());
+        assert!(Keyring::parse(&format!("{},{}", FIRST, SECOND)).is_ok());
+    }
+}
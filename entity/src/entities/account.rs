@@ -8,15 +8,24 @@ pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
     pub provider: String,
-    #[sea_orm(unique)]
     pub provider_id: String,
     pub access_token: String,
     pub refresh_token: String,
     pub user_id: String,
+    pub key_id: String,
+    pub data_key: String,
+    pub disabled_at: Option<DateTime>,
+    pub last_collection_at: Option<DateTime>,
+    pub last_collected_at: Option<DateTime>,
+    pub last_collection_error: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::play_log::Entity")]
+    PlayLog,
+    #[sea_orm(has_many = "super::playlist::Entity")]
+    Playlist,
     #[sea_orm(
         belongs_to = "super::user::Entity",
         from = "Column::UserId",
@@ -27,6 +36,18 @@ pub enum Relation {
     User,
 }
 
+impl Related<super::play_log::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PlayLog.def()
+    }
+}
+
+impl Related<super::playlist::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Playlist.def()
+    }
+}
+
 impl Related<super::user::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::User.def()
@@ -8,9 +8,11 @@ pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
     pub title: String,
-    pub release_date: Date,
+    pub release_date: Option<Date>,
     pub created_at: DateTime,
     pub updated_at: Option<DateTime>,
+    #[sea_orm(unique)]
+    pub uri: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -17,6 +17,8 @@ pub struct Model {
 pub enum Relation {
     #[sea_orm(has_many = "super::album_artist::Entity")]
     AlbumArtist,
+    #[sea_orm(has_many = "super::artist_genre::Entity")]
+    ArtistGenre,
 }
 
 impl Related<super::album_artist::Entity> for Entity {
@@ -25,6 +27,12 @@ impl Related<super::album_artist::Entity> for Entity {
     }
 }
 
+impl Related<super::artist_genre::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ArtistGenre.def()
+    }
+}
+
 impl Related<super::album::Entity> for Entity {
     fn to() -> RelationDef {
         super::album_artist::Relation::Album.def()
@@ -34,4 +42,13 @@ impl Related<super::album::Entity> for Entity {
     }
 }
 
+impl Related<super::genre::Entity> for Entity {
+    fn to() -> RelationDef {
+        super::artist_genre::Relation::Genre.def()
+    }
+    fn via() -> Option<RelationDef> {
+        Some(super::artist_genre::Relation::Artist.def().rev())
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
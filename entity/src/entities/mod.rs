@@ -6,7 +6,15 @@ pub mod account;
 pub mod album;
 pub mod album_artist;
 pub mod album_track;
+pub mod api_key;
 pub mod artist;
+pub mod artist_genre;
+pub mod genre;
 pub mod play_log;
+pub mod play_session;
+pub mod playlist;
+pub mod report;
+pub mod session;
 pub mod track;
 pub mod user;
+pub mod user_settings;
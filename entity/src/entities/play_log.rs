@@ -8,12 +8,21 @@ pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
     pub track_id: i32,
-    #[sea_orm(unique)]
     pub played_at: DateTime,
+    pub user_id: Option<String>,
+    pub account_id: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::account::Entity",
+        from = "Column::AccountId",
+        to = "super::account::Column::Id",
+        on_update = "Cascade",
+        on_delete = "SetNull"
+    )]
+    Account,
     #[sea_orm(
         belongs_to = "super::track::Entity",
         from = "Column::TrackId",
@@ -22,6 +31,20 @@ pub enum Relation {
         on_delete = "NoAction"
     )]
     Track,
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    User,
+}
+
+impl Related<super::account::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Account.def()
+    }
 }
 
 impl Related<super::track::Entity> for Entity {
@@ -30,4 +53,10 @@ impl Related<super::track::Entity> for Entity {
     }
 }
 
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -4,7 +4,15 @@ pub use super::account::Entity as Account;
 pub use super::album::Entity as Album;
 pub use super::album_artist::Entity as AlbumArtist;
 pub use super::album_track::Entity as AlbumTrack;
+pub use super::api_key::Entity as ApiKey;
 pub use super::artist::Entity as Artist;
+pub use super::artist_genre::Entity as ArtistGenre;
+pub use super::genre::Entity as Genre;
 pub use super::play_log::Entity as PlayLog;
+pub use super::play_session::Entity as PlaySession;
+pub use super::playlist::Entity as Playlist;
+pub use super::report::Entity as Report;
+pub use super::session::Entity as Session;
 pub use super::track::Entity as Track;
 pub use super::user::Entity as User;
+pub use super::user_settings::Entity as UserSettings;
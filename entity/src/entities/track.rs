@@ -10,6 +10,9 @@ pub struct Model {
     pub title: String,
     pub created_at: DateTime,
     pub updated_at: Option<DateTime>,
+    pub duration_ms: Option<i32>,
+    #[sea_orm(unique)]
+    pub uri: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -18,6 +21,8 @@ pub enum Relation {
     AlbumTrack,
     #[sea_orm(has_many = "super::play_log::Entity")]
     PlayLog,
+    #[sea_orm(has_many = "super::play_session::Entity")]
+    PlaySession,
 }
 
 impl Related<super::album_track::Entity> for Entity {
@@ -32,6 +37,12 @@ impl Related<super::play_log::Entity> for Entity {
     }
 }
 
+impl Related<super::play_session::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PlaySession.def()
+    }
+}
+
 impl Related<super::album::Entity> for Entity {
     fn to() -> RelationDef {
         super::album_track::Relation::Album.def()
@@ -11,12 +11,27 @@ pub struct Model {
     pub email: String,
     pub created_at: DateTime,
     pub updated_at: Option<DateTime>,
+    pub role: String,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(has_many = "super::account::Entity")]
     Account,
+    #[sea_orm(has_many = "super::api_key::Entity")]
+    ApiKey,
+    #[sea_orm(has_many = "super::play_log::Entity")]
+    PlayLog,
+    #[sea_orm(has_many = "super::play_session::Entity")]
+    PlaySession,
+    #[sea_orm(has_many = "super::playlist::Entity")]
+    Playlist,
+    #[sea_orm(has_many = "super::report::Entity")]
+    Report,
+    #[sea_orm(has_many = "super::session::Entity")]
+    Session,
+    #[sea_orm(has_one = "super::user_settings::Entity")]
+    UserSettings,
 }
 
 impl Related<super::account::Entity> for Entity {
@@ -25,4 +40,46 @@ impl Related<super::account::Entity> for Entity {
     }
 }
 
+impl Related<super::api_key::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ApiKey.def()
+    }
+}
+
+impl Related<super::play_log::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PlayLog.def()
+    }
+}
+
+impl Related<super::play_session::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PlaySession.def()
+    }
+}
+
+impl Related<super::playlist::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Playlist.def()
+    }
+}
+
+impl Related<super::report::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Report.def()
+    }
+}
+
+impl Related<super::session::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Session.def()
+    }
+}
+
+impl Related<super::user_settings::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::UserSettings.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
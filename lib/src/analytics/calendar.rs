@@ -0,0 +1,123 @@
+use chrono_tz::Tz;
+use sea_orm::{prelude::Date, DatabaseConnection, DbBackend, FromQueryResult, Statement};
+use serde::Serialize;
+use std::collections::HashMap;
+use tracing::error;
+
+use crate::{
+    analytics::TimeWindow,
+    db::{settings::UserSettings, DBError},
+};
+
+/// The number of plays on a single day
+#[derive(Serialize, FromQueryResult, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DayCount {
+    pub day: Date,
+    pub play_count: i64,
+}
+
+/// The number of plays in an hour of a day of the week
+#[derive(Serialize, FromQueryResult, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockCount {
+    /// The day of the week, from 0 for the first day of the user's week to 6 for the last
+    pub weekday: i32,
+    /// The hour of the day, from 0 to 23
+    pub hour: i32,
+    pub play_count: i64,
+}
+
+/// Count a user's plays on each day in a window, ordered by day
+/// Plays are put on the day they were played in the timezone, days without any plays are left out
+pub async fn daily_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    tz: Tz,
+) -> Result<Vec<DayCount>, DBError> {
+    // Plays are stored as naive UTC, so they're marked as UTC before converting to the timezone
+    DayCount::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT CAST(played_at AT TIME ZONE 'UTC' AT TIME ZONE $4 AS DATE) AS day,
+            COUNT(*) AS play_count
+        FROM play_log
+        WHERE user_id = $1 AND played_at >= $2 AND played_at < $3
+        GROUP BY day
+        ORDER BY day"#,
+        [
+            user_id.into(),
+            window.from.into(),
+            window.to.into(),
+            tz.name().into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error counting daily plays: {:?}", sea_err);
+        DBError
+    })
+}
+
+/// Count a user's plays in each hour of each day of the week in a window, in the user's timezone
+/// Every hour of the week is included, ordered from midnight on the first day of the user's week
+pub async fn clock_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    settings: &UserSettings,
+) -> Result<Vec<ClockCount>, DBError> {
+    // ISODOW goes from 1 for Monday to 7 for Sunday, so it's shifted to start from the week start
+    let counts = ClockCount::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT MOD(CAST(EXTRACT(ISODOW FROM local_played_at) AS INTEGER) + 6 - $5, 7) AS weekday,
+            CAST(EXTRACT(HOUR FROM local_played_at) AS INTEGER) AS hour,
+            COUNT(*) AS play_count
+        FROM (
+            SELECT played_at AT TIME ZONE 'UTC' AT TIME ZONE $4 AS local_played_at
+            FROM play_log
+            WHERE user_id = $1 AND played_at >= $2 AND played_at < $3
+        ) AS plays
+        GROUP BY weekday, hour"#,
+        [
+            user_id.into(),
+            window.from.into(),
+            window.to.into(),
+            settings.timezone.name().into(),
+            (settings.week_start.num_days_from_monday() as i32).into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error counting plays by hour: {:?}", sea_err);
+        DBError
+    })?;
+    let counts: HashMap<(i32, i32), i64> = counts
+        .into_iter()
+        .map(|count| ((count.weekday, count.hour), count.play_count))
+        .collect();
+    Ok((0..7)
+        .flat_map(|weekday| (0..24).map(move |hour| (weekday, hour)))
+        .map(|(weekday, hour)| ClockCount {
+            weekday,
+            hour,
+            play_count: counts.get(&(weekday, hour)).copied().unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Fill in the days without any plays between two days, inclusive
+pub fn fill_days(counts: &[DayCount], first: Date, last: Date) -> Vec<DayCount> {
+    let counts: HashMap<Date, i64> = counts
+        .iter()
+        .map(|count| (count.day, count.play_count))
+        .collect();
+    first
+        .iter_days()
+        .take_while(|day| *day <= last)
+        .map(|day| DayCount {
+            day,
+            play_count: counts.get(&day).copied().unwrap_or_default(),
+        })
+        .collect()
+}
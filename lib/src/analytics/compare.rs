@@ -0,0 +1,149 @@
+use sea_orm::DatabaseConnection;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::{
+    analytics::{
+        top::{self, TopItem, TopMetric},
+        TimeWindow,
+    },
+    db::{
+        catalog::{ArtistSummary, TrackDetails},
+        DBError,
+    },
+};
+
+/// How much artists count towards the compatibility score, the rest is from tracks
+const ARTIST_WEIGHT: f64 = 0.6;
+
+/// Anything in the catalog that can be compared by its ID
+pub trait CatalogItem {
+    fn catalog_id(&self) -> i32;
+}
+
+impl CatalogItem for ArtistSummary {
+    fn catalog_id(&self) -> i32 {
+        self.id
+    }
+}
+
+impl CatalogItem for TrackDetails {
+    fn catalog_id(&self) -> i32 {
+        self.track.id
+    }
+}
+
+/// Something both users have in their top lists
+#[derive(Serialize, Debug)]
+pub struct Shared<T> {
+    #[serde(flatten)]
+    pub item: T,
+    pub play_count: u64,
+    pub other_play_count: u64,
+}
+
+/// How the top lists of two users overlap
+#[derive(Serialize, Debug)]
+pub struct Overlap<T> {
+    /// The share of all the items in the lists that are in both lists, between 0 and 1
+    pub overlap: f64,
+    /// The cosine similarity of the play counts, between 0 and 1
+    pub similarity: f64,
+    /// The items in both lists, ordered by the least plays either user gave them
+    pub shared: Vec<Shared<T>>,
+    /// The items only in the list of the user
+    pub unique: Vec<TopItem<T>>,
+    /// The items only in the list of the other user
+    pub other_unique: Vec<TopItem<T>>,
+}
+
+/// Compare the top lists of two users
+pub fn overlap<T: CatalogItem>(items: Vec<TopItem<T>>, other_items: Vec<TopItem<T>>) -> Overlap<T> {
+    let other_counts: HashMap<i32, u64> = other_items
+        .iter()
+        .map(|other| (other.item.catalog_id(), other.play_count))
+        .collect();
+    let counts: HashMap<i32, u64> = items
+        .iter()
+        .map(|item| (item.item.catalog_id(), item.play_count))
+        .collect();
+    // Only the shared items add to the dot product, the rest multiply by zero
+    let dot: f64 = counts
+        .iter()
+        .filter_map(|(id, count)| Some(*count as f64 * *other_counts.get(id)? as f64))
+        .sum();
+    let norm = |counts: &HashMap<i32, u64>| {
+        counts
+            .values()
+            .map(|count| (*count as f64).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    };
+    let similarity = match norm(&counts) * norm(&other_counts) {
+        0.0 => 0.0,
+        norms => dot / norms,
+    };
+    let (shared, unique): (Vec<_>, Vec<_>) = items
+        .into_iter()
+        .partition(|item| other_counts.contains_key(&item.item.catalog_id()));
+    let other_unique: Vec<TopItem<T>> = other_items
+        .into_iter()
+        .filter(|other| !counts.contains_key(&other.item.catalog_id()))
+        .collect();
+    let mut shared: Vec<Shared<T>> = shared
+        .into_iter()
+        .map(|item| Shared {
+            other_play_count: other_counts[&item.item.catalog_id()],
+            play_count: item.play_count,
+            item: item.item,
+        })
+        .collect();
+    shared.sort_by_key(|shared| std::cmp::Reverse(shared.play_count.min(shared.other_play_count)));
+    let total = shared.len() + unique.len() + other_unique.len();
+    Overlap {
+        overlap: match total {
+            0 => 0.0,
+            total => shared.len() as f64 / total as f64,
+        },
+        similarity,
+        shared,
+        unique,
+        other_unique,
+    }
+}
+
+/// How the listening of two users compares
+#[derive(Serialize, Debug)]
+pub struct Comparison {
+    /// How alike the users' taste is, from 0 to 100
+    pub compatibility: f64,
+    pub artists: Overlap<ArtistSummary>,
+    pub tracks: Overlap<TrackDetails>,
+}
+
+/// Compare the most played artists and tracks of two users in a window
+/// The limit is how many of each user's most played items are compared
+pub async fn compare_users(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    other_user_id: &str,
+    window: TimeWindow,
+    limit: u64,
+) -> Result<Comparison, DBError> {
+    let metric = TopMetric::Plays;
+    let (artists, other_artists, tracks, other_tracks) = tokio::try_join!(
+        top::top_artists(conn, user_id, window, metric, limit),
+        top::top_artists(conn, other_user_id, window, metric, limit),
+        top::top_tracks(conn, user_id, window, metric, limit),
+        top::top_tracks(conn, other_user_id, window, metric, limit),
+    )?;
+    let artists = overlap(artists, other_artists);
+    let tracks = overlap(tracks, other_tracks);
+    let compatibility =
+        100.0 * (ARTIST_WEIGHT * artists.similarity + (1.0 - ARTIST_WEIGHT) * tracks.similarity);
+    Ok(Comparison {
+        compatibility,
+        artists,
+        tracks,
+    })
+}
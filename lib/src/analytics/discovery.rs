@@ -0,0 +1,322 @@
+use chrono_tz::Tz;
+use sea_orm::{
+    prelude::{Date, DateTime},
+    DatabaseConnection, DbBackend, FromQueryResult, Statement,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use tracing::error;
+
+use crate::{
+    analytics::{top::Grouping, TimeWindow},
+    db::{
+        catalog::{self, ArtistSummary, TrackDetails},
+        DBError,
+    },
+};
+
+/// Something the user heard for the first time
+#[derive(Serialize, Debug)]
+pub struct Discovery<T> {
+    #[serde(flatten)]
+    pub item: T,
+    pub first_played_at: DateTime,
+    /// The number of plays within the window that was looked at
+    pub play_count: u64,
+}
+
+#[derive(FromQueryResult, Debug)]
+struct FirstPlay {
+    id: i32,
+    first_played_at: DateTime,
+    play_count: i64,
+}
+
+/// Find the first ever play of everything in a grouping that was first played within the discovered
+/// window, along with how much it was played within the counted window
+/// Ordered by the play count, then by when they were first played
+async fn first_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    grouping: Grouping,
+    discovered: TimeWindow,
+    counted: TimeWindow,
+    limit: Option<u64>,
+) -> Result<Vec<FirstPlay>, DBError> {
+    // A track can be on multiple albums with multiple artists, so the plays are made distinct within each group
+    let sql = format!(
+        r#"SELECT group_id AS id, first_played_at, play_count
+        FROM (
+            SELECT group_id,
+                MIN(played_at) AS first_played_at,
+                COUNT(*) FILTER (WHERE played_at >= $4 AND played_at < $5) AS play_count
+            FROM (
+                SELECT DISTINCT play_log.id, play_log.played_at, {group_by} AS group_id
+                FROM play_log
+                INNER JOIN album_track ON album_track.track_id = play_log.track_id
+                INNER JOIN album_artist ON album_artist.album_id = album_track.album_id
+                WHERE play_log.user_id = $1
+            ) AS plays
+            GROUP BY group_id
+        ) AS firsts
+        WHERE first_played_at >= $2 AND first_played_at < $3
+        ORDER BY play_count DESC, first_played_at, group_id
+        LIMIT $6"#,
+        group_by = grouping.column(),
+    );
+    FirstPlay::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        sql,
+        [
+            user_id.into(),
+            discovered.from.into(),
+            discovered.to.into(),
+            counted.from.into(),
+            counted.to.into(),
+            // Postgres doesn't limit the rows if the limit is null
+            limit.map(|limit| limit as i64).into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error finding first plays by {:?}: {:?}", grouping, sea_err);
+        DBError
+    })
+}
+
+/// Attach the looked up items to their first plays, in the order of the first plays
+fn with_items<T: Clone>(firsts: Vec<FirstPlay>, items: HashMap<i32, T>) -> Vec<Discovery<T>> {
+    firsts
+        .into_iter()
+        .filter_map(|first| {
+            Some(Discovery {
+                item: items.get(&first.id)?.clone(),
+                first_played_at: first.first_played_at,
+                play_count: first.play_count as u64,
+            })
+        })
+        .collect()
+}
+
+/// Find the artists a user played for the first time ever within a window,
+/// ordered by how much they were played within the window
+pub async fn new_artists(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+) -> Result<Vec<Discovery<ArtistSummary>>, DBError> {
+    let firsts = first_plays(conn, user_id, Grouping::Artist, window, window, None).await?;
+    let ids = firsts.iter().map(|first| first.id).collect();
+    let artists = catalog::get_artists_by_ids(conn, ids).await?;
+    Ok(with_items(firsts, artists))
+}
+
+/// Find the tracks a user played for the first time ever within a window,
+/// ordered by how much they were played within the window
+pub async fn new_tracks(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+) -> Result<Vec<Discovery<TrackDetails>>, DBError> {
+    let firsts = first_plays(conn, user_id, Grouping::Track, window, window, None).await?;
+    let ids = firsts.iter().map(|first| first.id).collect();
+    let tracks = catalog::get_track_details(conn, ids).await?;
+    Ok(with_items(firsts, tracks))
+}
+
+/// Get when a user first heard each of their most played artists in a window, ordered by when they
+/// were first heard
+pub async fn favorite_artists_timeline(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    limit: u64,
+) -> Result<Vec<Discovery<ArtistSummary>>, DBError> {
+    let mut firsts = first_plays(
+        conn,
+        user_id,
+        Grouping::Artist,
+        TimeWindow::all(),
+        window,
+        Some(limit),
+    )
+    .await?;
+    firsts.retain(|first| first.play_count > 0);
+    firsts.sort_by_key(|first| first.first_played_at);
+    let ids = firsts.iter().map(|first| first.id).collect();
+    let artists = catalog::get_artists_by_ids(conn, ids).await?;
+    Ok(with_items(firsts, artists))
+}
+
+/// Get when a user first heard each of their most played tracks in a window, ordered by when they
+/// were first heard
+pub async fn favorite_tracks_timeline(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    limit: u64,
+) -> Result<Vec<Discovery<TrackDetails>>, DBError> {
+    let mut firsts = first_plays(
+        conn,
+        user_id,
+        Grouping::Track,
+        TimeWindow::all(),
+        window,
+        Some(limit),
+    )
+    .await?;
+    firsts.retain(|first| first.play_count > 0);
+    firsts.sort_by_key(|first| first.first_played_at);
+    let ids = firsts.iter().map(|first| first.id).collect();
+    let tracks = catalog::get_track_details(conn, ids).await?;
+    Ok(with_items(firsts, tracks))
+}
+
+/// The number of artists and tracks discovered in a month
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonthDiscoveries {
+    /// The first day of the month
+    pub month: Date,
+    pub new_artists: i64,
+    pub new_tracks: i64,
+}
+
+#[derive(FromQueryResult, Debug)]
+struct MonthCount {
+    month: Date,
+    discoveries: i64,
+}
+
+/// Count the first plays in a grouping within a window for each month in the timezone
+async fn monthly_first_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    grouping: Grouping,
+    window: TimeWindow,
+    tz: Tz,
+) -> Result<Vec<MonthCount>, DBError> {
+    let sql = format!(
+        r#"SELECT CAST(DATE_TRUNC('month', first_played_at AT TIME ZONE 'UTC' AT TIME ZONE $4) AS DATE)
+                AS month,
+            COUNT(*) AS discoveries
+        FROM (
+            SELECT {group_by} AS group_id, MIN(play_log.played_at) AS first_played_at
+            FROM play_log
+            INNER JOIN album_track ON album_track.track_id = play_log.track_id
+            INNER JOIN album_artist ON album_artist.album_id = album_track.album_id
+            WHERE play_log.user_id = $1
+            GROUP BY group_id
+        ) AS firsts
+        WHERE first_played_at >= $2 AND first_played_at < $3
+        GROUP BY month
+        ORDER BY month"#,
+        group_by = grouping.column(),
+    );
+    MonthCount::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        sql,
+        [
+            user_id.into(),
+            window.from.into(),
+            window.to.into(),
+            tz.name().into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!(
+            "Error counting discoveries by {:?}: {:?}",
+            grouping, sea_err
+        );
+        DBError
+    })
+}
+
+/// Count the artists and tracks a user discovered in each month of a window, in the timezone
+/// Months without any discoveries are left out
+pub async fn discoveries_per_month(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    tz: Tz,
+) -> Result<Vec<MonthDiscoveries>, DBError> {
+    let (artists, tracks) = tokio::try_join!(
+        monthly_first_plays(conn, user_id, Grouping::Artist, window, tz),
+        monthly_first_plays(conn, user_id, Grouping::Track, window, tz),
+    )?;
+    let mut months: BTreeMap<Date, MonthDiscoveries> = BTreeMap::new();
+    let empty = |month| MonthDiscoveries {
+        month,
+        new_artists: 0,
+        new_tracks: 0,
+    };
+    for count in artists {
+        months
+            .entry(count.month)
+            .or_insert_with(|| empty(count.month))
+            .new_artists = count.discoveries;
+    }
+    for count in tracks {
+        months
+            .entry(count.month)
+            .or_insert_with(|| empty(count.month))
+            .new_tracks = count.discoveries;
+    }
+    Ok(months.into_values().collect())
+}
+
+/// How much of a user's listening was to tracks they had never heard before
+#[derive(Serialize, Debug)]
+pub struct DiscoveryRatio {
+    pub total_plays: i64,
+    /// Plays that were the first ever play of their track
+    pub new_plays: i64,
+    pub repeat_plays: i64,
+    /// The share of plays that were new, between 0 and 1
+    pub ratio: f64,
+}
+
+#[derive(FromQueryResult, Debug)]
+struct PlayCounts {
+    total_plays: i64,
+    new_plays: i64,
+}
+
+/// Compare the new plays against the repeat plays of a user in a window
+pub async fn discovery_ratio(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+) -> Result<DiscoveryRatio, DBError> {
+    // The first play of a track is found over all the plays, before limiting them to the window
+    let counts = PlayCounts::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT COUNT(*) AS total_plays, COUNT(*) FILTER (WHERE is_first) AS new_plays
+        FROM (
+            SELECT played_at,
+                ROW_NUMBER() OVER (PARTITION BY track_id ORDER BY played_at, id) = 1 AS is_first
+            FROM play_log
+            WHERE user_id = $1
+        ) AS plays
+        WHERE played_at >= $2 AND played_at < $3"#,
+        [user_id.into(), window.from.into(), window.to.into()],
+    ))
+    .one(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error counting new plays: {:?}", sea_err);
+        DBError
+    })?
+    .ok_or(DBError)?;
+    Ok(DiscoveryRatio {
+        total_plays: counts.total_plays,
+        new_plays: counts.new_plays,
+        repeat_plays: counts.total_plays - counts.new_plays,
+        ratio: match counts.total_plays {
+            0 => 0.0,
+            total => counts.new_plays as f64 / total as f64,
+        },
+    })
+}
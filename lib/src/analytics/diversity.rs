@@ -0,0 +1,273 @@
+use chrono_tz::Tz;
+use sea_orm::{prelude::Date, DatabaseConnection, DbBackend, FromQueryResult, Statement};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use tracing::error;
+
+use crate::{
+    analytics::{top, TimeWindow},
+    db::DBError,
+};
+
+/// The number of most played artists the share of listening is measured for
+pub const TOP_ARTISTS_SHARE_SIZE: usize = 10;
+
+/// How spread out listening is over artists
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Diversity {
+    pub unique_artists: u64,
+    /// The Shannon entropy of the plays over artists, in bits, higher is more diverse
+    pub entropy: f64,
+    /// The entropy compared to the highest it could be for the number of artists, between 0 and 1
+    pub evenness: f64,
+    /// The chance two random plays are by different artists, between 0 and 1
+    pub simpson_index: f64,
+    /// The share of plays by the most played artists, between 0 and 1
+    pub top_artists_share: f64,
+}
+
+/// Measure the diversity of the play counts of each artist
+/// A play with multiple artists counts once for each of them
+pub fn diversity(play_counts: &[i64]) -> Diversity {
+    let total: i64 = play_counts.iter().sum();
+    let unique_artists = play_counts.iter().filter(|count| **count > 0).count();
+    if total == 0 {
+        return Diversity {
+            unique_artists: 0,
+            entropy: 0.0,
+            evenness: 0.0,
+            simpson_index: 0.0,
+            top_artists_share: 0.0,
+        };
+    }
+    let shares: Vec<f64> = play_counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| *count as f64 / total as f64)
+        .collect();
+    let entropy = -shares.iter().map(|share| share * share.log2()).sum::<f64>();
+    let mut counts = play_counts.to_vec();
+    counts.sort_unstable_by(|a, b| b.cmp(a));
+    let top_plays: i64 = counts.iter().take(TOP_ARTISTS_SHARE_SIZE).sum();
+    Diversity {
+        unique_artists: unique_artists as u64,
+        entropy,
+        evenness: match unique_artists {
+            0 | 1 => 0.0,
+            unique => entropy / (unique as f64).log2(),
+        },
+        simpson_index: 1.0 - shares.iter().map(|share| share * share).sum::<f64>(),
+        top_artists_share: top_plays as f64 / total as f64,
+    }
+}
+
+/// The diversity of a month of listening
+#[derive(Serialize, Debug)]
+pub struct MonthDiversity {
+    /// The first day of the month
+    pub month: Date,
+    pub play_count: i64,
+    #[serde(flatten)]
+    pub diversity: Diversity,
+}
+
+#[derive(FromQueryResult, Debug)]
+struct MonthCount {
+    month: Date,
+    play_count: i64,
+}
+
+#[derive(FromQueryResult, Debug)]
+struct MonthGroupCount {
+    month: Date,
+    name: String,
+    play_count: i64,
+}
+
+/// Count a user's plays in each month of a window, in the timezone
+async fn monthly_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    tz: Tz,
+) -> Result<Vec<MonthCount>, DBError> {
+    MonthCount::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT CAST(DATE_TRUNC('month', played_at AT TIME ZONE 'UTC' AT TIME ZONE $4) AS DATE)
+                AS month,
+            COUNT(*) AS play_count
+        FROM play_log
+        WHERE user_id = $1 AND played_at >= $2 AND played_at < $3
+        GROUP BY month
+        ORDER BY month"#,
+        [
+            user_id.into(),
+            window.from.into(),
+            window.to.into(),
+            tz.name().into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error counting monthly plays: {:?}", sea_err);
+        DBError
+    })
+}
+
+/// What the monthly plays are broken down by
+#[derive(Debug, Clone, Copy)]
+enum Breakdown {
+    /// Artists are named by their ID, since only the counts matter for diversity
+    Artist,
+    Genre,
+}
+
+/// Count a user's plays in each month of a window, in the timezone, broken down by artist or genre
+async fn monthly_group_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    tz: Tz,
+    breakdown: Breakdown,
+) -> Result<Vec<MonthGroupCount>, DBError> {
+    let (name, genre_joins) = match breakdown {
+        Breakdown::Artist => ("CAST(album_artist.artist_id AS TEXT)", ""),
+        Breakdown::Genre => (
+            "genre.name",
+            r#"INNER JOIN artist_genre ON artist_genre.artist_id = album_artist.artist_id
+            INNER JOIN genre ON genre.id = artist_genre.genre_id"#,
+        ),
+    };
+    let sql = format!(
+        r#"SELECT month, name, COUNT(*) AS play_count
+        FROM (
+            SELECT DISTINCT play_log.id,
+                CAST(DATE_TRUNC('month', play_log.played_at AT TIME ZONE 'UTC' AT TIME ZONE $4) AS DATE)
+                    AS month,
+                {name} AS name
+            FROM play_log
+            INNER JOIN album_track ON album_track.track_id = play_log.track_id
+            INNER JOIN album_artist ON album_artist.album_id = album_track.album_id
+            {genre_joins}
+            WHERE play_log.user_id = $1 AND play_log.played_at >= $2 AND play_log.played_at < $3
+        ) AS plays
+        GROUP BY month, name
+        ORDER BY month, play_count DESC, name"#,
+    );
+    MonthGroupCount::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        sql,
+        [
+            user_id.into(),
+            window.from.into(),
+            window.to.into(),
+            tz.name().into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!(
+            "Error counting monthly plays by {:?}: {:?}",
+            breakdown, sea_err
+        );
+        DBError
+    })
+}
+
+/// Measure how diverse a user's listening was in each month of a window, in the timezone
+/// Months without any plays are left out
+pub async fn monthly_diversity(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    tz: Tz,
+) -> Result<Vec<MonthDiversity>, DBError> {
+    let (months, artist_counts) = tokio::try_join!(
+        monthly_plays(conn, user_id, window, tz),
+        monthly_group_plays(conn, user_id, window, tz, Breakdown::Artist),
+    )?;
+    let mut artist_months: BTreeMap<Date, Vec<i64>> = BTreeMap::new();
+    for count in artist_counts {
+        artist_months
+            .entry(count.month)
+            .or_default()
+            .push(count.play_count);
+    }
+    Ok(months
+        .into_iter()
+        .map(|month| MonthDiversity {
+            month: month.month,
+            play_count: month.play_count,
+            diversity: diversity(
+                artist_months
+                    .get(&month.month)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default(),
+            ),
+        })
+        .collect())
+}
+
+/// How much of the listening was to a genre
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GenreShare {
+    pub name: String,
+    pub play_count: i64,
+    /// The share of the genre plays, between 0 and 1
+    pub share: f64,
+}
+
+/// The genres listened to in a month
+#[derive(Serialize, Debug)]
+pub struct MonthGenres {
+    /// The first day of the month
+    pub month: Date,
+    pub genres: Vec<GenreShare>,
+    /// The share of the genre plays that went to genres not in the list
+    pub other_share: f64,
+}
+
+/// Get the share of each of a user's most played genres in each month of a window, in the timezone
+/// A play counts once for every genre of its artists, so the shares are of all the genre plays
+/// Months without any genre plays are left out
+pub async fn monthly_genre_shares(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    tz: Tz,
+    limit: u64,
+) -> Result<Vec<MonthGenres>, DBError> {
+    let (top_genres, genre_counts) = tokio::try_join!(
+        top::top_genres(conn, user_id, window, limit),
+        monthly_group_plays(conn, user_id, window, tz, Breakdown::Genre),
+    )?;
+    let top_genres: HashSet<String> = top_genres.into_iter().map(|genre| genre.name).collect();
+    let mut months: BTreeMap<Date, Vec<MonthGroupCount>> = BTreeMap::new();
+    for count in genre_counts {
+        months.entry(count.month).or_default().push(count);
+    }
+    Ok(months
+        .into_iter()
+        .map(|(month, counts)| {
+            let total: i64 = counts.iter().map(|count| count.play_count).sum();
+            let share = |play_count: i64| play_count as f64 / total as f64;
+            let genres: Vec<GenreShare> = counts
+                .into_iter()
+                .filter(|count| top_genres.contains(&count.name))
+                .map(|count| GenreShare {
+                    share: share(count.play_count),
+                    name: count.name,
+                    play_count: count.play_count,
+                })
+                .collect();
+            let listed: i64 = genres.iter().map(|genre| genre.play_count).sum();
+            MonthGenres {
+                month,
+                genres,
+                other_share: share(total - listed),
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,155 @@
+use chrono::Months;
+use chrono_tz::Tz;
+use sea_orm::{
+    prelude::{Date, DateTime},
+    sqlx::types::chrono::Utc,
+    DatabaseConnection, DbBackend, FromQueryResult, Statement,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use tracing::error;
+
+use crate::{
+    analytics::top::Grouping,
+    db::{
+        catalog::{self, AlbumSummary, TrackDetails},
+        DBError,
+    },
+};
+
+/// Something that used to be played a lot, but hasn't been played in a while
+#[derive(Serialize, Debug)]
+pub struct Forgotten<T> {
+    #[serde(flatten)]
+    pub item: T,
+    /// All the plays it ever had
+    pub play_count: i64,
+    /// The month it was played the most in, and how many plays it had in that month
+    pub peak_month: Date,
+    pub peak_month_plays: i64,
+    pub last_played_at: DateTime,
+}
+
+pub type ForgottenTrack = Forgotten<TrackDetails>;
+pub type ForgottenAlbum = Forgotten<AlbumSummary>;
+
+#[derive(FromQueryResult, Debug)]
+struct Faded {
+    id: i32,
+    play_count: i64,
+    peak_month: Date,
+    peak_month_plays: i64,
+    last_played_at: DateTime,
+}
+
+/// Get the start of the period a user has to have not played something in for it to be forgotten
+pub fn forgotten_since(months: u32) -> DateTime {
+    let now = Utc::now().naive_utc();
+    now.checked_sub_months(Months::new(months)).unwrap_or(now)
+}
+
+/// Find everything in a grouping played at least a number of times, but not since a time,
+/// ranked by the most plays it had in a month, in the timezone
+async fn faded(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    grouping: Grouping,
+    since: DateTime,
+    min_plays: u64,
+    tz: Tz,
+    limit: u64,
+) -> Result<Vec<Faded>, DBError> {
+    // A track can be on multiple albums with multiple artists, so the plays are made distinct within each group
+    let sql = format!(
+        r#"SELECT group_id AS id,
+            CAST(SUM(month_plays) AS BIGINT) AS play_count,
+            (ARRAY_AGG(month ORDER BY month_plays DESC, month))[1] AS peak_month,
+            MAX(month_plays) AS peak_month_plays,
+            MAX(last_played_at) AS last_played_at
+        FROM (
+            SELECT group_id,
+                CAST(DATE_TRUNC('month', played_at AT TIME ZONE 'UTC' AT TIME ZONE $3) AS DATE)
+                    AS month,
+                COUNT(*) AS month_plays,
+                MAX(played_at) AS last_played_at
+            FROM (
+                SELECT DISTINCT play_log.id, play_log.played_at, {group_by} AS group_id
+                FROM play_log
+                INNER JOIN album_track ON album_track.track_id = play_log.track_id
+                INNER JOIN album_artist ON album_artist.album_id = album_track.album_id
+                WHERE play_log.user_id = $1
+            ) AS plays
+            GROUP BY group_id, month
+        ) AS months
+        GROUP BY group_id
+        HAVING MAX(last_played_at) < $2 AND SUM(month_plays) >= $4
+        ORDER BY peak_month_plays DESC, play_count DESC, group_id
+        LIMIT $5"#,
+        group_by = grouping.column(),
+    );
+    Faded::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        sql,
+        [
+            user_id.into(),
+            since.into(),
+            tz.name().into(),
+            (min_plays as i64).into(),
+            (limit as i64).into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error finding forgotten {:?}: {:?}", grouping, sea_err);
+        DBError
+    })
+}
+
+/// Attach the looked up items to what faded, in the order of what faded
+fn with_items<T: Clone>(faded: Vec<Faded>, items: HashMap<i32, T>) -> Vec<Forgotten<T>> {
+    faded
+        .into_iter()
+        .filter_map(|faded| {
+            Some(Forgotten {
+                item: items.get(&faded.id)?.clone(),
+                play_count: faded.play_count,
+                peak_month: faded.peak_month,
+                peak_month_plays: faded.peak_month_plays,
+                last_played_at: faded.last_played_at,
+            })
+        })
+        .collect()
+}
+
+/// Get the tracks a user played at least a number of times but not since a time,
+/// ranked by how much they were played in their peak month
+pub async fn forgotten_tracks(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    since: DateTime,
+    min_plays: u64,
+    tz: Tz,
+    limit: u64,
+) -> Result<Vec<ForgottenTrack>, DBError> {
+    let faded = faded(conn, user_id, Grouping::Track, since, min_plays, tz, limit).await?;
+    let ids = faded.iter().map(|faded| faded.id).collect();
+    let tracks = catalog::get_track_details(conn, ids).await?;
+    Ok(with_items(faded, tracks))
+}
+
+/// Get the albums a user played at least a number of times but not since a time,
+/// ranked by how much they were played in their peak month
+pub async fn forgotten_albums(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    since: DateTime,
+    min_plays: u64,
+    tz: Tz,
+    limit: u64,
+) -> Result<Vec<ForgottenAlbum>, DBError> {
+    let faded = faded(conn, user_id, Grouping::Album, since, min_plays, tz, limit).await?;
+    let ids = faded.iter().map(|faded| faded.id).collect();
+    let albums = catalog::get_albums_by_ids(conn, ids).await?;
+    Ok(with_items(faded, albums))
+}
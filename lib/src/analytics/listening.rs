@@ -0,0 +1,150 @@
+use chrono::Duration;
+use chrono_tz::Tz;
+use sea_orm::{
+    prelude::{Date, DateTime},
+    DatabaseConnection, DbBackend, FromQueryResult, Statement,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tracing::error;
+
+use crate::{analytics::TimeWindow, db::DBError};
+
+/// The default gap between plays that starts a new listening session
+pub const DEFAULT_SESSION_GAP_MINUTES: i64 = 30;
+
+/// A single play, spanning from when it started to when it was played at
+#[derive(FromQueryResult, Debug, Clone, Copy)]
+pub struct TimedPlay {
+    pub played_at: DateTime,
+    /// Missing for tracks that were saved before durations were collected
+    pub duration_ms: Option<i32>,
+}
+
+impl TimedPlay {
+    /// Spotify records a play when it finishes, so it started its duration before that
+    fn started_at(&self) -> DateTime {
+        self.played_at - Duration::milliseconds(self.duration_ms.unwrap_or_default() as i64)
+    }
+}
+
+/// A stretch of listening without a break longer than the session gap
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListeningSession {
+    pub start: DateTime,
+    pub end: DateTime,
+    pub play_count: u64,
+    pub length_ms: i64,
+}
+
+/// The number of listening sessions started on a day
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DaySessions {
+    pub day: Date,
+    pub session_count: u64,
+}
+
+/// How a user's listening splits into sessions
+#[derive(Serialize, Debug)]
+pub struct SessionStats {
+    pub session_count: u64,
+    pub average_length_ms: i64,
+    pub longest: Option<ListeningSession>,
+    /// The average number of sessions on the days with any listening
+    pub average_per_day: f64,
+    pub per_day: Vec<DaySessions>,
+}
+
+/// Split plays into listening sessions, starting a new session whenever the time between
+/// the end of a play and the start of the next is longer than the gap
+/// The plays must be sorted by when they were played
+pub fn detect_sessions(plays: &[TimedPlay], gap: Duration) -> Vec<ListeningSession> {
+    let mut sessions: Vec<ListeningSession> = vec![];
+    for play in plays {
+        let started_at = play.started_at();
+        match sessions.last_mut() {
+            Some(session) if started_at - session.end <= gap => {
+                session.end = session.end.max(play.played_at);
+                session.play_count += 1;
+            }
+            _ => sessions.push(ListeningSession {
+                start: started_at,
+                end: play.played_at,
+                play_count: 1,
+                length_ms: 0,
+            }),
+        }
+    }
+    for session in sessions.iter_mut() {
+        session.length_ms = (session.end - session.start).num_milliseconds();
+    }
+    sessions
+}
+
+/// Summarize listening sessions, counting each session on the day it started in the timezone
+pub fn session_stats(sessions: &[ListeningSession], tz: Tz) -> SessionStats {
+    let session_count = sessions.len() as u64;
+    let total_ms: i64 = sessions.iter().map(|session| session.length_ms).sum();
+    // Prefer the earliest session if there is a tie
+    let longest = sessions
+        .iter()
+        .rev()
+        .max_by_key(|session| session.length_ms)
+        .copied();
+    let mut days: BTreeMap<Date, u64> = BTreeMap::new();
+    for session in sessions {
+        *days
+            .entry(session.start.and_utc().with_timezone(&tz).date_naive())
+            .or_default() += 1;
+    }
+    let per_day: Vec<DaySessions> = days
+        .into_iter()
+        .map(|(day, session_count)| DaySessions { day, session_count })
+        .collect();
+    SessionStats {
+        session_count,
+        average_length_ms: total_ms
+            .checked_div(session_count as i64)
+            .unwrap_or_default(),
+        longest,
+        average_per_day: match per_day.len() {
+            0 => 0.0,
+            days => session_count as f64 / days as f64,
+        },
+        per_day,
+    }
+}
+
+/// Get a user's plays in a window with their durations, ordered by when they were played
+pub async fn timed_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+) -> Result<Vec<TimedPlay>, DBError> {
+    TimedPlay::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT play_log.played_at, track.duration_ms
+        FROM play_log
+        INNER JOIN track ON track.id = play_log.track_id
+        WHERE play_log.user_id = $1 AND play_log.played_at >= $2 AND play_log.played_at < $3
+        ORDER BY play_log.played_at"#,
+        [user_id.into(), window.from.into(), window.to.into()],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error looking up timed plays: {:?}", sea_err);
+        DBError
+    })
+}
+
+/// Get a user's listening sessions in a window
+pub async fn listening_sessions(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    gap: Duration,
+) -> Result<Vec<ListeningSession>, DBError> {
+    let plays = timed_plays(conn, user_id, window).await?;
+    Ok(detect_sessions(&plays, gap))
+}
@@ -0,0 +1,93 @@
+pub mod calendar;
+pub mod compare;
+pub mod discovery;
+pub mod diversity;
+pub mod forgotten;
+pub mod listening;
+pub mod report;
+pub mod skips;
+pub mod streaks;
+pub mod top;
+
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use sea_orm::prelude::DateTime;
+use serde::{Deserialize, Serialize};
+
+/// A named period of time to analyze, ending now
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Week,
+    #[default]
+    Month,
+    Year,
+    /// Everything that was ever played
+    All,
+    /// A window with an explicit start and end
+    Custom,
+}
+
+/// The window of time to analyze plays in, from is inclusive and to is exclusive
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct TimeWindow {
+    pub from: DateTime,
+    pub to: DateTime,
+}
+
+impl TimeWindow {
+    /// Get the window for a period, ending now
+    /// The from and to are required for a custom period, and ignored otherwise
+    pub fn from_period(
+        period: Period,
+        from: Option<DateTime>,
+        to: Option<DateTime>,
+    ) -> Result<Self, String> {
+        let now = Utc::now().naive_utc();
+        let from = match period {
+            Period::Week => now - Duration::weeks(1),
+            Period::Month => now - Duration::days(30),
+            Period::Year => now - Duration::days(365),
+            Period::All => return Ok(Self::all()),
+            Period::Custom => {
+                let (Some(from), Some(to)) = (from, to) else {
+                    return Err("A custom period requires a from and to".to_string());
+                };
+                if from >= to {
+                    return Err("The from of a custom period must be before the to".to_string());
+                }
+                return Ok(Self { from, to });
+            }
+        };
+        Ok(Self { from, to: now })
+    }
+
+    /// Get the window of everything that was ever played, up to now
+    pub fn all() -> Self {
+        // Nothing can have been played before the epoch
+        Self {
+            from: DateTime::default(),
+            to: Utc::now().naive_utc(),
+        }
+    }
+
+    /// Get the window for a calendar year in a timezone, returning nothing if the year is out of range
+    pub fn for_year(year: i32, tz: Tz) -> Option<Self> {
+        Some(Self {
+            from: local_midnight(NaiveDate::from_ymd_opt(year, 1, 1)?, tz)?,
+            to: local_midnight(NaiveDate::from_ymd_opt(year + 1, 1, 1)?, tz)?,
+        })
+    }
+}
+
+/// Get the start of a day in a timezone as naive UTC, the way plays are stored
+fn local_midnight(day: NaiveDate, tz: Tz) -> Option<DateTime> {
+    let midnight = day.and_hms_opt(0, 0, 0)?;
+    // A day can start in a gap when the clocks change, so fall back to the earliest hour that exists
+    (0..3)
+        .find_map(|hour| {
+            tz.from_local_datetime(&(midnight + Duration::hours(hour)))
+                .earliest()
+        })
+        .map(|local| local.naive_utc())
+}
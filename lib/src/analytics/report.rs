@@ -0,0 +1,152 @@
+use entity::report;
+use sea_orm::{
+    prelude::DateTime, sqlx::types::chrono::Utc, DatabaseConnection, DbBackend, FromQueryResult,
+    Statement,
+};
+use serde::Serialize;
+use tracing::error;
+
+use crate::{
+    analytics::{
+        calendar::{self, DayCount},
+        discovery::{self, Discovery},
+        streaks::{self, Streak},
+        top::{self, TopAlbum, TopArtist, TopGenre, TopMetric, TopTrack},
+        TimeWindow,
+    },
+    db::{
+        self,
+        catalog::ArtistSummary,
+        play_log::{PlayFilter, PlayRecord},
+        settings::UserSettings,
+        DBError,
+    },
+};
+
+/// The number of items in each of the top lists of a report
+pub const REPORT_TOP_SIZE: u64 = 5;
+/// The number of new artists listed in a report, the count includes all of them
+pub const REPORT_NEW_ARTISTS_SIZE: usize = 10;
+
+/// A user's year in review
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub year: i32,
+    /// The timezone the days of the year are in
+    pub timezone: String,
+    /// The locale to format the report in
+    pub locale: String,
+    pub generated_at: DateTime,
+    pub total_plays: u64,
+    /// The total minutes listened, assuming each play was played in full
+    pub total_minutes: i64,
+    pub top_artists: Vec<TopArtist>,
+    pub top_albums: Vec<TopAlbum>,
+    pub top_tracks: Vec<TopTrack>,
+    pub top_genres: Vec<TopGenre>,
+    /// The first thing played in the year
+    pub first_play: Option<PlayRecord>,
+    pub most_played_day: Option<DayCount>,
+    pub longest_streak: Option<Streak>,
+    /// The number of artists played for the first time ever in the year
+    pub new_artist_count: u64,
+    /// The most played of the artists discovered in the year
+    pub new_artists: Vec<Discovery<ArtistSummary>>,
+}
+
+#[derive(FromQueryResult, Debug)]
+struct Totals {
+    play_count: i64,
+    ms_played: i64,
+}
+
+/// Count a user's plays in a window and the time spent listening to them
+async fn totals(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+) -> Result<Totals, DBError> {
+    Totals::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT COUNT(*) AS play_count,
+            CAST(COALESCE(SUM(track.duration_ms), 0) AS BIGINT) AS ms_played
+        FROM play_log
+        INNER JOIN track ON track.id = play_log.track_id
+        WHERE play_log.user_id = $1 AND play_log.played_at >= $2 AND play_log.played_at < $3"#,
+        [user_id.into(), window.from.into(), window.to.into()],
+    ))
+    .one(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error counting total plays: {:?}", sea_err);
+        DBError
+    })?
+    .ok_or(DBError)
+}
+
+/// Generate a user's year in review from their plays in the year, in their timezone
+pub async fn generate_report(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    year: i32,
+    settings: &UserSettings,
+) -> Result<Report, DBError> {
+    let tz = settings.timezone;
+    let window = TimeWindow::for_year(year, tz).ok_or(DBError)?;
+    let filter = PlayFilter {
+        from: Some(window.from),
+        to: Some(window.to),
+        ..Default::default()
+    };
+    let (totals, top_artists, top_albums, top_tracks, top_genres, first_play, days, new_artists) =
+        tokio::try_join!(
+            totals(conn, user_id, window),
+            top::top_artists(conn, user_id, window, TopMetric::Plays, REPORT_TOP_SIZE),
+            top::top_albums(conn, user_id, window, TopMetric::Plays, REPORT_TOP_SIZE),
+            top::top_tracks(conn, user_id, window, TopMetric::Plays, REPORT_TOP_SIZE),
+            top::top_genres(conn, user_id, window, REPORT_TOP_SIZE),
+            db::play_log::get_first_play(conn, user_id, &filter),
+            calendar::daily_plays(conn, user_id, window, tz),
+            discovery::new_artists(conn, user_id, window),
+        )?;
+    // Prefer the earliest day if there is a tie
+    let most_played_day = days.iter().rev().max_by_key(|day| day.play_count).copied();
+    let longest_streak = streaks::longest_streak(days.iter().map(|day| day.day));
+    let new_artist_count = new_artists.len() as u64;
+    Ok(Report {
+        year,
+        timezone: tz.name().to_string(),
+        locale: settings.locale.clone(),
+        generated_at: Utc::now().naive_utc(),
+        total_plays: totals.play_count as u64,
+        total_minutes: totals.ms_played / 60_000,
+        top_artists,
+        top_albums,
+        top_tracks,
+        top_genres,
+        first_play,
+        most_played_day,
+        longest_streak,
+        new_artist_count,
+        new_artists: new_artists
+            .into_iter()
+            .take(REPORT_NEW_ARTISTS_SIZE)
+            .collect(),
+    })
+}
+
+/// Generate a user's year in review with their settings and save it as a snapshot,
+/// replacing any previous snapshot
+pub async fn create_snapshot(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    year: i32,
+) -> Result<report::Model, DBError> {
+    let settings = db::settings::get_user_settings(conn, user_id).await?;
+    let report = generate_report(conn, user_id, year, &settings).await?;
+    let data = serde_json::to_value(report).map_err(|serde_err| {
+        error!("Error serializing report: {:?}", serde_err);
+        DBError
+    })?;
+    db::report::save_report(conn, user_id, year, data).await
+}
@@ -0,0 +1,115 @@
+use sea_orm::{DatabaseConnection, DbBackend, FromQueryResult, Statement};
+use serde::Serialize;
+use tracing::error;
+
+use crate::{
+    analytics::TimeWindow,
+    db::{
+        catalog::{self, TrackDetails},
+        DBError,
+    },
+};
+
+/// How often a user skips the tracks they play, from the play sessions tracked while polling
+#[derive(Serialize, FromQueryResult, Debug)]
+pub struct SkipStats {
+    pub sessions: i64,
+    pub skipped: i64,
+    /// The share of sessions that were skipped, between 0 and 1
+    pub skip_rate: f64,
+    pub ms_played: i64,
+}
+
+/// Get the skip stats of a user's play sessions started in a window
+pub async fn skip_stats(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+) -> Result<SkipStats, DBError> {
+    // Postgres sums bigints into a numeric, so cast it back
+    SkipStats::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT COUNT(*) AS sessions,
+            COUNT(*) FILTER (WHERE skipped) AS skipped,
+            COALESCE(CAST(COUNT(*) FILTER (WHERE skipped) AS DOUBLE PRECISION) / NULLIF(COUNT(*), 0), 0)
+                AS skip_rate,
+            CAST(COALESCE(SUM(ms_played), 0) AS BIGINT) AS ms_played
+        FROM play_session
+        WHERE user_id = $1 AND started_at >= $2 AND started_at < $3"#,
+        [user_id.into(), window.from.into(), window.to.into()],
+    ))
+    .one(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error getting skip stats: {:?}", sea_err);
+        DBError
+    })?
+    .ok_or(DBError)
+}
+
+/// A track and how often it was skipped
+#[derive(Serialize, Debug)]
+pub struct SkippedTrack {
+    #[serde(flatten)]
+    pub track: TrackDetails,
+    pub sessions: i64,
+    pub skipped: i64,
+    /// The share of the track's sessions that were skipped, between 0 and 1
+    pub skip_rate: f64,
+}
+
+#[derive(FromQueryResult, Debug)]
+struct Skipped {
+    track_id: i32,
+    sessions: i64,
+    skipped: i64,
+    skip_rate: f64,
+}
+
+/// Get the tracks a user skipped the most in a window
+/// Only tracks skipped at least twice are included, so a single skip doesn't top the list
+pub async fn most_skipped_tracks(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    limit: u64,
+) -> Result<Vec<SkippedTrack>, DBError> {
+    let skipped = Skipped::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT track_id,
+            COUNT(*) AS sessions,
+            COUNT(*) FILTER (WHERE skipped) AS skipped,
+            CAST(COUNT(*) FILTER (WHERE skipped) AS DOUBLE PRECISION) / COUNT(*) AS skip_rate
+        FROM play_session
+        WHERE user_id = $1 AND started_at >= $2 AND started_at < $3
+        GROUP BY track_id
+        HAVING COUNT(*) FILTER (WHERE skipped) >= 2
+        ORDER BY skipped DESC, skip_rate DESC, track_id
+        LIMIT $4"#,
+        [
+            user_id.into(),
+            window.from.into(),
+            window.to.into(),
+            (limit as i64).into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error ranking skipped tracks: {:?}", sea_err);
+        DBError
+    })?;
+    let ids = skipped.iter().map(|skipped| skipped.track_id).collect();
+    let tracks = catalog::get_track_details(conn, ids).await?;
+    Ok(skipped
+        .into_iter()
+        .filter_map(|skipped| {
+            Some(SkippedTrack {
+                track: tracks.get(&skipped.track_id)?.clone(),
+                sessions: skipped.sessions,
+                skipped: skipped.skipped,
+                skip_rate: skipped.skip_rate,
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,270 @@
+use chrono_tz::Tz;
+use sea_orm::{
+    prelude::{Date, DateTime},
+    DatabaseConnection, DbBackend, FromQueryResult, Statement,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use tracing::error;
+
+use crate::{
+    analytics::{top::Grouping, TimeWindow},
+    db::{
+        catalog::{self, ArtistSummary, TrackDetails},
+        DBError,
+    },
+};
+
+/// A run of consecutive days with at least one play
+#[derive(Serialize, FromQueryResult, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Streak {
+    pub start: Date,
+    pub end: Date,
+    pub days: i64,
+}
+
+/// Split days into streaks of consecutive days, in the order of the days
+/// The days must be sorted, duplicates are ignored
+pub fn streaks(days: impl IntoIterator<Item = Date>) -> Vec<Streak> {
+    let mut streaks: Vec<Streak> = vec![];
+    for day in days {
+        match streaks.last_mut() {
+            Some(streak) if day == streak.end => {}
+            Some(streak) if Some(day) == streak.end.succ_opt() => {
+                streak.end = day;
+                streak.days += 1;
+            }
+            _ => streaks.push(Streak {
+                start: day,
+                end: day,
+                days: 1,
+            }),
+        }
+    }
+    streaks
+}
+
+/// Find the longest streak of consecutive days, preferring the earliest if there is a tie
+/// The days must be sorted, duplicates are ignored
+pub fn longest_streak(days: impl IntoIterator<Item = Date>) -> Option<Streak> {
+    streaks(days)
+        .into_iter()
+        .rev()
+        .max_by_key(|streak| streak.days)
+}
+
+/// Find the streak that is still going on the given day
+/// A streak ending the day before is still going, since there's still time to keep it up
+/// The days must be sorted, duplicates are ignored
+pub fn current_streak(days: impl IntoIterator<Item = Date>, today: Date) -> Option<Streak> {
+    streaks(days)
+        .pop()
+        .filter(|streak| streak.end == today || streak.end.succ_opt() == Some(today))
+}
+
+/// The longest streak of an item, like an artist or track
+#[derive(Serialize, Debug)]
+pub struct ItemStreak<T> {
+    #[serde(flatten)]
+    pub item: T,
+    #[serde(flatten)]
+    pub streak: Streak,
+}
+
+pub type ArtistStreak = ItemStreak<ArtistSummary>;
+pub type TrackStreak = ItemStreak<TrackDetails>;
+
+#[derive(FromQueryResult, Debug)]
+struct GroupStreak {
+    id: i32,
+    start: Date,
+    end: Date,
+    days: i64,
+}
+
+/// Find the longest streak within a window of everything in a grouping, in the timezone,
+/// ordered by the longest streaks
+async fn group_streaks(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    grouping: Grouping,
+    window: TimeWindow,
+    tz: Tz,
+    limit: u64,
+) -> Result<Vec<GroupStreak>, DBError> {
+    // Subtracting the row number from consecutive days gives the same date, marking each streak
+    let sql = format!(
+        r#"SELECT id, start, "end", days
+        FROM (
+            SELECT DISTINCT ON (group_id) group_id AS id,
+                MIN(day) AS start,
+                MAX(day) AS "end",
+                COUNT(*) AS days
+            FROM (
+                SELECT group_id, day,
+                    day - CAST(ROW_NUMBER() OVER (PARTITION BY group_id ORDER BY day) AS INTEGER)
+                        AS streak_id
+                FROM (
+                    SELECT DISTINCT {group_by} AS group_id,
+                        CAST(play_log.played_at AT TIME ZONE 'UTC' AT TIME ZONE $4 AS DATE) AS day
+                    FROM play_log
+                    INNER JOIN album_track ON album_track.track_id = play_log.track_id
+                    INNER JOIN album_artist ON album_artist.album_id = album_track.album_id
+                    WHERE play_log.user_id = $1
+                        AND play_log.played_at >= $2 AND play_log.played_at < $3
+                ) AS days
+            ) AS streak_days
+            GROUP BY group_id, streak_id
+            ORDER BY group_id, days DESC, start
+        ) AS streaks
+        ORDER BY days DESC, start, id
+        LIMIT $5"#,
+        group_by = grouping.column(),
+    );
+    GroupStreak::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        sql,
+        [
+            user_id.into(),
+            window.from.into(),
+            window.to.into(),
+            tz.name().into(),
+            (limit as i64).into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error finding streaks by {:?}: {:?}", grouping, sea_err);
+        DBError
+    })
+}
+
+/// Attach the looked up items to their streaks, in the order of the streaks
+fn with_items<T: Clone>(streaks: Vec<GroupStreak>, items: HashMap<i32, T>) -> Vec<ItemStreak<T>> {
+    streaks
+        .into_iter()
+        .filter_map(|streak| {
+            Some(ItemStreak {
+                item: items.get(&streak.id)?.clone(),
+                streak: Streak {
+                    start: streak.start,
+                    end: streak.end,
+                    days: streak.days,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Get the artists a user listened to on the most consecutive days in a window, in the timezone
+pub async fn artist_streaks(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    tz: Tz,
+    limit: u64,
+) -> Result<Vec<ArtistStreak>, DBError> {
+    let streaks = group_streaks(conn, user_id, Grouping::Artist, window, tz, limit).await?;
+    let ids = streaks.iter().map(|streak| streak.id).collect();
+    let artists = catalog::get_artists_by_ids(conn, ids).await?;
+    Ok(with_items(streaks, artists))
+}
+
+/// Get the tracks a user listened to on the most consecutive days in a window, in the timezone
+pub async fn track_streaks(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    tz: Tz,
+    limit: u64,
+) -> Result<Vec<TrackStreak>, DBError> {
+    let streaks = group_streaks(conn, user_id, Grouping::Track, window, tz, limit).await?;
+    let ids = streaks.iter().map(|streak| streak.id).collect();
+    let tracks = catalog::get_track_details(conn, ids).await?;
+    Ok(with_items(streaks, tracks))
+}
+
+/// A track that was played over and over within a few days
+#[derive(Serialize, Debug)]
+pub struct Obsession {
+    #[serde(flatten)]
+    pub track: TrackDetails,
+    /// The most plays within the days
+    pub play_count: i64,
+    /// The first of the plays
+    pub start: DateTime,
+    /// The last of the plays
+    pub end: DateTime,
+}
+
+#[derive(FromQueryResult, Debug)]
+struct Peak {
+    id: i32,
+    play_count: i64,
+    start: DateTime,
+    end: DateTime,
+}
+
+/// Find the tracks a user played at least a number of times within a number of days, during a window
+/// Each track is only included once, with the most plays it had within the days, ordered by the most plays
+pub async fn obsessions(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    min_plays: u64,
+    days: u32,
+    limit: u64,
+) -> Result<Vec<Obsession>, DBError> {
+    // Each play counts the plays of its track in the days up to and including it
+    let peaks = Peak::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT id, play_count, start, "end"
+        FROM (
+            SELECT DISTINCT ON (track_id) track_id AS id, play_count, start, played_at AS "end"
+            FROM (
+                SELECT track_id, played_at,
+                    COUNT(*) OVER recent AS play_count,
+                    MIN(played_at) OVER recent AS start
+                FROM play_log
+                WHERE user_id = $1 AND played_at >= $2 AND played_at < $3
+                WINDOW recent AS (
+                    PARTITION BY track_id
+                    ORDER BY played_at
+                    RANGE BETWEEN make_interval(days => $4) PRECEDING AND CURRENT ROW
+                )
+            ) AS counted
+            WHERE play_count >= $5
+            ORDER BY track_id, play_count DESC, played_at
+        ) AS peaks
+        ORDER BY play_count DESC, start, id
+        LIMIT $6"#,
+        [
+            user_id.into(),
+            window.from.into(),
+            window.to.into(),
+            (days as i32).into(),
+            (min_plays as i64).into(),
+            (limit as i64).into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error finding obsessions: {:?}", sea_err);
+        DBError
+    })?;
+    let ids = peaks.iter().map(|peak| peak.id).collect();
+    let tracks = catalog::get_track_details(conn, ids).await?;
+    Ok(peaks
+        .into_iter()
+        .filter_map(|peak| {
+            Some(Obsession {
+                track: tracks.get(&peak.id)?.clone(),
+                play_count: peak.play_count,
+                start: peak.start,
+                end: peak.end,
+            })
+        })
+        .collect())
+}
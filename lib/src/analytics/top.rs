@@ -0,0 +1,237 @@
+use sea_orm::{DatabaseConnection, DbBackend, FromQueryResult, Statement};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::error;
+
+use crate::{
+    analytics::TimeWindow,
+    db::{
+        catalog::{self, AlbumSummary, ArtistSummary, TrackDetails},
+        DBError,
+    },
+};
+
+/// What to rank the top items by
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TopMetric {
+    /// The number of times the item was played
+    #[default]
+    Plays,
+    /// The total time spent listening to the item, as tracked by its play sessions,
+    /// plays without a session are assumed to have been played in full
+    Time,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TopItem<T> {
+    #[serde(flatten)]
+    pub item: T,
+    pub play_count: u64,
+    pub ms_played: i64,
+}
+
+pub type TopArtist = TopItem<ArtistSummary>;
+pub type TopAlbum = TopItem<AlbumSummary>;
+pub type TopTrack = TopItem<TrackDetails>;
+
+#[derive(FromQueryResult, Debug)]
+struct Ranked {
+    id: i32,
+    play_count: i64,
+    ms_played: i64,
+}
+
+/// What the plays are grouped by when ranking
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Grouping {
+    Artist,
+    Album,
+    Track,
+}
+
+impl Grouping {
+    /// The column of the joined plays identifying the group
+    pub(crate) fn column(&self) -> &'static str {
+        match self {
+            Grouping::Artist => "album_artist.artist_id",
+            Grouping::Album => "album_track.album_id",
+            Grouping::Track => "play_log.track_id",
+        }
+    }
+
+    /// The column of the joined play sessions identifying the group
+    pub(crate) fn session_column(&self) -> &'static str {
+        match self {
+            Grouping::Track => "play_session.track_id",
+            grouping => grouping.column(),
+        }
+    }
+}
+
+/// Rank a user's plays in a window by the given grouping
+async fn rank(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    grouping: Grouping,
+    metric: TopMetric,
+    limit: u64,
+) -> Result<Vec<Ranked>, DBError> {
+    let order_by = match metric {
+        TopMetric::Plays => "play_count DESC, ms_played DESC",
+        TopMetric::Time => "ms_played DESC, play_count DESC",
+    };
+    // A track can be on multiple albums with multiple artists, so the plays and sessions are made distinct
+    // within each group before aggregating, making sure each is only counted once per group
+    // The time listened comes from the play sessions, which also include skips that never became plays,
+    // plays only add their full duration when no session was tracked around the time they were played
+    let sql = format!(
+        r#"SELECT group_id AS id,
+            COUNT(play_id) AS play_count,
+            CAST(COALESCE(SUM(ms_played), 0) AS BIGINT) AS ms_played
+        FROM (
+            SELECT DISTINCT play_log.id AS play_id, {group_by} AS group_id,
+                CASE WHEN EXISTS (
+                    SELECT 1 FROM play_session
+                    WHERE play_session.user_id = play_log.user_id
+                        AND play_session.track_id = play_log.track_id
+                        AND play_log.played_at >= play_session.started_at - INTERVAL '1 minute'
+                        AND play_log.played_at <= play_session.ended_at + INTERVAL '1 minute'
+                ) THEN 0 ELSE COALESCE(track.duration_ms, 0) END AS ms_played
+            FROM play_log
+            INNER JOIN track ON track.id = play_log.track_id
+            INNER JOIN album_track ON album_track.track_id = play_log.track_id
+            INNER JOIN album_artist ON album_artist.album_id = album_track.album_id
+            WHERE play_log.user_id = $1 AND play_log.played_at >= $2 AND play_log.played_at < $3
+            UNION ALL
+            SELECT DISTINCT ON (play_session.id, {session_group_by})
+                NULL AS play_id, {session_group_by} AS group_id, play_session.ms_played
+            FROM play_session
+            INNER JOIN album_track ON album_track.track_id = play_session.track_id
+            INNER JOIN album_artist ON album_artist.album_id = album_track.album_id
+            WHERE play_session.user_id = $1
+                AND play_session.started_at >= $2 AND play_session.started_at < $3
+        ) AS listens
+        GROUP BY group_id
+        HAVING COUNT(play_id) > 0
+        ORDER BY {order_by}, group_id
+        LIMIT $4"#,
+        group_by = grouping.column(),
+        session_group_by = grouping.session_column(),
+    );
+    Ranked::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        sql,
+        [
+            user_id.into(),
+            window.from.into(),
+            window.to.into(),
+            (limit as i64).into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error ranking plays by {:?}: {:?}", grouping, sea_err);
+        DBError
+    })
+}
+
+/// Attach the looked up items to their rankings, in the order of the rankings
+fn with_items<T: Clone>(ranked: Vec<Ranked>, items: HashMap<i32, T>) -> Vec<TopItem<T>> {
+    ranked
+        .into_iter()
+        .filter_map(|ranked| {
+            Some(TopItem {
+                item: items.get(&ranked.id)?.clone(),
+                play_count: ranked.play_count as u64,
+                ms_played: ranked.ms_played,
+            })
+        })
+        .collect()
+}
+
+/// Get a user's most played artists in a window
+pub async fn top_artists(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    metric: TopMetric,
+    limit: u64,
+) -> Result<Vec<TopArtist>, DBError> {
+    let ranked = rank(conn, user_id, window, Grouping::Artist, metric, limit).await?;
+    let ids = ranked.iter().map(|ranked| ranked.id).collect();
+    let artists = catalog::get_artists_by_ids(conn, ids).await?;
+    Ok(with_items(ranked, artists))
+}
+
+/// Get a user's most played albums in a window
+pub async fn top_albums(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    metric: TopMetric,
+    limit: u64,
+) -> Result<Vec<TopAlbum>, DBError> {
+    let ranked = rank(conn, user_id, window, Grouping::Album, metric, limit).await?;
+    let ids = ranked.iter().map(|ranked| ranked.id).collect();
+    let albums = catalog::get_albums_by_ids(conn, ids).await?;
+    Ok(with_items(ranked, albums))
+}
+
+/// Get a user's most played tracks in a window
+pub async fn top_tracks(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    metric: TopMetric,
+    limit: u64,
+) -> Result<Vec<TopTrack>, DBError> {
+    let ranked = rank(conn, user_id, window, Grouping::Track, metric, limit).await?;
+    let ids = ranked.iter().map(|ranked| ranked.id).collect();
+    let tracks = catalog::get_track_details(conn, ids).await?;
+    Ok(with_items(ranked, tracks))
+}
+
+/// A genre and how much it was played
+#[derive(Serialize, FromQueryResult, Debug)]
+pub struct TopGenre {
+    pub name: String,
+    pub play_count: i64,
+}
+
+/// Get a user's most played genres in a window, from the genres of the artists they played
+/// A play counts once for every genre of its artists
+pub async fn top_genres(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    limit: u64,
+) -> Result<Vec<TopGenre>, DBError> {
+    TopGenre::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT genre.name, COUNT(DISTINCT play_log.id) AS play_count
+        FROM play_log
+        INNER JOIN album_track ON album_track.track_id = play_log.track_id
+        INNER JOIN album_artist ON album_artist.album_id = album_track.album_id
+        INNER JOIN artist_genre ON artist_genre.artist_id = album_artist.artist_id
+        INNER JOIN genre ON genre.id = artist_genre.genre_id
+        WHERE play_log.user_id = $1 AND play_log.played_at >= $2 AND play_log.played_at < $3
+        GROUP BY genre.name
+        ORDER BY play_count DESC, genre.name
+        LIMIT $4"#,
+        [
+            user_id.into(),
+            window.from.into(),
+            window.to.into(),
+            (limit as i64).into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error ranking genres: {:?}", sea_err);
+        DBError
+    })
+}
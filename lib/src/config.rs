@@ -0,0 +1,647 @@
+use crypto::Keyring;
+use serde::Deserialize;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use surf::Url;
+
+use crate::music::{
+    lastfm::LastfmCredentials,
+    spotify::{SpotifyCredentials, PLAYLIST_SCOPES},
+};
+
+/// The environment variable pointing to the config file
+pub const CONFIG_PATH_VAR: &str = "UNWRAPPED_CONFIG";
+
+/// The config file read when no path is given, if it exists
+pub const DEFAULT_CONFIG_PATH: &str = "unwrapped.toml";
+
+#[derive(Debug)]
+pub struct ConfigError(pub String);
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Config Error: {}", self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// All the settings of the application, read from a TOML file and then overridden by the environment
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub providers: ProvidersConfig,
+    pub scheduler: SchedulerConfig,
+    pub security: SecurityConfig,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The address the API listens on
+    pub bind: SocketAddr,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: SocketAddr::from(([0, 0, 0, 0], 8000)),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub connect_timeout_secs: u64,
+    pub acquire_timeout_secs: u64,
+    pub idle_timeout_secs: u64,
+    pub max_lifetime_secs: u64,
+    /// Whether every statement is logged
+    pub sqlx_logging: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: String::new(),
+            max_connections: 100,
+            min_connections: 5,
+            connect_timeout_secs: 8,
+            acquire_timeout_secs: 8,
+            idle_timeout_secs: 8,
+            max_lifetime_secs: 8,
+            sqlx_logging: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProvidersConfig {
+    pub spotify: SpotifyConfig,
+    pub lastfm: LastfmConfig,
+    pub listenbrainz: ListenBrainzConfig,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpotifyConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    /// The scopes requested when logging in, the playlist scopes are always added
+    pub scopes: Vec<String>,
+    /// Where Spotify redirects back to after logging in, the callback route
+    pub redirect_uri: String,
+    /// Whether logins are started with a PKCE code challenge
+    pub pkce: bool,
+}
+
+impl SpotifyConfig {
+    pub fn credentials(&self) -> SpotifyCredentials {
+        SpotifyCredentials {
+            client_id: self.client_id.clone(),
+            client_secret: self.client_secret.clone(),
+        }
+    }
+}
+
+/// Last.fm accounts can only be linked once an API account is set up, it's left out without an API key
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LastfmConfig {
+    pub api_key: String,
+    pub shared_secret: String,
+    /// Where Last.fm redirects back to after allowing access, the callback route
+    pub callback_url: String,
+}
+
+impl LastfmConfig {
+    /// Whether Last.fm accounts can be linked
+    pub fn enabled(&self) -> bool {
+        !self.api_key.trim().is_empty()
+    }
+
+    pub fn credentials(&self) -> LastfmCredentials {
+        LastfmCredentials {
+            api_key: self.api_key.clone(),
+            shared_secret: self.shared_secret.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenBrainzConfig {
+    /// Where the ListenBrainz API is hosted, only changed for self hosted instances
+    pub api_url: String,
+}
+
+impl Default for ListenBrainzConfig {
+    fn default() -> Self {
+        ListenBrainzConfig {
+            api_url: "https://api.listenbrainz.org".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    /// How often the currently playing tracks are polled
+    pub poll_interval_secs: u64,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            poll_interval_secs: 15,
+        }
+    }
+}
+
+impl SchedulerConfig {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval_secs)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityConfig {
+    /// The master keys the account tokens are encrypted with, as comma separated `id:base64` pairs
+    /// The first key is the active one, see the `crypto` crate
+    pub token_encryption_keys: String,
+    /// How long a session lasts before the user has to log in again
+    pub session_ttl_days: i64,
+    /// Whether cookies are only sent over HTTPS, only turn this off when developing locally over HTTP
+    pub secure_cookies: bool,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        SecurityConfig {
+            token_encryption_keys: String::new(),
+            session_ttl_days: 30,
+            secure_cookies: true,
+        }
+    }
+}
+
+/// Read an environment variable, treating an empty value as unset
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+/// Looks up the variables settings are overridden by, the environment outside of tests
+type Vars<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+/// Parse a variable if it's set
+fn parse_var<T: std::str::FromStr>(vars: Vars, name: &str) -> Result<Option<T>, ConfigError>
+where
+    T::Err: std::fmt::Display,
+{
+    vars(name)
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|parse_err| ConfigError(format!("{} is invalid: {}", name, parse_err)))
+        })
+        .transpose()
+}
+
+/// Require a setting to have a value, naming both where it can be set
+fn require(value: &str, key: &str, var: &str) -> Result<(), ConfigError> {
+    if value.trim().is_empty() {
+        return Err(ConfigError(format!("{} or {} must be set", key, var)));
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Load the config file, apply the environment overrides, and check the result is usable
+    /// Without a path, the file from `UNWRAPPED_CONFIG` is read, or `unwrapped.toml` if it exists
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let path = match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => env_var(CONFIG_PATH_VAR)
+                .map(PathBuf::from)
+                .or(Some(PathBuf::from(DEFAULT_CONFIG_PATH)).filter(|path| path.exists())),
+        };
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Read the config from a TOML file, leaving out settings at their defaults
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|io_err| {
+            ConfigError(format!("Failed to read {}: {}", path.display(), io_err))
+        })?;
+        toml::from_str(&contents)
+            .map_err(|toml_err| ConfigError(format!("Invalid {}: {}", path.display(), toml_err)))
+    }
+
+    /// Override the settings with any that are set in the environment
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        self.apply_vars(&env_var)
+    }
+
+    /// Override the settings with any of the variables that are set
+    fn apply_vars(&mut self, vars: Vars) -> Result<(), ConfigError> {
+        if let Some(bind) = parse_var(vars, "BIND_ADDRESS")? {
+            self.server.bind = bind;
+        }
+        if let Some(url) = vars("DATABASE_URL") {
+            self.database.url = url;
+        }
+        if let Some(max_connections) = parse_var(vars, "DATABASE_MAX_CONNECTIONS")? {
+            self.database.max_connections = max_connections;
+        }
+        if let Some(min_connections) = parse_var(vars, "DATABASE_MIN_CONNECTIONS")? {
+            self.database.min_connections = min_connections;
+        }
+        let spotify = &mut self.providers.spotify;
+        if let Some(client_id) = vars("SPOTIFY_ID") {
+            spotify.client_id = client_id;
+        }
+        if let Some(client_secret) = vars("SPOTIFY_SECRET") {
+            spotify.client_secret = client_secret;
+        }
+        if let Some(scopes) = vars("SPOTIFY_SCOPES") {
+            spotify.scopes = scopes.split_whitespace().map(String::from).collect();
+        }
+        if let Some(redirect_uri) = vars("SPOTIFY_REDIRECT_URI") {
+            spotify.redirect_uri = redirect_uri;
+        }
+        if let Some(pkce) = parse_var(vars, "SPOTIFY_PKCE")? {
+            spotify.pkce = pkce;
+        }
+        let lastfm = &mut self.providers.lastfm;
+        if let Some(api_key) = vars("LASTFM_API_KEY") {
+            lastfm.api_key = api_key;
+        }
+        if let Some(shared_secret) = vars("LASTFM_SECRET") {
+            lastfm.shared_secret = shared_secret;
+        }
+        if let Some(callback_url) = vars("LASTFM_CALLBACK_URL") {
+            lastfm.callback_url = callback_url;
+        }
+        if let Some(api_url) = vars("LISTENBRAINZ_API_URL") {
+            self.providers.listenbrainz.api_url = api_url;
+        }
+        if let Some(poll_interval) = parse_var(vars, "SPOTIFY_POLL_INTERVAL")? {
+            self.scheduler.poll_interval_secs = poll_interval;
+        }
+        if let Some(keys) = vars(crypto::KEYS_VAR) {
+            self.security.token_encryption_keys = keys;
+        }
+        if let Some(session_ttl) = parse_var(vars, "SESSION_TTL_DAYS")? {
+            self.security.session_ttl_days = session_ttl;
+        }
+        if let Some(secure_cookies) = parse_var(vars, "SECURE_COOKIES")? {
+            self.security.secure_cookies = secure_cookies;
+        }
+        Ok(())
+    }
+
+    /// Check every setting is usable, so nothing fails later on while serving
+    pub fn validate(&mut self) -> Result<(), ConfigError> {
+        require(&self.database.url, "database.url", "DATABASE_URL")?;
+        if self.database.max_connections == 0 {
+            return Err(ConfigError(
+                "database.max_connections must be more than 0".to_string(),
+            ));
+        }
+        if self.database.min_connections > self.database.max_connections {
+            return Err(ConfigError(
+                "database.min_connections can't be more than database.max_connections".to_string(),
+            ));
+        }
+        let spotify = &mut self.providers.spotify;
+        require(
+            &spotify.client_id,
+            "providers.spotify.client_id",
+            "SPOTIFY_ID",
+        )?;
+        require(
+            &spotify.client_secret,
+            "providers.spotify.client_secret",
+            "SPOTIFY_SECRET",
+        )?;
+        if spotify.scopes.is_empty() {
+            return Err(ConfigError(
+                "providers.spotify.scopes or SPOTIFY_SCOPES must be set".to_string(),
+            ));
+        }
+        // Generated playlists are written back to Spotify, so those scopes are always requested
+        for scope in PLAYLIST_SCOPES {
+            if !spotify.scopes.iter().any(|existing| existing == scope) {
+                spotify.scopes.push(scope.to_string());
+            }
+        }
+        require(
+            &spotify.redirect_uri,
+            "providers.spotify.redirect_uri",
+            "SPOTIFY_REDIRECT_URI",
+        )?;
+        Url::parse(&spotify.redirect_uri).map_err(|url_err| {
+            ConfigError(format!(
+                "providers.spotify.redirect_uri is not a valid URL: {}",
+                url_err
+            ))
+        })?;
+        let lastfm = &self.providers.lastfm;
+        if lastfm.enabled() {
+            require(
+                &lastfm.shared_secret,
+                "providers.lastfm.shared_secret",
+                "LASTFM_SECRET",
+            )?;
+            require(
+                &lastfm.callback_url,
+                "providers.lastfm.callback_url",
+                "LASTFM_CALLBACK_URL",
+            )?;
+            Url::parse(&lastfm.callback_url).map_err(|url_err| {
+                ConfigError(format!(
+                    "providers.lastfm.callback_url is not a valid URL: {}",
+                    url_err
+                ))
+            })?;
+        }
+        Url::parse(&self.providers.listenbrainz.api_url).map_err(|url_err| {
+            ConfigError(format!(
+                "providers.listenbrainz.api_url is not a valid URL: {}",
+                url_err
+            ))
+        })?;
+        if self.scheduler.poll_interval_secs == 0 {
+            return Err(ConfigError(
+                "scheduler.poll_interval_secs must be more than 0".to_string(),
+            ));
+        }
+        require(
+            &self.security.token_encryption_keys,
+            "security.token_encryption_keys",
+            crypto::KEYS_VAR,
+        )?;
+        self.security
+            .keyring()
+            .map_err(|crypto_err| ConfigError(crypto_err.to_string()))?;
+        if self.security.session_ttl_days <= 0 {
+            return Err(ConfigError(
+                "security.session_ttl_days must be more than 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl SecurityConfig {
+    pub fn keyring(&self) -> Result<Keyring, crypto::CryptoError> {
+        Keyring::parse(&self.token_encryption_keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const KEYS: &str = "first:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+    /// A config with every required setting, which the tests break one at a time
+    fn config() -> Config {
+        toml::from_str(&format!(
+            r#"
+            [database]
+            url = "postgres://localhost/unwrapped"
+
+            [providers.spotify]
+            client_id = "id"
+            client_secret = "secret"
+            scopes = ["user-read-recently-played"]
+            redirect_uri = "http://localhost:8000/auth/spotify/callback"
+
+            [security]
+            token_encryption_keys = "{}"
+            "#,
+            KEYS
+        ))
+        .unwrap()
+    }
+
+    /// Apply variables in place of the environment
+    fn apply(config: &mut Config, vars: &[(&str, &str)]) -> Result<(), ConfigError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        config.apply_vars(&|name| vars.get(name).cloned())
+    }
+
+    /// The error validating a config after changing it
+    fn invalid(change: impl FnOnce(&mut Config)) -> String {
+        let mut config = config();
+        change(&mut config);
+        config.validate().unwrap_err().0
+    }
+
+    #[test]
+    fn file_settings_override_the_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            bind = "127.0.0.1:9000"
+
+            [database]
+            max_connections = 10
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.bind, SocketAddr::from(([127, 0, 0, 1], 9000)));
+        assert_eq!(config.database.max_connections, 10);
+        // Everything left out keeps its default
+        assert_eq!(config.database.min_connections, 5);
+        assert_eq!(config.scheduler.poll_interval_secs, 15);
+        assert_eq!(config.security.session_ttl_days, 30);
+        assert!(config.security.secure_cookies);
+        assert_eq!(
+            config.providers.listenbrainz.api_url,
+            "https://api.listenbrainz.org"
+        );
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(toml::from_str::<Config>("[database]\nulr = \"postgres://\"").is_err());
+    }
+
+    #[test]
+    fn variables_override_the_file() {
+        let mut config = config();
+        apply(
+            &mut config,
+            &[
+                ("DATABASE_URL", "postgres://db/other"),
+                ("DATABASE_MAX_CONNECTIONS", "20"),
+                (
+                    "SPOTIFY_SCOPES",
+                    "user-read-currently-playing user-top-read",
+                ),
+                ("SPOTIFY_PKCE", "true"),
+                ("SESSION_TTL_DAYS", "7"),
+                ("SECURE_COOKIES", "false"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.database.url, "postgres://db/other");
+        assert_eq!(config.database.max_connections, 20);
+        assert_eq!(
+            config.providers.spotify.scopes,
+            vec!["user-read-currently-playing", "user-top-read"]
+        );
+        assert!(config.providers.spotify.pkce);
+        assert_eq!(config.security.session_ttl_days, 7);
+        assert!(!config.security.secure_cookies);
+        // Settings without a variable keep the value from the file
+        assert_eq!(config.providers.spotify.client_id, "id");
+        assert_eq!(config.security.token_encryption_keys, KEYS);
+    }
+
+    #[test]
+    fn invalid_variables_are_rejected() {
+        for (name, value) in [
+            ("BIND_ADDRESS", "localhost"),
+            ("DATABASE_MAX_CONNECTIONS", "many"),
+            ("SPOTIFY_PKCE", "yes"),
+            ("SPOTIFY_POLL_INTERVAL", "-1"),
+            ("SESSION_TTL_DAYS", "week"),
+            ("SECURE_COOKIES", "maybe"),
+        ] {
+            let err = apply(&mut config(), &[(name, value)]).unwrap_err();
+            assert!(err.0.starts_with(name), "{}", err);
+        }
+    }
+
+    #[test]
+    fn playlist_scopes_are_always_requested() {
+        let mut config = config();
+        config.validate().unwrap();
+        let scopes = config.providers.spotify.scopes.clone();
+        assert_eq!(scopes[0], "user-read-recently-played");
+        for scope in PLAYLIST_SCOPES {
+            assert_eq!(
+                scopes.iter().filter(|existing| *existing == scope).count(),
+                1
+            );
+        }
+        // Validating again doesn't add them twice
+        config.validate().unwrap();
+        assert_eq!(config.providers.spotify.scopes.len(), scopes.len());
+    }
+
+    #[test]
+    fn required_settings_are_checked() {
+        assert_eq!(
+            invalid(|config| config.database.url.clear()),
+            "database.url or DATABASE_URL must be set"
+        );
+        assert_eq!(
+            invalid(|config| config.providers.spotify.client_id.clear()),
+            "providers.spotify.client_id or SPOTIFY_ID must be set"
+        );
+        assert_eq!(
+            invalid(|config| config.providers.spotify.client_secret.clear()),
+            "providers.spotify.client_secret or SPOTIFY_SECRET must be set"
+        );
+        assert_eq!(
+            invalid(|config| config.providers.spotify.scopes.clear()),
+            "providers.spotify.scopes or SPOTIFY_SCOPES must be set"
+        );
+        assert_eq!(
+            invalid(|config| config.providers.spotify.redirect_uri.clear()),
+            "providers.spotify.redirect_uri or SPOTIFY_REDIRECT_URI must be set"
+        );
+        assert_eq!(
+            invalid(|config| config.security.token_encryption_keys.clear()),
+            format!(
+                "security.token_encryption_keys or {} must be set",
+                crypto::KEYS_VAR
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_settings_are_checked() {
+        assert_eq!(
+            invalid(|config| config.database.max_connections = 0),
+            "database.max_connections must be more than 0"
+        );
+        assert_eq!(
+            invalid(|config| config.database.min_connections = 200),
+            "database.min_connections can't be more than database.max_connections"
+        );
+        assert!(
+            invalid(|config| config.providers.spotify.redirect_uri = "callback".to_string())
+                .starts_with("providers.spotify.redirect_uri is not a valid URL")
+        );
+        assert!(invalid(
+            |config| config.providers.listenbrainz.api_url = "listenbrainz".to_string()
+        )
+        .starts_with("providers.listenbrainz.api_url is not a valid URL"));
+        assert_eq!(
+            invalid(|config| config.scheduler.poll_interval_secs = 0),
+            "scheduler.poll_interval_secs must be more than 0"
+        );
+        assert_eq!(
+            invalid(|config| config.security.token_encryption_keys = "first:AAAA".to_string()),
+            "Encryption Error: Key first must be 32 bytes"
+        );
+        assert_eq!(
+            invalid(|config| config.security.session_ttl_days = 0),
+            "security.session_ttl_days must be more than 0"
+        );
+    }
+
+    #[test]
+    fn lastfm_settings_are_checked_once_enabled() {
+        let mut config = config();
+        config.providers.lastfm.callback_url = "callback".to_string();
+        // Without an API key Last.fm is left out, so the rest isn't checked
+        config.validate().unwrap();
+        assert_eq!(
+            invalid(|config| config.providers.lastfm.api_key = "key".to_string()),
+            "providers.lastfm.shared_secret or LASTFM_SECRET must be set"
+        );
+        assert_eq!(
+            invalid(|config| {
+                config.providers.lastfm.api_key = "key".to_string();
+                config.providers.lastfm.shared_secret = "secret".to_string();
+            }),
+            "providers.lastfm.callback_url or LASTFM_CALLBACK_URL must be set"
+        );
+        assert!(invalid(|config| {
+            config.providers.lastfm.api_key = "key".to_string();
+            config.providers.lastfm.shared_secret = "secret".to_string();
+            config.providers.lastfm.callback_url = "callback".to_string();
+        })
+        .starts_with("providers.lastfm.callback_url is not a valid URL"));
+    }
+}
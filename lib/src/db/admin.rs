@@ -0,0 +1,122 @@
+use sea_orm::{prelude::DateTime, DatabaseConnection, DbBackend, FromQueryResult, Statement};
+use serde::Serialize;
+use tracing::error;
+
+use crate::db::DBError;
+
+/// A user of the instance along with how much they've collected
+#[derive(FromQueryResult, Serialize, Debug)]
+pub struct UserListing {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub role: String,
+    pub created_at: DateTime,
+    pub accounts: i64,
+    pub plays: i64,
+    pub last_played_at: Option<DateTime>,
+}
+
+/// Get every user of the instance, oldest first
+pub async fn list_users(conn: &DatabaseConnection) -> Result<Vec<UserListing>, DBError> {
+    UserListing::find_by_statement(Statement::from_string(
+        DbBackend::Postgres,
+        r#"SELECT "user".id, "user".name, "user".email, "user".role, "user".created_at,
+            (SELECT COUNT(*) FROM account WHERE account.user_id = "user".id) AS accounts,
+            (SELECT COUNT(*) FROM play_log WHERE play_log.user_id = "user".id) AS plays,
+            (SELECT MAX(played_at) FROM play_log WHERE play_log.user_id = "user".id) AS last_played_at
+        FROM "user"
+        ORDER BY "user".created_at, "user".id"#,
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error listing users: {:?}", sea_err);
+        DBError
+    })
+}
+
+/// A linked account along with how collecting it last went, without its tokens
+#[derive(FromQueryResult, Serialize, Debug)]
+pub struct AccountStatus {
+    pub id: i32,
+    pub user_id: String,
+    pub provider: String,
+    pub provider_id: String,
+    pub disabled_at: Option<DateTime>,
+    /// When collecting the account was last attempted
+    pub last_collection_at: Option<DateTime>,
+    /// When collecting the account last succeeded
+    pub last_collected_at: Option<DateTime>,
+    /// Why collecting the account last failed, missing if it succeeded
+    pub last_collection_error: Option<String>,
+    pub plays: i64,
+}
+
+/// Get every linked account of the instance with its collection status, grouped by user
+pub async fn list_accounts(conn: &DatabaseConnection) -> Result<Vec<AccountStatus>, DBError> {
+    AccountStatus::find_by_statement(Statement::from_string(
+        DbBackend::Postgres,
+        r#"SELECT account.id, account.user_id, account.provider, account.provider_id,
+            account.disabled_at, account.last_collection_at, account.last_collected_at,
+            account.last_collection_error,
+            (SELECT COUNT(*) FROM play_log WHERE play_log.account_id = account.id) AS plays
+        FROM account
+        ORDER BY account.user_id, account.id"#,
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error listing account statuses: {:?}", sea_err);
+        DBError
+    })
+}
+
+/// Totals across every user of the instance
+#[derive(FromQueryResult, Serialize, Debug)]
+pub struct InstanceStats {
+    pub users: i64,
+    pub admins: i64,
+    pub accounts: i64,
+    pub disabled_accounts: i64,
+    /// Accounts whose last collection failed
+    pub failing_accounts: i64,
+    pub plays: i64,
+    /// Plays in the last 24 hours
+    pub recent_plays: i64,
+    pub play_sessions: i64,
+    pub artists: i64,
+    pub albums: i64,
+    pub tracks: i64,
+    pub api_keys: i64,
+    pub last_played_at: Option<DateTime>,
+}
+
+/// Get the totals across every user of the instance
+pub async fn get_instance_stats(conn: &DatabaseConnection) -> Result<InstanceStats, DBError> {
+    InstanceStats::find_by_statement(Statement::from_string(
+        DbBackend::Postgres,
+        r#"SELECT
+            (SELECT COUNT(*) FROM "user") AS users,
+            (SELECT COUNT(*) FROM "user" WHERE role = 'admin') AS admins,
+            (SELECT COUNT(*) FROM account) AS accounts,
+            (SELECT COUNT(*) FROM account WHERE disabled_at IS NOT NULL) AS disabled_accounts,
+            (SELECT COUNT(*) FROM account WHERE last_collection_error IS NOT NULL) AS failing_accounts,
+            (SELECT COUNT(*) FROM play_log) AS plays,
+            (SELECT COUNT(*) FROM play_log
+                WHERE played_at >= (NOW() AT TIME ZONE 'UTC') - INTERVAL '1 day') AS recent_plays,
+            (SELECT COUNT(*) FROM play_session) AS play_sessions,
+            (SELECT COUNT(*) FROM artist) AS artists,
+            (SELECT COUNT(*) FROM album) AS albums,
+            (SELECT COUNT(*) FROM track) AS tracks,
+            (SELECT COUNT(*) FROM api_key) AS api_keys,
+            (SELECT MAX(played_at) FROM play_log) AS last_played_at"#,
+    ))
+    .one(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error getting instance stats: {:?}", sea_err);
+        DBError
+    })?
+    .ok_or(DBError)
+}
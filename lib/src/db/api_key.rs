@@ -0,0 +1,169 @@
+use base64::prelude::*;
+use entity::{api_key, user};
+use rand::RngCore;
+use sea_orm::{
+    sqlx::types::chrono::Utc, ActiveValue::NotSet, ColumnTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use crate::db::DBError;
+
+/// Every key starts with this, so leaked keys are easy to recognize
+pub const API_KEY_PREFIX: &str = "unw_";
+
+/// The number of random bytes in a key
+const API_KEY_LENGTH: usize = 32;
+
+/// The number of characters of a key kept in plain text, so users can tell their keys apart
+const DISPLAY_PREFIX_LENGTH: usize = 12;
+
+/// What an API key is allowed to do
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApiScope {
+    /// Read the listening history and everything derived from it
+    #[serde(rename = "read:plays")]
+    ReadPlays,
+    #[serde(rename = "read:settings")]
+    ReadSettings,
+    #[serde(rename = "write:settings")]
+    WriteSettings,
+    /// Generate playlists on the connected provider
+    #[serde(rename = "write:playlists")]
+    WritePlaylists,
+    /// Regenerate the yearly reports
+    #[serde(rename = "write:reports")]
+    WriteReports,
+    /// Collect the recently played tracks from the connected provider
+    #[serde(rename = "write:collect")]
+    WriteCollect,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::ReadPlays => "read:plays",
+            ApiScope::ReadSettings => "read:settings",
+            ApiScope::WriteSettings => "write:settings",
+            ApiScope::WritePlaylists => "write:playlists",
+            ApiScope::WriteReports => "write:reports",
+            ApiScope::WriteCollect => "write:collect",
+        }
+    }
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Hash a key the way it's stored
+/// Keys are long and random, so a fast hash is enough to keep them from being recovered
+fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// Generate a new random key
+fn generate_key() -> String {
+    let mut bytes = [0u8; API_KEY_LENGTH];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", API_KEY_PREFIX, BASE64_URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// Create an API key for a user, returning the saved key along with the key itself
+/// The key itself isn't stored, so this is the only time it's available
+pub async fn create_api_key(
+    conn: &DatabaseConnection,
+    user_id: String,
+    name: String,
+    scopes: &[ApiScope],
+) -> Result<(api_key::Model, String), DBError> {
+    let key = generate_key();
+    let mut scopes: Vec<String> = scopes.iter().map(|scope| scope.to_string()).collect();
+    scopes.sort();
+    scopes.dedup();
+    let api_key = api_key::ActiveModel {
+        id: NotSet,
+        user_id: Set(user_id),
+        name: Set(name),
+        prefix: Set(key.chars().take(DISPLAY_PREFIX_LENGTH).collect()),
+        key_hash: Set(hash_key(&key)),
+        scopes: Set(scopes),
+        created_at: Set(Utc::now().naive_utc()),
+        last_used_at: Set(None),
+    };
+    let model = api_key::Entity::insert(api_key)
+        .exec_with_returning(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error creating API key: {:?}", sea_err);
+            DBError
+        })?;
+    Ok((model, key))
+}
+
+/// Get the API keys of a user, newest first
+pub async fn get_user_api_keys(
+    conn: &DatabaseConnection,
+    user_id: &str,
+) -> Result<Vec<api_key::Model>, DBError> {
+    api_key::Entity::find()
+        .filter(api_key::Column::UserId.eq(user_id))
+        .order_by_desc(api_key::Column::CreatedAt)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up API keys: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// Revoke an API key of a user, returning whether there was a key to revoke
+pub async fn delete_api_key(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    key_id: i32,
+) -> Result<bool, DBError> {
+    api_key::Entity::delete_many()
+        .filter(api_key::Column::Id.eq(key_id))
+        .filter(api_key::Column::UserId.eq(user_id))
+        .exec(conn)
+        .await
+        .map(|res| res.rows_affected > 0)
+        .map_err(|sea_err| {
+            error!("Error deleting API key: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// Get the user a key belongs to along with the saved key, marking the key as just used
+pub async fn get_api_key_user(
+    conn: &DatabaseConnection,
+    key: &str,
+) -> Result<Option<(api_key::Model, user::Model)>, DBError> {
+    let found = api_key::Entity::find()
+        .filter(api_key::Column::KeyHash.eq(hash_key(key)))
+        .find_also_related(user::Entity)
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up API key: {:?}", sea_err);
+            DBError
+        })?;
+    let Some((api_key, Some(user))) = found else {
+        return Ok(None);
+    };
+    api_key::Entity::update_many()
+        .col_expr(api_key::Column::LastUsedAt, Utc::now().naive_utc().into())
+        .filter(api_key::Column::Id.eq(api_key.id))
+        .exec(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error marking API key as used: {:?}", sea_err);
+            DBError
+        })?;
+    Ok(Some((api_key, user)))
+}
@@ -0,0 +1,654 @@
+use entity::{album, album_artist, album_track, artist, play_log, track};
+use migration::{Expr, Func, LikeExpr};
+use sea_orm::{
+    prelude::Date, sea_query::NullOrdering, ColumnTrait, ConnectionTrait, DatabaseConnection,
+    DbBackend, EntityTrait, FromQueryResult, JoinType, ModelTrait, Order, QueryFilter, QueryOrder,
+    QuerySelect, RelationTrait, Select, Statement,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use tracing::error;
+
+use crate::db::DBError;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ArtistSummary {
+    pub id: i32,
+    pub name: String,
+}
+
+impl From<artist::Model> for ArtistSummary {
+    fn from(artist: artist::Model) -> Self {
+        Self {
+            id: artist.id,
+            name: artist.name,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AlbumSummary {
+    pub id: i32,
+    pub title: String,
+    /// Missing for albums only known from providers that don't say when they were released
+    pub release_date: Option<Date>,
+}
+
+impl From<album::Model> for AlbumSummary {
+    fn from(album: album::Model) -> Self {
+        Self {
+            id: album.id,
+            title: album.title,
+            release_date: album.release_date,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TrackSummary {
+    pub id: i32,
+    pub title: String,
+    pub duration_ms: Option<i32>,
+}
+
+impl From<track::Model> for TrackSummary {
+    fn from(track: track::Model) -> Self {
+        Self {
+            id: track.id,
+            title: track.title,
+            duration_ms: track.duration_ms,
+        }
+    }
+}
+
+/// A track along with the album it is on and the artists of that album
+#[derive(Serialize, Debug, Clone)]
+pub struct TrackDetails {
+    pub track: TrackSummary,
+    pub album: Option<AlbumSummary>,
+    pub artists: Vec<ArtistSummary>,
+}
+
+/// Look up the album and artists of each track, returning the details keyed by the track ID
+pub async fn get_track_details(
+    conn: &DatabaseConnection,
+    track_ids: Vec<i32>,
+) -> Result<HashMap<i32, TrackDetails>, DBError> {
+    // Tracks are related to albums through album_track
+    let tracks_with_albums = track::Entity::find()
+        .filter(track::Column::Id.is_in(track_ids))
+        .find_with_related(album::Entity)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up tracks with albums: {:?}", sea_err);
+            DBError
+        })?;
+    // Albums are related to artists through album_artist
+    let album_ids: Vec<i32> = tracks_with_albums
+        .iter()
+        .filter_map(|(_, albums)| albums.first().map(|album| album.id))
+        .collect();
+    let album_artists: HashMap<i32, Vec<artist::Model>> = album::Entity::find()
+        .filter(album::Column::Id.is_in(album_ids))
+        .find_with_related(artist::Entity)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up albums with artists: {:?}", sea_err);
+            DBError
+        })?
+        .into_iter()
+        .map(|(album, artists)| (album.id, artists))
+        .collect();
+    // Put together the details of each track
+    Ok(tracks_with_albums
+        .into_iter()
+        .map(|(track, albums)| {
+            let album = albums.into_iter().next();
+            let artists = album
+                .as_ref()
+                .and_then(|album| album_artists.get(&album.id))
+                .map(|artists| artists.iter().cloned().map(ArtistSummary::from).collect())
+                .unwrap_or_default();
+            let details = TrackDetails {
+                album: album.map(AlbumSummary::from),
+                artists,
+                track: TrackSummary::from(track.clone()),
+            };
+            (track.id, details)
+        })
+        .collect())
+}
+
+/// Look up artists by their IDs, keyed by the ID
+pub async fn get_artists_by_ids(
+    conn: &DatabaseConnection,
+    ids: Vec<i32>,
+) -> Result<HashMap<i32, ArtistSummary>, DBError> {
+    artist::Entity::find()
+        .filter(artist::Column::Id.is_in(ids))
+        .all(conn)
+        .await
+        .map(|artists| {
+            artists
+                .into_iter()
+                .map(|artist| (artist.id, artist.into()))
+                .collect()
+        })
+        .map_err(|sea_err| {
+            error!("Error looking up artists: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// Look up albums by their IDs, keyed by the ID
+pub async fn get_albums_by_ids(
+    conn: &DatabaseConnection,
+    ids: Vec<i32>,
+) -> Result<HashMap<i32, AlbumSummary>, DBError> {
+    album::Entity::find()
+        .filter(album::Column::Id.is_in(ids))
+        .all(conn)
+        .await
+        .map(|albums| {
+            albums
+                .into_iter()
+                .map(|album| (album.id, album.into()))
+                .collect()
+        })
+        .map_err(|sea_err| {
+            error!("Error looking up albums: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// The most catalog items that can be listed at once
+pub const MAX_LIST_SIZE: u64 = 200;
+
+/// Options for listing and searching the catalog
+#[derive(Debug, Default)]
+pub struct CatalogQuery {
+    /// Only include items with a name containing this, ignoring case
+    pub search: Option<String>,
+    pub limit: u64,
+    pub offset: u64,
+}
+
+impl CatalogQuery {
+    /// Apply the search and pagination to a query, searching the given column
+    fn apply<E: EntityTrait>(&self, query: Select<E>, column: impl ColumnTrait) -> Select<E> {
+        let query = match &self.search {
+            Some(search) => query.filter(Expr::expr(Func::lower(Expr::col(column))).like(
+                LikeExpr::new(format!("%{}%", escape_like(&search.to_lowercase()))).escape('\\'),
+            )),
+            None => query,
+        };
+        query
+            .order_by_asc(column)
+            .offset(self.offset)
+            .limit(self.limit.clamp(1, MAX_LIST_SIZE))
+    }
+}
+
+/// Escape the wildcards of a `LIKE` pattern, so they're matched as they are
+fn escape_like(search: &str) -> String {
+    let mut escaped = String::with_capacity(search.len());
+    for c in search.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[derive(Serialize, Debug)]
+pub struct ArtistListing {
+    #[serde(flatten)]
+    pub artist: ArtistSummary,
+    pub play_count: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AlbumListing {
+    #[serde(flatten)]
+    pub album: AlbumSummary,
+    pub play_count: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TrackListing {
+    #[serde(flatten)]
+    pub track: TrackSummary,
+    pub play_count: u64,
+}
+
+/// An artist with all of their albums
+#[derive(Serialize, Debug)]
+pub struct ArtistPage {
+    #[serde(flatten)]
+    pub artist: ArtistSummary,
+    pub play_count: u64,
+    pub albums: Vec<AlbumListing>,
+}
+
+/// An album with its artists and tracklist
+#[derive(Serialize, Debug)]
+pub struct AlbumPage {
+    #[serde(flatten)]
+    pub album: AlbumSummary,
+    pub play_count: u64,
+    pub artists: Vec<ArtistSummary>,
+    pub tracks: Vec<TrackListing>,
+}
+
+/// A track with the albums it is on and their artists
+#[derive(Serialize, Debug)]
+pub struct TrackPage {
+    #[serde(flatten)]
+    pub track: TrackSummary,
+    pub play_count: u64,
+    pub albums: Vec<AlbumSummary>,
+    pub artists: Vec<ArtistSummary>,
+}
+
+#[derive(FromQueryResult)]
+struct PlayCount {
+    id: i32,
+    play_count: i64,
+}
+
+/// A query for a user's plays, joined all the way to the artists of the played tracks
+fn user_plays(user_id: &str) -> Select<play_log::Entity> {
+    play_log::Entity::find()
+        .select_only()
+        .join(JoinType::InnerJoin, play_log::Relation::Track.def())
+        .join(JoinType::InnerJoin, track::Relation::AlbumTrack.def())
+        .join(JoinType::InnerJoin, album_track::Relation::Album.def())
+        .join(JoinType::InnerJoin, album::Relation::AlbumArtist.def())
+        .filter(play_log::Column::UserId.eq(user_id))
+}
+
+/// Count the plays of a user grouped by the given column, keyed by the column's value
+async fn count_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    column: impl ColumnTrait,
+    ids: Vec<i32>,
+) -> Result<HashMap<i32, u64>, DBError> {
+    // A track can be on multiple albums, so make sure each play is only counted once
+    user_plays(user_id)
+        .column_as(column, "id")
+        .column_as(Expr::cust("COUNT(DISTINCT play_log.id)"), "play_count")
+        .filter(column.is_in(ids))
+        .group_by(column)
+        .into_model::<PlayCount>()
+        .all(conn)
+        .await
+        .map(|counts| {
+            counts
+                .into_iter()
+                .map(|count| (count.id, count.play_count as u64))
+                .collect()
+        })
+        .map_err(|sea_err| {
+            error!("Error counting plays: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// Count a user's plays of each artist
+pub async fn count_artist_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    artist_ids: Vec<i32>,
+) -> Result<HashMap<i32, u64>, DBError> {
+    count_plays(conn, user_id, album_artist::Column::ArtistId, artist_ids).await
+}
+
+/// Count a user's plays of each album
+pub async fn count_album_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    album_ids: Vec<i32>,
+) -> Result<HashMap<i32, u64>, DBError> {
+    count_plays(conn, user_id, album_track::Column::AlbumId, album_ids).await
+}
+
+/// Count a user's plays of each track
+pub async fn count_track_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    track_ids: Vec<i32>,
+) -> Result<HashMap<i32, u64>, DBError> {
+    count_plays(conn, user_id, play_log::Column::TrackId, track_ids).await
+}
+
+/// List or search artists, with the user's play count for each
+pub async fn list_artists(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    query: &CatalogQuery,
+) -> Result<Vec<ArtistListing>, DBError> {
+    let artists = query
+        .apply(artist::Entity::find(), artist::Column::Name)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error listing artists: {:?}", sea_err);
+            DBError
+        })?;
+    let ids = artists.iter().map(|artist| artist.id).collect();
+    let counts = count_artist_plays(conn, user_id, ids).await?;
+    Ok(artists
+        .into_iter()
+        .map(|artist| ArtistListing {
+            play_count: counts.get(&artist.id).copied().unwrap_or_default(),
+            artist: artist.into(),
+        })
+        .collect())
+}
+
+/// List or search albums, with the user's play count for each
+pub async fn list_albums(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    query: &CatalogQuery,
+) -> Result<Vec<AlbumListing>, DBError> {
+    let albums = query
+        .apply(album::Entity::find(), album::Column::Title)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error listing albums: {:?}", sea_err);
+            DBError
+        })?;
+    let ids = albums.iter().map(|album| album.id).collect();
+    let counts = count_album_plays(conn, user_id, ids).await?;
+    Ok(albums
+        .into_iter()
+        .map(|album| AlbumListing {
+            play_count: counts.get(&album.id).copied().unwrap_or_default(),
+            album: album.into(),
+        })
+        .collect())
+}
+
+/// List or search tracks, with the user's play count for each
+pub async fn list_tracks(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    query: &CatalogQuery,
+) -> Result<Vec<TrackListing>, DBError> {
+    let tracks = query
+        .apply(track::Entity::find(), track::Column::Title)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error listing tracks: {:?}", sea_err);
+            DBError
+        })?;
+    let ids = tracks.iter().map(|track| track.id).collect();
+    let counts = count_track_plays(conn, user_id, ids).await?;
+    Ok(tracks
+        .into_iter()
+        .map(|track| TrackListing {
+            play_count: counts.get(&track.id).copied().unwrap_or_default(),
+            track: track.into(),
+        })
+        .collect())
+}
+
+/// Get an artist with their albums, returning None if the artist doesn't exist
+pub async fn get_artist(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    artist_id: i32,
+) -> Result<Option<ArtistPage>, DBError> {
+    let Some(artist) = artist::Entity::find_by_id(artist_id)
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up artist: {:?}", sea_err);
+            DBError
+        })?
+    else {
+        return Ok(None);
+    };
+    // Artists are related to albums through album_artist, albums without a release date go last
+    let albums = artist
+        .find_related(album::Entity)
+        .order_by_with_nulls(album::Column::ReleaseDate, Order::Desc, NullOrdering::Last)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up artist albums: {:?}", sea_err);
+            DBError
+        })?;
+    let artist_counts = count_artist_plays(conn, user_id, vec![artist.id]).await?;
+    let album_ids = albums.iter().map(|album| album.id).collect();
+    let album_counts = count_album_plays(conn, user_id, album_ids).await?;
+    Ok(Some(ArtistPage {
+        play_count: artist_counts.get(&artist.id).copied().unwrap_or_default(),
+        artist: artist.into(),
+        albums: albums
+            .into_iter()
+            .map(|album| AlbumListing {
+                play_count: album_counts.get(&album.id).copied().unwrap_or_default(),
+                album: album.into(),
+            })
+            .collect(),
+    }))
+}
+
+/// Get an album with its artists and tracklist, returning None if the album doesn't exist
+pub async fn get_album(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    album_id: i32,
+) -> Result<Option<AlbumPage>, DBError> {
+    let Some(album) = album::Entity::find_by_id(album_id)
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up album: {:?}", sea_err);
+            DBError
+        })?
+    else {
+        return Ok(None);
+    };
+    // Albums are related to artists through album_artist, and tracks through album_track
+    let artists = album
+        .find_related(artist::Entity)
+        .order_by_asc(artist::Column::Name)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up album artists: {:?}", sea_err);
+            DBError
+        })?;
+    let tracks = album
+        .find_related(track::Entity)
+        .order_by_asc(track::Column::Id)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up album tracks: {:?}", sea_err);
+            DBError
+        })?;
+    let album_counts = count_album_plays(conn, user_id, vec![album.id]).await?;
+    let track_ids = tracks.iter().map(|track| track.id).collect();
+    let track_counts = count_track_plays(conn, user_id, track_ids).await?;
+    Ok(Some(AlbumPage {
+        play_count: album_counts.get(&album.id).copied().unwrap_or_default(),
+        album: album.into(),
+        artists: artists.into_iter().map(ArtistSummary::from).collect(),
+        tracks: tracks
+            .into_iter()
+            .map(|track| TrackListing {
+                play_count: track_counts.get(&track.id).copied().unwrap_or_default(),
+                track: track.into(),
+            })
+            .collect(),
+    }))
+}
+
+/// Get a track with its albums and artists, returning None if the track doesn't exist
+pub async fn get_track(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    track_id: i32,
+) -> Result<Option<TrackPage>, DBError> {
+    let Some(track) = track::Entity::find_by_id(track_id)
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up track: {:?}", sea_err);
+            DBError
+        })?
+    else {
+        return Ok(None);
+    };
+    // Tracks are related to albums through album_track
+    let albums = track
+        .find_related(album::Entity)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up track albums: {:?}", sea_err);
+            DBError
+        })?;
+    let album_ids: Vec<i32> = albums.iter().map(|album| album.id).collect();
+    let artists = artist::Entity::find()
+        .inner_join(album::Entity)
+        .filter(album::Column::Id.is_in(album_ids))
+        .distinct()
+        .order_by_asc(artist::Column::Name)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up track artists: {:?}", sea_err);
+            DBError
+        })?;
+    let counts = count_track_plays(conn, user_id, vec![track.id]).await?;
+    Ok(Some(TrackPage {
+        play_count: counts.get(&track.id).copied().unwrap_or_default(),
+        track: track.into(),
+        albums: albums.into_iter().map(AlbumSummary::from).collect(),
+        artists: artists.into_iter().map(ArtistSummary::from).collect(),
+    }))
+}
+
+/// The number of catalog rows removed because nothing referenced them anymore
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PrunedCatalog {
+    pub tracks: u64,
+    pub albums: u64,
+    pub artists: u64,
+    pub genres: u64,
+}
+
+/// The catalog rows a user's plays referenced, which are the only ones pruning looks at once the user is deleted
+/// Pruning only what the user played keeps it away from rows a collect of another user just saved,
+/// and is about to save plays of
+#[derive(FromQueryResult, Debug, Default, Clone, PartialEq, Eq)]
+pub struct PlayedCatalog {
+    pub track_ids: Vec<i32>,
+    pub album_ids: Vec<i32>,
+    pub artist_ids: Vec<i32>,
+    pub genre_ids: Vec<i32>,
+}
+
+/// Get the tracks a user played, along with their albums, artists and genres
+pub async fn get_played_catalog<C: ConnectionTrait>(
+    conn: &C,
+    user_id: &str,
+) -> Result<PlayedCatalog, DBError> {
+    PlayedCatalog::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"WITH tracks AS (
+            SELECT track_id AS id FROM play_log WHERE user_id = $1
+            UNION SELECT track_id FROM play_session WHERE user_id = $1
+        ),
+        albums AS (
+            SELECT DISTINCT album_id AS id FROM album_track WHERE track_id IN (SELECT id FROM tracks)
+        ),
+        artists AS (
+            SELECT DISTINCT artist_id AS id FROM album_artist WHERE album_id IN (SELECT id FROM albums)
+        ),
+        genres AS (
+            SELECT DISTINCT genre_id AS id FROM artist_genre WHERE artist_id IN (SELECT id FROM artists)
+        )
+        SELECT ARRAY(SELECT id FROM tracks) AS track_ids,
+            ARRAY(SELECT id FROM albums) AS album_ids,
+            ARRAY(SELECT id FROM artists) AS artist_ids,
+            ARRAY(SELECT id FROM genres) AS genre_ids"#,
+        [user_id.into()],
+    ))
+    .one(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error looking up played catalog: {:?}", sea_err);
+        DBError
+    })
+    .map(Option::unwrap_or_default)
+}
+
+/// Remove the played tracks nobody plays anymore, then the albums, artists and genres left without any tracks
+/// The links between them cascade, so only the catalog rows themselves are deleted
+pub async fn prune_catalog<C: ConnectionTrait>(
+    conn: &C,
+    played: PlayedCatalog,
+) -> Result<PrunedCatalog, DBError> {
+    // Each step can leave rows of the next one unreferenced, so they run in order
+    let steps = [
+        (
+            r#"DELETE FROM track
+            WHERE track.id = ANY($1)
+                AND NOT EXISTS (SELECT 1 FROM play_log WHERE play_log.track_id = track.id)
+                AND NOT EXISTS (SELECT 1 FROM play_session WHERE play_session.track_id = track.id)"#,
+            played.track_ids,
+        ),
+        (
+            r#"DELETE FROM album
+            WHERE album.id = ANY($1)
+                AND NOT EXISTS (SELECT 1 FROM album_track WHERE album_track.album_id = album.id)"#,
+            played.album_ids,
+        ),
+        (
+            r#"DELETE FROM artist
+            WHERE artist.id = ANY($1)
+                AND NOT EXISTS (SELECT 1 FROM album_artist WHERE album_artist.artist_id = artist.id)"#,
+            played.artist_ids,
+        ),
+        (
+            r#"DELETE FROM genre
+            WHERE genre.id = ANY($1)
+                AND NOT EXISTS (SELECT 1 FROM artist_genre WHERE artist_genre.genre_id = genre.id)"#,
+            played.genre_ids,
+        ),
+    ];
+    let mut removed = [0; 4];
+    for (step, (sql, ids)) in steps.into_iter().enumerate() {
+        removed[step] = conn
+            .execute(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                sql,
+                [ids.into()],
+            ))
+            .await
+            .map_err(|sea_err| {
+                error!("Error pruning catalog: {:?}", sea_err);
+                DBError
+            })?
+            .rows_affected();
+    }
+    let [tracks, albums, artists, genres] = removed;
+    Ok(PrunedCatalog {
+        tracks,
+        albums,
+        artists,
+        genres,
+    })
+}
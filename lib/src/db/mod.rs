@@ -1,9 +1,20 @@
+pub mod admin;
+pub mod api_key;
+pub mod catalog;
+pub mod play_log;
+pub mod play_session;
+pub mod playlist;
+pub mod report;
+pub mod session;
+pub mod settings;
 pub mod spotify;
 pub mod user;
 
 use sea_orm::{ConnectOptions, Database, DatabaseConnection};
 use std::time::Duration;
 
+use crate::config::DatabaseConfig;
+
 #[derive(Debug)]
 pub struct DBError;
 
@@ -13,17 +24,18 @@ impl std::fmt::Display for DBError {
     }
 }
 
+impl std::error::Error for DBError {}
+
 // A function for getting a pool of database connections
-pub async fn get_connection() -> Result<DatabaseConnection, DBError> {
-    let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let mut opt = ConnectOptions::new(&db_url);
-    opt.max_connections(100)
-        .min_connections(5)
-        .connect_timeout(Duration::from_secs(8))
-        .acquire_timeout(Duration::from_secs(8))
-        .idle_timeout(Duration::from_secs(8))
-        .max_lifetime(Duration::from_secs(8))
-        .sqlx_logging(true);
+pub async fn get_connection(config: &DatabaseConfig) -> Result<DatabaseConnection, DBError> {
+    let mut opt = ConnectOptions::new(&config.url);
+    opt.max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs))
+        .idle_timeout(Duration::from_secs(config.idle_timeout_secs))
+        .max_lifetime(Duration::from_secs(config.max_lifetime_secs))
+        .sqlx_logging(config.sqlx_logging);
 
     Database::connect(opt).await.map_err(|_| DBError)
 }
@@ -0,0 +1,218 @@
+use entity::{album_artist, album_track, play_log};
+use migration::{Expr, Query};
+use sea_orm::{
+    prelude::DateTime, ColumnTrait, DatabaseConnection, EntityTrait, FromQueryResult, QueryFilter,
+    QueryOrder, QuerySelect, Select,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use tracing::error;
+
+use crate::db::{
+    catalog::{self, AlbumSummary, ArtistSummary, TrackSummary},
+    DBError,
+};
+
+/// The most plays that can be fetched in a single page
+pub const MAX_PAGE_SIZE: u64 = 200;
+
+/// Filters for looking up a user's plays
+#[derive(Debug, Default)]
+pub struct PlayFilter {
+    /// Only include plays before this time, used as the cursor for paginating
+    pub before: Option<DateTime>,
+    /// Only include plays at or after this time
+    pub from: Option<DateTime>,
+    /// Only include plays before this time
+    pub to: Option<DateTime>,
+    pub artist_id: Option<i32>,
+    pub album_id: Option<i32>,
+    pub track_id: Option<i32>,
+    /// Only include plays collected from this linked account
+    pub account_id: Option<i32>,
+}
+
+impl PlayFilter {
+    /// Apply the filters to a play log query
+    pub fn apply(&self, mut query: Select<play_log::Entity>) -> Select<play_log::Entity> {
+        if let Some(before) = self.before {
+            query = query.filter(play_log::Column::PlayedAt.lt(before));
+        }
+        if let Some(from) = self.from {
+            query = query.filter(play_log::Column::PlayedAt.gte(from));
+        }
+        if let Some(to) = self.to {
+            query = query.filter(play_log::Column::PlayedAt.lt(to));
+        }
+        if let Some(account_id) = self.account_id {
+            query = query.filter(play_log::Column::AccountId.eq(account_id));
+        }
+        if let Some(track_id) = self.track_id {
+            query = query.filter(play_log::Column::TrackId.eq(track_id));
+        }
+        // Tracks are related to albums through album_track
+        if let Some(album_id) = self.album_id {
+            query = query.filter(
+                play_log::Column::TrackId.in_subquery(
+                    Query::select()
+                        .column(album_track::Column::TrackId)
+                        .from(album_track::Entity)
+                        .and_where(album_track::Column::AlbumId.eq(album_id))
+                        .to_owned(),
+                ),
+            );
+        }
+        // Tracks are related to artists through the artists of their album
+        if let Some(artist_id) = self.artist_id {
+            query = query.filter(
+                play_log::Column::TrackId.in_subquery(
+                    Query::select()
+                        .column((album_track::Entity, album_track::Column::TrackId))
+                        .from(album_track::Entity)
+                        .inner_join(
+                            album_artist::Entity,
+                            Expr::col((album_artist::Entity, album_artist::Column::AlbumId))
+                                .equals((album_track::Entity, album_track::Column::AlbumId)),
+                        )
+                        .and_where(
+                            Expr::col((album_artist::Entity, album_artist::Column::ArtistId))
+                                .eq(artist_id),
+                        )
+                        .to_owned(),
+                ),
+            );
+        }
+        query
+    }
+}
+
+/// A single play along with what was played
+#[derive(Serialize, Debug)]
+pub struct PlayRecord {
+    pub id: i32,
+    pub played_at: DateTime,
+    /// The linked account the play was collected from, missing if the account was unlinked
+    pub account_id: Option<i32>,
+    pub track: TrackSummary,
+    pub album: Option<AlbumSummary>,
+    pub artists: Vec<ArtistSummary>,
+}
+
+/// A page of plays, newest first
+#[derive(Serialize, Debug)]
+pub struct PlayPage {
+    pub items: Vec<PlayRecord>,
+    /// Pass as `before` to get the next page, missing on the last page
+    pub next_cursor: Option<DateTime>,
+}
+
+/// Get a page of a user's plays, newest first, with the track, album and artists of each play
+pub async fn get_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    filter: &PlayFilter,
+    limit: u64,
+) -> Result<PlayPage, DBError> {
+    let limit = limit.clamp(1, MAX_PAGE_SIZE);
+    // Fetch one more than the limit to know if there is another page
+    let mut plays = filter
+        .apply(play_log::Entity::find().filter(play_log::Column::UserId.eq(user_id)))
+        .order_by_desc(play_log::Column::PlayedAt)
+        .limit(limit + 1)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up plays: {:?}", sea_err);
+            DBError
+        })?;
+    let next_cursor = match plays.len() as u64 > limit {
+        true => {
+            plays.truncate(limit as usize);
+            plays.last().map(|play| play.played_at)
+        }
+        false => None,
+    };
+    // Look up what was played
+    let track_ids = plays.iter().map(|play| play.track_id).collect();
+    let details = catalog::get_track_details(conn, track_ids).await?;
+    let items = plays
+        .into_iter()
+        .filter_map(|play| {
+            let details = details.get(&play.track_id)?.clone();
+            Some(PlayRecord {
+                id: play.id,
+                played_at: play.played_at,
+                account_id: play.account_id,
+                track: details.track,
+                album: details.album,
+                artists: details.artists,
+            })
+        })
+        .collect();
+    Ok(PlayPage { items, next_cursor })
+}
+
+/// Get a user's earliest play matching the filter, with what was played
+pub async fn get_first_play(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    filter: &PlayFilter,
+) -> Result<Option<PlayRecord>, DBError> {
+    let play = filter
+        .apply(play_log::Entity::find().filter(play_log::Column::UserId.eq(user_id)))
+        .order_by_asc(play_log::Column::PlayedAt)
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up first play: {:?}", sea_err);
+            DBError
+        })?;
+    let Some(play) = play else {
+        return Ok(None);
+    };
+    let details = catalog::get_track_details(conn, vec![play.track_id]).await?;
+    Ok(details
+        .get(&play.track_id)
+        .cloned()
+        .map(|details| PlayRecord {
+            id: play.id,
+            played_at: play.played_at,
+            account_id: play.account_id,
+            track: details.track,
+            album: details.album,
+            artists: details.artists,
+        }))
+}
+
+#[derive(FromQueryResult)]
+struct AccountPlayCount {
+    account_id: i32,
+    play_count: i64,
+}
+
+/// Count a user's plays collected from each of their linked accounts, keyed by the account ID
+pub async fn count_account_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+) -> Result<HashMap<i32, u64>, DBError> {
+    play_log::Entity::find()
+        .select_only()
+        .column(play_log::Column::AccountId)
+        .column_as(play_log::Column::Id.count(), "play_count")
+        .filter(play_log::Column::UserId.eq(user_id))
+        .filter(play_log::Column::AccountId.is_not_null())
+        .group_by(play_log::Column::AccountId)
+        .into_model::<AccountPlayCount>()
+        .all(conn)
+        .await
+        .map(|counts| {
+            counts
+                .into_iter()
+                .map(|count| (count.account_id, count.play_count as u64))
+                .collect()
+        })
+        .map_err(|sea_err| {
+            error!("Error counting account plays: {:?}", sea_err);
+            DBError
+        })
+}
@@ -0,0 +1,33 @@
+use entity::play_session;
+use migration::OnConflict;
+use sea_orm::{DatabaseConnection, EntityTrait};
+use tracing::{debug, error};
+
+use crate::db::DBError;
+
+/// A function for saving a finished play session
+/// Sessions that were already saved for the user are ignored
+pub async fn insert_play_session(
+    session: play_session::ActiveModel,
+    conn: &DatabaseConnection,
+) -> Result<(), DBError> {
+    play_session::Entity::insert(session)
+        .on_conflict(
+            OnConflict::columns([
+                play_session::Column::UserId,
+                play_session::Column::StartedAt,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .do_nothing()
+        .exec(conn)
+        .await
+        .map_err(|db_err| {
+            error!("Error inserting play session: {:?}", db_err);
+            DBError
+        })
+        .map(|_| {
+            debug!("Inserted play session");
+        })
+}
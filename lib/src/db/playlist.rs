@@ -0,0 +1,109 @@
+use entity::{playlist, track};
+use migration::OnConflict;
+use sea_orm::{
+    sqlx::types::chrono::Utc, ActiveValue::NotSet, ColumnTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, QueryOrder, Set,
+};
+use std::collections::HashMap;
+use tracing::error;
+
+use crate::{db::DBError, music::spotify::TRACK_URI_PREFIX};
+
+/// Options when saving a generated playlist
+pub struct SavePlaylistOptions {
+    pub user_id: String,
+    pub account_id: i32,
+    pub kind: String,
+    pub provider_id: String,
+    pub name: String,
+    pub track_count: i32,
+}
+
+/// Save a generated playlist, replacing the playlist of the same kind on the account
+pub async fn save_playlist(
+    conn: &DatabaseConnection,
+    opts: SavePlaylistOptions,
+) -> Result<playlist::Model, DBError> {
+    let playlist = playlist::ActiveModel {
+        id: NotSet,
+        user_id: Set(opts.user_id),
+        account_id: Set(opts.account_id),
+        kind: Set(opts.kind),
+        provider_id: Set(opts.provider_id),
+        name: Set(opts.name),
+        track_count: Set(opts.track_count),
+        created_at: NotSet,
+        updated_at: Set(Utc::now().naive_utc()),
+    };
+    playlist::Entity::insert(playlist)
+        .on_conflict(
+            OnConflict::columns([playlist::Column::AccountId, playlist::Column::Kind])
+                .update_columns([
+                    playlist::Column::ProviderId,
+                    playlist::Column::Name,
+                    playlist::Column::TrackCount,
+                    playlist::Column::UpdatedAt,
+                ])
+                .to_owned(),
+        )
+        .exec_with_returning(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error saving playlist: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// Get the generated playlist of a kind on an account
+pub async fn get_playlist(
+    conn: &DatabaseConnection,
+    account_id: i32,
+    kind: &str,
+) -> Result<Option<playlist::Model>, DBError> {
+    playlist::Entity::find()
+        .filter(playlist::Column::AccountId.eq(account_id))
+        .filter(playlist::Column::Kind.eq(kind))
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up playlist: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// Get all the playlists generated for a user, most recently updated first
+pub async fn get_user_playlists(
+    conn: &DatabaseConnection,
+    user_id: &str,
+) -> Result<Vec<playlist::Model>, DBError> {
+    playlist::Entity::find()
+        .filter(playlist::Column::UserId.eq(user_id))
+        .order_by_desc(playlist::Column::UpdatedAt)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up playlists: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// Look up the Spotify URIs of tracks, keyed by the track ID
+/// Tracks without a Spotify URI are left out, like local files and tracks only scrobbled from other providers
+pub async fn get_track_uris(
+    conn: &DatabaseConnection,
+    ids: Vec<i32>,
+) -> Result<HashMap<i32, String>, DBError> {
+    let tracks = track::Entity::find()
+        .filter(track::Column::Id.is_in(ids))
+        .filter(track::Column::Uri.starts_with(TRACK_URI_PREFIX))
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up track URIs: {:?}", sea_err);
+            DBError
+        })?;
+    Ok(tracks
+        .into_iter()
+        .filter_map(|track| Some((track.id, track.uri?)))
+        .collect())
+}
@@ -0,0 +1,54 @@
+use entity::report;
+use migration::OnConflict;
+use sea_orm::{
+    sqlx::types::chrono::Utc, ActiveValue::NotSet, ColumnTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, Set,
+};
+use tracing::error;
+
+use crate::db::DBError;
+
+/// Save a snapshot of a user's report for a year, replacing any existing snapshot
+pub async fn save_report(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    year: i32,
+    data: serde_json::Value,
+) -> Result<report::Model, DBError> {
+    let report = report::ActiveModel {
+        id: NotSet,
+        user_id: Set(user_id.to_string()),
+        year: Set(year),
+        data: Set(data),
+        created_at: Set(Utc::now().naive_utc()),
+    };
+    report::Entity::insert(report)
+        .on_conflict(
+            OnConflict::columns([report::Column::UserId, report::Column::Year])
+                .update_columns([report::Column::Data, report::Column::CreatedAt])
+                .to_owned(),
+        )
+        .exec_with_returning(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error saving report: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// Get the saved snapshot of a user's report for a year
+pub async fn get_report(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    year: i32,
+) -> Result<Option<report::Model>, DBError> {
+    report::Entity::find()
+        .filter(report::Column::UserId.eq(user_id))
+        .filter(report::Column::Year.eq(year))
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up report: {:?}", sea_err);
+            DBError
+        })
+}
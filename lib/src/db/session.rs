@@ -0,0 +1,58 @@
+use chrono::{Duration, Utc};
+use entity::{session, user};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use tracing::error;
+
+use crate::db::DBError;
+
+/// Create a new session for a user lasting `ttl_days`, returning the session with its ID
+pub async fn create_session(
+    conn: &DatabaseConnection,
+    user_id: String,
+    ttl_days: i64,
+) -> Result<session::Model, DBError> {
+    let now = Utc::now().naive_utc();
+    let session = session::ActiveModel {
+        id: Set(uuid::Uuid::new_v4().simple().to_string()),
+        user_id: Set(user_id),
+        created_at: Set(now),
+        expires_at: Set(now + Duration::days(ttl_days)),
+    };
+    session::Entity::insert(session)
+        .exec_with_returning(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error creating session: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// Get the user a session belongs to, if the session exists and hasn't expired
+pub async fn get_session_user(
+    conn: &DatabaseConnection,
+    session_id: &str,
+) -> Result<Option<user::Model>, DBError> {
+    let now = Utc::now().naive_utc();
+    session::Entity::find_by_id(session_id)
+        .filter(session::Column::ExpiresAt.gt(now))
+        .find_also_related(user::Entity)
+        .one(conn)
+        .await
+        .map(|session| session.and_then(|(_, user)| user))
+        .map_err(|sea_err| {
+            error!("Error looking up session: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// Delete a session, logging the user out
+pub async fn delete_session(conn: &DatabaseConnection, session_id: &str) -> Result<(), DBError> {
+    session::Entity::delete_by_id(session_id)
+        .exec(conn)
+        .await
+        .map(|_| ())
+        .map_err(|sea_err| {
+            error!("Error deleting session: {:?}", sea_err);
+            DBError
+        })
+}
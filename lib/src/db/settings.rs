@@ -0,0 +1,168 @@
+use chrono::Weekday;
+use chrono_tz::Tz;
+use entity::user_settings;
+use migration::OnConflict;
+use sea_orm::{sqlx::types::chrono::Utc, DatabaseConnection, EntityTrait, Set};
+use serde::{Deserialize, Serialize, Serializer};
+use tracing::{error, warn};
+
+use crate::db::DBError;
+
+/// A user's preferences for how their plays are bucketed and shown
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct UserSettings {
+    /// The timezone plays are put into days and hours in
+    pub timezone: Tz,
+    /// The first day of the week
+    #[serde(serialize_with = "serialize_weekday")]
+    pub week_start: Weekday,
+    /// A BCP 47 language tag like `en-US`, used for formatting
+    pub locale: String,
+    /// Whether other users of the instance can compare their listening with the user's
+    pub allow_comparisons: bool,
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        Self {
+            timezone: Tz::UTC,
+            week_start: Weekday::Mon,
+            locale: "en-US".to_string(),
+            allow_comparisons: false,
+        }
+    }
+}
+
+impl From<user_settings::Model> for UserSettings {
+    fn from(model: user_settings::Model) -> Self {
+        let defaults = Self::default();
+        // The settings are validated before saving, so this only falls back if the data was changed by hand
+        Self {
+            timezone: model.timezone.parse().unwrap_or_else(|_| {
+                warn!("Invalid timezone saved for {}", model.user_id);
+                defaults.timezone
+            }),
+            week_start: model.week_start.parse().unwrap_or_else(|_| {
+                warn!("Invalid week start saved for {}", model.user_id);
+                defaults.week_start
+            }),
+            locale: model.locale,
+            allow_comparisons: model.allow_comparisons,
+        }
+    }
+}
+
+/// Changes to a user's settings, leaving out the settings that aren't changing
+#[derive(Deserialize, Debug, Default)]
+pub struct SettingsUpdate {
+    pub timezone: Option<String>,
+    pub week_start: Option<String>,
+    pub locale: Option<String>,
+    pub allow_comparisons: Option<bool>,
+}
+
+impl UserSettings {
+    /// Apply changes to the settings, validating each of them
+    pub fn apply(mut self, update: SettingsUpdate) -> Result<Self, String> {
+        if let Some(timezone) = update.timezone {
+            self.timezone = timezone
+                .parse()
+                .map_err(|_| format!("Unknown timezone {}", timezone))?;
+        }
+        if let Some(week_start) = update.week_start {
+            self.week_start = week_start
+                .parse()
+                .map_err(|_| format!("Unknown week start {}", week_start))?;
+        }
+        if let Some(locale) = update.locale {
+            if !is_locale(&locale) {
+                return Err(format!("Invalid locale {}", locale));
+            }
+            self.locale = locale;
+        }
+        if let Some(allow_comparisons) = update.allow_comparisons {
+            self.allow_comparisons = allow_comparisons;
+        }
+        Ok(self)
+    }
+}
+
+/// A loose check for a BCP 47 language tag, like `en` or `pt-BR`
+fn is_locale(locale: &str) -> bool {
+    let mut subtags = locale.split('-');
+    let language_valid = subtags.next().is_some_and(|language| {
+        (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_alphabetic())
+    });
+    language_valid
+        && locale.len() <= 35
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+/// Weekdays are saved and shown by their full lowercase name
+fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "monday",
+        Weekday::Tue => "tuesday",
+        Weekday::Wed => "wednesday",
+        Weekday::Thu => "thursday",
+        Weekday::Fri => "friday",
+        Weekday::Sat => "saturday",
+        Weekday::Sun => "sunday",
+    }
+}
+
+fn serialize_weekday<S: Serializer>(weekday: &Weekday, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(weekday_name(*weekday))
+}
+
+/// Get a user's settings, using the defaults if they haven't saved any
+pub async fn get_user_settings(
+    conn: &DatabaseConnection,
+    user_id: &str,
+) -> Result<UserSettings, DBError> {
+    let model = user_settings::Entity::find_by_id(user_id)
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up user settings: {:?}", sea_err);
+            DBError
+        })?;
+    Ok(model.map(UserSettings::from).unwrap_or_default())
+}
+
+/// Save a user's settings, replacing any previously saved settings
+pub async fn save_user_settings(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    settings: &UserSettings,
+) -> Result<UserSettings, DBError> {
+    let model = user_settings::ActiveModel {
+        user_id: Set(user_id.to_string()),
+        timezone: Set(settings.timezone.name().to_string()),
+        week_start: Set(weekday_name(settings.week_start).to_string()),
+        locale: Set(settings.locale.clone()),
+        allow_comparisons: Set(settings.allow_comparisons),
+        updated_at: Set(Utc::now().naive_utc()),
+    };
+    user_settings::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(user_settings::Column::UserId)
+                .update_columns([
+                    user_settings::Column::Timezone,
+                    user_settings::Column::WeekStart,
+                    user_settings::Column::Locale,
+                    user_settings::Column::AllowComparisons,
+                    user_settings::Column::UpdatedAt,
+                ])
+                .to_owned(),
+        )
+        .exec_with_returning(conn)
+        .await
+        .map(UserSettings::from)
+        .map_err(|sea_err| {
+            error!("Error saving user settings: {:?}", sea_err);
+            DBError
+        })
+}
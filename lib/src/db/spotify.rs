@@ -1,8 +1,12 @@
-use entity::{album, album_artist, album_track, artist, play_log, track};
+use chrono::Duration;
+use entity::{album, album_artist, album_track, artist, artist_genre, genre, play_log, track};
 use migration::{Expr, IntoCondition, OnConflict};
 use sea_orm::{
-    ActiveValue, Condition, DatabaseConnection, EntityTrait, QueryFilter, TransactionTrait,
+    prelude::DateTime, ActiveValue, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection,
+    DatabaseTransaction, DbBackend, EntityTrait, FromQueryResult, NotSet, QueryFilter, QueryOrder,
+    SqlErr, Statement, TransactionTrait, UpdateMany,
 };
+use std::collections::HashMap;
 use tokio::task::JoinSet;
 use tracing::{debug, error};
 
@@ -60,45 +64,63 @@ pub async fn upsert_artists(
 }
 
 /// A function for upserting albums and their artists into the database
-/// Returns all the IDs of the albums
+/// Returns the saved album of each album given, in the same order
 /// The album_artists are the artists that are associated with the album
 pub async fn upsert_albums_with_artists(
     albums_with_artists: Vec<(album::ActiveModel, Vec<artist::Model>)>,
     conn: &DatabaseConnection,
 ) -> Result<Vec<album::Model>, DBError> {
+    // The same album shows up once for every track played from it, only save it once
+    // Albums of different artists can share a title, so the artists are part of what makes an album unique
+    let mut unique_albums: Vec<(album::ActiveModel, Vec<artist::Model>)> = vec![];
+    let mut album_indexes: Vec<usize> = vec![];
+    for (album, artists) in albums_with_artists {
+        let index = unique_albums
+            .iter()
+            .position(|(saved, saved_artists)| *saved == album && *saved_artists == artists);
+        album_indexes.push(index.unwrap_or_else(|| {
+            unique_albums.push((album, artists));
+            unique_albums.len() - 1
+        }));
+    }
     // First, create individual queries for each album with its artists
-    type AlbumSaveResult = JoinSet<Result<album::Model, DBError>>;
+    type AlbumSaveResult = JoinSet<(usize, Result<album::Model, DBError>)>;
     let mut album_queries: AlbumSaveResult = JoinSet::new();
-    albums_with_artists
+    unique_albums
         .into_iter()
-        .for_each(|(album, artists)| {
+        .enumerate()
+        .for_each(|(index, (album, artists))| {
             let album_conn = conn.clone();
             let album_query = insert_album_with_artists(album, artists, album_conn);
-            album_queries.spawn(album_query);
+            album_queries.spawn(async move { (index, album_query.await) });
         });
     // Execute each query, saving albums
-    let mut albums: Vec<album::Model> = vec![];
+    let mut albums: Vec<Option<album::Model>> = vec![None; album_queries.len()];
     while let Some(res) = album_queries.join_next().await {
         match res {
-            Ok(Ok(album_model)) => {
-                debug!("Album was inserted: {:?}", album_model);
-                albums.push(album_model);
+            Ok((index, Ok(album_model))) => {
+                debug!("Album was upserted: {:?}", album_model);
+                albums[index] = Some(album_model);
             }
-            Ok(Err(db_err)) => {
-                error!("Error inserting album: {:?}", db_err);
+            Ok((_, Err(db_err))) => {
+                error!("Error upserting album: {:?}", db_err);
                 return Err(DBError);
             }
             Err(join_err) => {
-                error!("Error joining album insert: {:?}", join_err);
+                error!("Error joining album upsert: {:?}", join_err);
                 return Err(DBError);
             }
         }
     }
-    // Return the albums that were inserted
-    Ok(albums)
+    // Return the albums that were upserted, one for each album given
+    album_indexes
+        .into_iter()
+        .map(|index| albums[index].clone().ok_or(DBError))
+        .collect()
 }
 
-/// An internal function for composing a transaction to insert an album with its artists
+/// An internal function for composing a transaction to upsert an album with its artists
+/// Albums are matched on their URI, so saving an album again returns the one that was already saved
 async fn insert_album_with_artists(
     album: album::ActiveModel,
     artists: Vec<artist::Model>,
@@ -109,14 +131,22 @@ async fn insert_album_with_artists(
         error!("Error starting transaction for album: {:?}", sea_err);
         DBError
     })?;
-    // Insert the album
-    let album_model = album::Entity::insert(album)
-        .exec_with_returning(&txn)
-        .await
-        .map_err(|sea_err| {
-            error!("Error inserting album: {:?}", sea_err);
-            DBError
-        })?;
+    // Find the album if it was already saved, otherwise insert it
+    let album_model = match find_album(&album, &artists, &txn).await? {
+        Some(album_model) => album_model,
+        None => album::Entity::insert(album)
+            .on_conflict(
+                OnConflict::column(album::Column::Uri)
+                    .update_column(album::Column::Title)
+                    .to_owned(),
+            )
+            .exec_with_returning(&txn)
+            .await
+            .map_err(|sea_err| {
+                error!("Error inserting album: {:?}", sea_err);
+                DBError
+            })?,
+    };
     // Convert artists to album_artists
     let album_artists: Vec<album_artist::ActiveModel> = artists
         .into_iter()
@@ -125,8 +155,17 @@ async fn insert_album_with_artists(
             artist_id: ActiveValue::set(artist.id),
         })
         .collect();
-    // Insert the album artists
+    // Insert the album artists the album doesn't have yet
     album_artist::Entity::insert_many(album_artists)
+        .on_conflict(
+            OnConflict::columns([
+                album_artist::Column::AlbumId,
+                album_artist::Column::ArtistId,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .do_nothing()
         .exec(&txn)
         .await
         .map_err(|sea_err| {
@@ -141,46 +180,124 @@ async fn insert_album_with_artists(
     Ok(album_model)
 }
 
+/// An internal function for finding the album an album to save was already saved as
+/// Albums saved before they had a URI are claimed by the first album with the same title, release date and artist,
+/// so their plays aren't split from the ones collected from now on
+async fn find_album(
+    album: &album::ActiveModel,
+    artists: &[artist::Model],
+    txn: &DatabaseTransaction,
+) -> Result<Option<album::Model>, DBError> {
+    let uri = album.uri.clone().unwrap();
+    if let Some(uri) = &uri {
+        let saved = album::Entity::find()
+            .filter(album::Column::Uri.eq(uri))
+            .one(txn)
+            .await
+            .map_err(|sea_err| {
+                error!("Error looking up album: {:?}", sea_err);
+                DBError
+            })?;
+        if saved.is_some() {
+            return Ok(saved);
+        }
+    }
+    let release_date = match album.release_date.clone().unwrap() {
+        Some(release_date) => album::Column::ReleaseDate.eq(release_date),
+        None => album::Column::ReleaseDate.is_null(),
+    };
+    let legacy = album::Entity::find()
+        .inner_join(album_artist::Entity)
+        .filter(album::Column::Uri.is_null())
+        .filter(album::Column::Title.eq(album.title.clone().unwrap()))
+        .filter(release_date)
+        .filter(album_artist::Column::ArtistId.is_in(artists.iter().map(|artist| artist.id)))
+        .order_by_asc(album::Column::Id)
+        .one(txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up album without a URI: {:?}", sea_err);
+            DBError
+        })?;
+    match (legacy, uri) {
+        (Some(legacy), Some(uri)) => {
+            let claimed = claim_uri(
+                album::Entity::update_many()
+                    .col_expr(album::Column::Uri, Expr::value(uri.clone()))
+                    .filter(album::Column::Id.eq(legacy.id))
+                    .filter(album::Column::Uri.is_null()),
+                txn,
+            )
+            .await?;
+            Ok(claimed.then_some(album::Model {
+                uri: Some(uri),
+                ..legacy
+            }))
+        }
+        // Albums without a URI, like those of local files, are only ever matched on their title, release date and artist
+        (legacy, None) => Ok(legacy),
+        (None, Some(_)) => Ok(None),
+    }
+}
+
 /// A function for upserting tracks with their albums
-/// Returns all the IDs of the tracks that were upserted
+/// Returns the saved track of each track given, in the same order
 /// The album_id is the ID of the album that the track is associated with
 pub async fn upsert_tracks_with_albums(
     tracks_with_ablums: Vec<(track::ActiveModel, album::Model)>,
     conn: &DatabaseConnection,
 ) -> Result<Vec<track::Model>, DBError> {
+    // A track played more than once shows up once for every play, only save it once
+    let mut unique_tracks: Vec<(track::ActiveModel, album::Model)> = vec![];
+    let mut track_indexes: Vec<usize> = vec![];
+    for (track, album) in tracks_with_ablums {
+        let index = unique_tracks
+            .iter()
+            .position(|(saved, saved_album)| *saved == track && saved_album.id == album.id);
+        track_indexes.push(index.unwrap_or_else(|| {
+            unique_tracks.push((track, album));
+            unique_tracks.len() - 1
+        }));
+    }
     // First, create individual queries for each track with its album
-    type TrackSaveResult = JoinSet<Result<track::Model, DBError>>;
+    type TrackSaveResult = JoinSet<(usize, Result<track::Model, DBError>)>;
     let mut track_queries: TrackSaveResult = JoinSet::new();
-    tracks_with_ablums.into_iter().for_each(|(track, album)| {
-        let track_conn = conn.clone();
-        let track_query = insert_track_with_album(track, album, track_conn);
-        track_queries.spawn(track_query);
-    });
+    unique_tracks
+        .into_iter()
+        .enumerate()
+        .for_each(|(index, (track, album))| {
+            let track_conn = conn.clone();
+            let track_query = insert_track_with_album(track, album, track_conn);
+            track_queries.spawn(async move { (index, track_query.await) });
+        });
     // Execute each query, saving tracks
-    let mut tracks: Vec<track::Model> = vec![];
+    let mut tracks: Vec<Option<track::Model>> = vec![None; track_queries.len()];
     while let Some(res) = track_queries.join_next().await {
         match res {
-            Ok(Ok(track_model)) => {
-                debug!("Track was inserted: {:?}", track_model);
-                tracks.push(track_model);
+            Ok((index, Ok(track_model))) => {
+                debug!("Track was upserted: {:?}", track_model);
+                tracks[index] = Some(track_model);
             }
-            Ok(Err(db_err)) => {
-                error!("Error inserting track: {:?}", db_err);
+            Ok((_, Err(db_err))) => {
+                error!("Error upserting track: {:?}", db_err);
                 return Err(DBError);
             }
             Err(join_err) => {
-                error!("Error joining track insert: {:?}", join_err);
+                error!("Error joining track upsert: {:?}", join_err);
                 return Err(DBError);
             }
         }
     }
-    // Return the tracks that were inserted
-    Ok(tracks)
+    // Return the tracks that were upserted, one for each track given
+    track_indexes
+        .into_iter()
+        .map(|index| tracks[index].clone().ok_or(DBError))
+        .collect()
 }
 
-/// An internal function for composing a transaction to insert a track with its album
-/// The album_id is the ID of the album that the track is associated with
-/// The track is the track to insert
+/// An internal function for composing a transaction to upsert a track with its album
+/// Tracks are matched on their URI, so saving a track again returns the one that was already saved
+/// The album is the album that the track is associated with
 async fn insert_track_with_album(
     track: track::ActiveModel,
     album: album::Model,
@@ -191,19 +308,33 @@ async fn insert_track_with_album(
         error!("Error starting transaction for track: {:?}", sea_err);
         DBError
     })?;
-    // Insert the track
-    let track_model = track::Entity::insert(track)
-        .exec_with_returning(&txn)
-        .await
-        .map_err(|sea_err| {
-            error!("Error inserting track: {:?}", sea_err);
-            DBError
-        })?;
-    // Insert the track album
+    // Find the track if it was already saved, otherwise insert it
+    let track_model = match find_track(&track, &album, &txn).await? {
+        Some(track_model) => track_model,
+        None => track::Entity::insert(track)
+            .on_conflict(
+                OnConflict::column(track::Column::Uri)
+                    .update_columns([track::Column::Title, track::Column::DurationMs])
+                    .to_owned(),
+            )
+            .exec_with_returning(&txn)
+            .await
+            .map_err(|sea_err| {
+                error!("Error inserting track: {:?}", sea_err);
+                DBError
+            })?,
+    };
+    // Insert the track album, unless the track already has it
     album_track::Entity::insert(album_track::ActiveModel {
         track_id: ActiveValue::set(track_model.id),
         album_id: ActiveValue::set(album.id),
     })
+    .on_conflict(
+        OnConflict::columns([album_track::Column::AlbumId, album_track::Column::TrackId])
+            .do_nothing()
+            .to_owned(),
+    )
+    .do_nothing()
     .exec(&txn)
     .await
     .map_err(|sea_err| {
@@ -218,14 +349,322 @@ async fn insert_track_with_album(
     Ok(track_model)
 }
 
+/// An internal function for finding the track a track to save was already saved as
+/// Tracks saved before they had a URI are claimed by the first track with the same title on the same album,
+/// so their plays aren't split from the ones collected from now on
+async fn find_track(
+    track: &track::ActiveModel,
+    album: &album::Model,
+    txn: &DatabaseTransaction,
+) -> Result<Option<track::Model>, DBError> {
+    let uri = track.uri.clone().unwrap();
+    if let Some(uri) = &uri {
+        let saved = get_track_by_uri(uri, txn).await?;
+        if saved.is_some() {
+            return Ok(saved);
+        }
+    }
+    let legacy = track::Entity::find()
+        .inner_join(album_track::Entity)
+        .filter(track::Column::Uri.is_null())
+        .filter(track::Column::Title.eq(track.title.clone().unwrap()))
+        .filter(album_track::Column::AlbumId.eq(album.id))
+        .order_by_asc(track::Column::Id)
+        .one(txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up track without a URI: {:?}", sea_err);
+            DBError
+        })?;
+    match (legacy, uri) {
+        (Some(legacy), Some(uri)) => {
+            let duration_ms = track.duration_ms.clone().unwrap();
+            let claimed = claim_uri(
+                track::Entity::update_many()
+                    .col_expr(track::Column::Uri, Expr::value(uri.clone()))
+                    .col_expr(track::Column::DurationMs, Expr::value(duration_ms))
+                    .filter(track::Column::Id.eq(legacy.id))
+                    .filter(track::Column::Uri.is_null()),
+                txn,
+            )
+            .await?;
+            Ok(claimed.then_some(track::Model {
+                uri: Some(uri),
+                duration_ms,
+                ..legacy
+            }))
+        }
+        // Tracks without a URI, like local files, are only ever matched on their title and album
+        (legacy, None) => Ok(legacy),
+        (None, Some(_)) => Ok(None),
+    }
+}
+
+/// An internal function for giving a URI to a catalog row saved before it had one
+/// Returns false if the row can't be claimed, because it was claimed first or the URI was saved in the meantime,
+/// in which case the row to save should be inserted instead
+async fn claim_uri<E: EntityTrait>(
+    update: UpdateMany<E>,
+    txn: &DatabaseTransaction,
+) -> Result<bool, DBError> {
+    // A savepoint keeps the transaction usable when the URI turns out to be taken
+    let savepoint = txn.begin().await.map_err(|sea_err| {
+        error!("Error starting savepoint for claiming a URI: {:?}", sea_err);
+        DBError
+    })?;
+    match update.exec(&savepoint).await {
+        Ok(res) => {
+            savepoint.commit().await.map_err(|sea_err| {
+                error!(
+                    "Error committing savepoint for claiming a URI: {:?}",
+                    sea_err
+                );
+                DBError
+            })?;
+            Ok(res.rows_affected == 1)
+        }
+        Err(sea_err)
+            if matches!(
+                sea_err.sql_err(),
+                Some(SqlErr::UniqueConstraintViolation(_))
+            ) =>
+        {
+            debug!("URI was saved in the meantime: {:?}", sea_err);
+            savepoint.rollback().await.map_err(|sea_err| {
+                error!(
+                    "Error rolling back savepoint for claiming a URI: {:?}",
+                    sea_err
+                );
+                DBError
+            })?;
+            Ok(false)
+        }
+        Err(sea_err) => {
+            error!("Error claiming a URI: {:?}", sea_err);
+            Err(DBError)
+        }
+    }
+}
+
+/// Get the track saved with a Spotify URI
+pub async fn get_track_by_uri<C: ConnectionTrait>(
+    uri: &str,
+    conn: &C,
+) -> Result<Option<track::Model>, DBError> {
+    track::Entity::find()
+        .filter(track::Column::Uri.eq(uri))
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up track by URI: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// How much further apart than the length of a song plays from different accounts can be to still be one listen
+/// Spotify saves when a play ended and scrobblers when it started, and their clocks can be a little off
+const SAME_LISTEN_SLACK_SECS: i64 = 60;
+
+/// The length assumed for songs whose length isn't known, like most scrobbled tracks
+const UNKNOWN_DURATION_MS: i64 = 10 * 60 * 1000;
+
+/// The furthest apart plays can be to be one listen, so very long tracks don't swallow the plays around them
+const MAX_SAME_LISTEN_SECS: i64 = 60 * 60;
+
+/// A track along with what's needed to tell whether tracks from different providers are the same song
+#[derive(FromQueryResult, Debug, Clone, PartialEq)]
+pub struct Song {
+    pub track_id: i32,
+    pub title: String,
+    pub duration_ms: Option<i32>,
+    /// The artists of the albums the track is on
+    pub artist_ids: Vec<i32>,
+}
+
+impl Song {
+    /// Whether both are the same song, either the same track or tracks with the same title by the same artist
+    /// Providers save their own copy of a song, like a Spotify track and the track scrobbled from it on Last.fm
+    fn is(&self, other: &Song) -> bool {
+        self.track_id == other.track_id
+            || (self.title.to_lowercase() == other.title.to_lowercase()
+                && self
+                    .artist_ids
+                    .iter()
+                    .any(|artist_id| other.artist_ids.contains(artist_id)))
+    }
+}
+
+/// A play of a song from one of the accounts of a user
+#[derive(Debug, Clone, PartialEq)]
+pub struct SongPlay {
+    pub account_id: Option<i32>,
+    pub played_at: DateTime,
+    pub song: Song,
+}
+
+impl SongPlay {
+    /// Whether this play is a listen that was already saved from another account
+    /// Plays from the same account are always their own listens, like a song played on repeat
+    pub fn is_listen_of(&self, saved: &SongPlay) -> bool {
+        if self.account_id == saved.account_id || !self.song.is(&saved.song) {
+            return false;
+        }
+        let duration_ms = self
+            .song
+            .duration_ms
+            .max(saved.song.duration_ms)
+            .map(i64::from)
+            .unwrap_or(UNKNOWN_DURATION_MS);
+        let window = (Duration::milliseconds(duration_ms)
+            + Duration::seconds(SAME_LISTEN_SLACK_SECS))
+        .min(Duration::seconds(MAX_SAME_LISTEN_SECS));
+        (self.played_at - saved.played_at).abs() <= window
+    }
+}
+
+#[derive(FromQueryResult, Debug)]
+struct SavedPlay {
+    account_id: Option<i32>,
+    played_at: DateTime,
+    track_id: i32,
+}
+
+/// Look up the songs of tracks, keyed by the track ID
+async fn get_songs(
+    conn: &DatabaseConnection,
+    track_ids: Vec<i32>,
+) -> Result<HashMap<i32, Song>, DBError> {
+    let songs = Song::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT track.id AS track_id, track.title, track.duration_ms,
+            COALESCE(
+                ARRAY_AGG(DISTINCT album_artist.artist_id) FILTER (WHERE album_artist.artist_id IS NOT NULL),
+                '{}'
+            ) AS artist_ids
+        FROM track
+        LEFT JOIN album_track ON album_track.track_id = track.id
+        LEFT JOIN album_artist ON album_artist.album_id = album_track.album_id
+        WHERE track.id = ANY($1)
+        GROUP BY track.id"#,
+        [track_ids.into()],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error looking up songs: {:?}", sea_err);
+        DBError
+    })?;
+    Ok(songs
+        .into_iter()
+        .map(|song| (song.track_id, song))
+        .collect())
+}
+
+/// Leave out the plays of a user that are listens already saved from another of their accounts
+/// The same listen is collected from Spotify and from the scrobbler it was scrobbled to, at different times
+async fn without_saved_listens(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    playlogs: Vec<play_log::ActiveModel>,
+) -> Result<Vec<play_log::ActiveModel>, DBError> {
+    let played_at = playlogs
+        .iter()
+        .map(|playlog| playlog.played_at.clone().unwrap());
+    let (Some(from), Some(to)) = (played_at.clone().min(), played_at.max()) else {
+        return Ok(playlogs);
+    };
+    let window = Duration::seconds(MAX_SAME_LISTEN_SECS);
+    let saved = SavedPlay::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT play_log.account_id, play_log.played_at, play_log.track_id
+        FROM play_log
+        WHERE play_log.user_id = $1 AND play_log.played_at BETWEEN $2 AND $3"#,
+        [user_id.into(), (from - window).into(), (to + window).into()],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error looking up saved plays: {:?}", sea_err);
+        DBError
+    })?;
+    if saved.is_empty() {
+        return Ok(playlogs);
+    }
+    let mut track_ids: Vec<i32> = playlogs
+        .iter()
+        .map(|playlog| playlog.track_id.clone().unwrap())
+        .chain(saved.iter().map(|play| play.track_id))
+        .collect();
+    track_ids.sort();
+    track_ids.dedup();
+    let songs = get_songs(conn, track_ids).await?;
+    let song_play = |account_id: Option<i32>, played_at: DateTime, track_id: i32| {
+        Some(SongPlay {
+            account_id,
+            played_at,
+            song: songs.get(&track_id)?.clone(),
+        })
+    };
+    let saved: Vec<SongPlay> = saved
+        .into_iter()
+        .filter_map(|play| song_play(play.account_id, play.played_at, play.track_id))
+        .collect();
+    Ok(playlogs
+        .into_iter()
+        .filter(|playlog| {
+            let play = song_play(
+                playlog.account_id.clone().unwrap(),
+                playlog.played_at.clone().unwrap(),
+                playlog.track_id.clone().unwrap(),
+            );
+            let Some(play) = play else {
+                return true;
+            };
+            match saved.iter().find(|saved| play.is_listen_of(saved)) {
+                Some(saved) => {
+                    debug!(
+                        "Play of track {} at {} was already saved at {}",
+                        play.song.track_id, play.played_at, saved.played_at
+                    );
+                    false
+                }
+                None => true,
+            }
+        })
+        .collect())
+}
+
 /// A function for upserting play logs
+/// Plays already saved from the same account are matched on when they were played,
+/// and plays already saved from another account on the song and about when it was played
 pub async fn upsert_playlogs(
     playlogs: Vec<play_log::ActiveModel>,
     conn: &DatabaseConnection,
 ) -> Result<(), DBError> {
-    play_log::Entity::insert_many(playlogs)
+    // The plays of each user are only compared with their own history
+    let mut users_playlogs: HashMap<Option<String>, Vec<play_log::ActiveModel>> = HashMap::new();
+    for playlog in playlogs {
+        users_playlogs
+            .entry(playlog.user_id.clone().unwrap())
+            .or_default()
+            .push(playlog);
+    }
+    let mut new_playlogs = vec![];
+    for (user_id, playlogs) in users_playlogs {
+        match user_id {
+            Some(user_id) => {
+                new_playlogs.extend(without_saved_listens(conn, &user_id, playlogs).await?)
+            }
+            None => new_playlogs.extend(playlogs),
+        }
+    }
+    if new_playlogs.is_empty() {
+        debug!("No new play logs to insert");
+        return Ok(());
+    }
+    play_log::Entity::insert_many(new_playlogs)
         .on_conflict(
-            OnConflict::column(play_log::Column::PlayedAt)
+            OnConflict::columns([play_log::Column::UserId, play_log::Column::PlayedAt])
                 .do_nothing()
                 .to_owned(),
         )
@@ -240,3 +679,150 @@ pub async fn upsert_playlogs(
             debug!("Inserted play logs");
         })
 }
+
+/// A function for upserting the genres of artists
+/// Genres are matched by their name, and artists keep any genres they already had
+pub async fn upsert_artist_genres(
+    artist_genres: Vec<(artist::Model, Vec<String>)>,
+    conn: &DatabaseConnection,
+) -> Result<(), DBError> {
+    let mut genre_names: Vec<String> = artist_genres
+        .iter()
+        .flat_map(|(_, genres)| genres.clone())
+        .collect();
+    genre_names.sort();
+    genre_names.dedup();
+    if genre_names.is_empty() {
+        return Ok(());
+    }
+    // Insert the genres, then look them up to get their IDs
+    let genres = genre_names.iter().map(|name| genre::ActiveModel {
+        id: NotSet,
+        name: ActiveValue::set(name.clone()),
+    });
+    genre::Entity::insert_many(genres)
+        .on_conflict(
+            OnConflict::column(genre::Column::Name)
+                .do_nothing()
+                .to_owned(),
+        )
+        .do_nothing()
+        .exec(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error inserting genres: {:?}", sea_err);
+            DBError
+        })?;
+    let db_genres = genre::Entity::find()
+        .filter(genre::Column::Name.is_in(genre_names))
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up genres: {:?}", sea_err);
+            DBError
+        })?;
+    // Link the artists to their genres
+    let links: Vec<artist_genre::ActiveModel> = artist_genres
+        .iter()
+        .flat_map(|(artist, genres)| {
+            db_genres
+                .iter()
+                .filter(|db_genre| genres.contains(&db_genre.name))
+                .map(|db_genre| artist_genre::ActiveModel {
+                    artist_id: ActiveValue::set(artist.id),
+                    genre_id: ActiveValue::set(db_genre.id),
+                })
+        })
+        .collect();
+    artist_genre::Entity::insert_many(links)
+        .on_conflict(
+            OnConflict::columns([
+                artist_genre::Column::ArtistId,
+                artist_genre::Column::GenreId,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .do_nothing()
+        .exec(conn)
+        .await
+        .map(|_| {
+            debug!("Inserted artist genres");
+        })
+        .map_err(|sea_err| {
+            error!("Error inserting artist genres: {:?}", sea_err);
+            DBError
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn song(track_id: i32, title: &str, duration_ms: Option<i32>, artist_ids: &[i32]) -> Song {
+        Song {
+            track_id,
+            title: title.to_string(),
+            duration_ms,
+            artist_ids: artist_ids.to_vec(),
+        }
+    }
+
+    /// A play a number of seconds into the test
+    fn play(account_id: i32, seconds: i64, song: &Song) -> SongPlay {
+        SongPlay {
+            account_id: Some(account_id),
+            played_at: NaiveDate::from_ymd_opt(2024, 1, 1)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap()
+                + Duration::seconds(seconds),
+            song: song.clone(),
+        }
+    }
+
+    #[test]
+    fn scrobbles_of_spotify_plays_are_one_listen() {
+        let spotify = song(1, "Airbag", Some(284_000), &[7]);
+        let scrobbled = song(2, "airbag", None, &[7]);
+        // Spotify saves the end of the play, the scrobbler the start
+        let ended = play(1, 284, &spotify);
+        let started = play(2, 0, &scrobbled);
+        assert!(started.is_listen_of(&ended));
+        assert!(ended.is_listen_of(&started));
+        assert!(play(2, 0, &spotify).is_listen_of(&ended));
+    }
+
+    #[test]
+    fn plays_from_the_same_account_are_their_own_listens() {
+        let spotify = song(1, "Airbag", Some(284_000), &[7]);
+        assert!(!play(1, 284, &spotify).is_listen_of(&play(1, 0, &spotify)));
+    }
+
+    #[test]
+    fn different_songs_are_different_listens() {
+        let intro = song(1, "Intro", Some(120_000), &[7]);
+        let other_intro = song(2, "Intro", Some(120_000), &[8]);
+        let other_song = song(3, "Hunter", Some(120_000), &[7]);
+        let played = play(1, 0, &intro);
+        assert!(!play(2, 10, &other_intro).is_listen_of(&played));
+        assert!(!play(2, 10, &other_song).is_listen_of(&played));
+    }
+
+    #[test]
+    fn plays_further_apart_than_the_song_are_different_listens() {
+        let spotify = song(1, "Airbag", Some(284_000), &[7]);
+        let scrobbled = song(2, "Airbag", None, &[7]);
+        let played = play(1, 0, &spotify);
+        assert!(play(2, 284 + 60, &scrobbled).is_listen_of(&played));
+        assert!(!play(2, 284 + 61, &scrobbled).is_listen_of(&played));
+        // Without a length either side, plays are compared over a long song
+        let unknown = song(3, "Airbag", None, &[7]);
+        assert!(play(1, 0, &scrobbled).is_listen_of(&play(2, 600, &unknown)));
+        assert!(!play(1, 0, &scrobbled).is_listen_of(&play(2, 661, &unknown)));
+        // And very long tracks are capped
+        let long = song(4, "Airbag", Some(3 * 60 * 60 * 1000), &[7]);
+        assert!(!play(1, 0, &long).is_listen_of(&play(2, 3601, &scrobbled)));
+    }
+}
@@ -1,46 +1,814 @@
-use entity::{account, user};
-use sea_orm::{ActiveValue::NotSet, DatabaseConnection, EntityTrait, Set};
+use crypto::{Keyring, TokenColumn, WrappedKey};
+use entity::{
+    account, api_key, play_log, play_session, playlist, report, session, user, user_settings,
+};
+use sea_orm::{
+    sqlx::types::chrono::Utc, ActiveValue::NotSet, ColumnTrait, ConnectionTrait,
+    DatabaseConnection, DbBackend, EntityTrait, FromQueryResult, QueryFilter, QueryOrder,
+    QuerySelect, Set, Statement, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
 
-/// Options when creating a user with an account
-struct CreateUserOptions {
-    email: String,
-    name: String,
+use crate::db::{
+    catalog::{self, PrunedCatalog},
+    DBError,
+};
+
+fn keyring() -> Result<&'static Keyring, DBError> {
+    crypto::keyring().map_err(|crypto_err| {
+        error!("Error getting token keys: {}", crypto_err);
+        DBError
+    })
+}
+
+/// The tokens of an account encrypted with its data key, along with the wrapped data key
+struct SealedTokens {
     access_token: String,
     refresh_token: String,
-    provider: String,
-    provider_id: String,
+    key: WrappedKey,
+}
+
+/// Encrypt the tokens of an account with its data key, or with a new data key if it doesn't have one
+/// The tokens are bound to the account, so they can only be decrypted as the tokens of that account
+fn seal_tokens(
+    account_id: i32,
+    access_token: &str,
+    refresh_token: &str,
+    key: Option<WrappedKey>,
+) -> Result<SealedTokens, DBError> {
+    let keyring = keyring()?;
+    let (data_key, key) = match key {
+        Some(key) => keyring.unwrap(&key).map(|data_key| (data_key, key)),
+        None => keyring.new_data_key(),
+    }
+    .map_err(|crypto_err| {
+        error!("Error getting account data key: {}", crypto_err);
+        DBError
+    })?;
+    let encrypt = |column: TokenColumn, token: &str| {
+        data_key
+            .encrypt(account_id, column, token)
+            .map_err(|crypto_err| {
+                error!("Error encrypting account token: {}", crypto_err);
+                DBError
+            })
+    };
+    Ok(SealedTokens {
+        access_token: encrypt(TokenColumn::AccessToken, access_token)?,
+        refresh_token: encrypt(TokenColumn::RefreshToken, refresh_token)?,
+        key,
+    })
+}
+
+/// Decrypt the tokens of an account as it was stored, so callers only ever see plain tokens
+fn open_account(mut account: account::Model) -> Result<account::Model, DBError> {
+    let data_key = keyring()?
+        .unwrap(&WrappedKey {
+            key_id: account.key_id.clone(),
+            data_key: account.data_key.clone(),
+        })
+        .map_err(|crypto_err| {
+            error!(
+                "Error unwrapping key of account {}: {}",
+                account.id, crypto_err
+            );
+            DBError
+        })?;
+    let decrypt = |column: TokenColumn, sealed: &str| {
+        data_key
+            .decrypt(account.id, column, sealed)
+            .map_err(|crypto_err| {
+                error!(
+                    "Error decrypting token of account {}: {}",
+                    account.id, crypto_err
+                );
+                DBError
+            })
+    };
+    account.access_token = decrypt(TokenColumn::AccessToken, &account.access_token)?;
+    account.refresh_token = decrypt(TokenColumn::RefreshToken, &account.refresh_token)?;
+    Ok(account)
+}
+
+#[derive(FromQueryResult)]
+struct NextId {
+    id: i32,
+}
+
+/// Take the ID the next account will be inserted with, its tokens are bound to it before it's inserted
+async fn next_account_id<C: ConnectionTrait>(conn: &C) -> Result<i32, DBError> {
+    NextId::find_by_statement(Statement::from_string(
+        DbBackend::Postgres,
+        "SELECT CAST(nextval(pg_get_serial_sequence('account', 'id')) AS INTEGER) AS id",
+    ))
+    .one(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error taking account ID: {:?}", sea_err);
+        DBError
+    })?
+    .map(|next| next.id)
+    .ok_or(DBError)
+}
+
+/// What a user is allowed to do on the instance
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can manage every user and account on the instance
+    Admin,
+    Member,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Member => "member",
+        }
+    }
+
+    /// The role of a user, treating anything unknown as a member so it never grants more access
+    pub fn of(user: &user::Model) -> Self {
+        match user.role.as_str() {
+            "admin" => Role::Admin,
+            _ => Role::Member,
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "admin" => Ok(Role::Admin),
+            "member" => Ok(Role::Member),
+            _ => Err(format!("Unknown role {}", role)),
+        }
+    }
+}
+
+/// Where a linked account's listening history comes from, stored as the account's `provider`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// Can log in, and is polled for what's playing on top of its recent tracks
+    Spotify,
+    /// Only links to a user that's logged in, its scrobbles are collected
+    Lastfm,
+    /// Only links to a user that's logged in with a user token, its listens are collected
+    ListenBrainz,
+}
+
+impl Provider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Provider::Spotify => "spotify",
+            Provider::Lastfm => "lastfm",
+            Provider::ListenBrainz => "listenbrainz",
+        }
+    }
+
+    /// The name of the provider as it's shown to users
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::Spotify => "Spotify",
+            Provider::Lastfm => "Last.fm",
+            Provider::ListenBrainz => "ListenBrainz",
+        }
+    }
+}
+
+impl std::fmt::Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for Provider {
+    type Err = String;
+
+    fn from_str(provider: &str) -> Result<Self, Self::Err> {
+        match provider {
+            "spotify" => Ok(Provider::Spotify),
+            "lastfm" => Ok(Provider::Lastfm),
+            "listenbrainz" => Ok(Provider::ListenBrainz),
+            _ => Err(format!("Unknown provider {}", provider)),
+        }
+    }
+}
+
+/// Options when creating a user with an account
+pub struct CreateUserOptions {
+    pub email: String,
+    pub name: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub provider: String,
+    pub provider_id: String,
 }
 
 /// Create a user with an account connected
 pub async fn create_user_with_account(
     conn: &DatabaseConnection,
     opts: CreateUserOptions,
-) -> (user::Model, account::Model) {
+) -> Result<(user::Model, account::Model), DBError> {
+    // Create the user and account together, so a user never exists without an account
+    let txn = conn.begin().await.map_err(|sea_err| {
+        error!("Error starting transaction for user: {:?}", sea_err);
+        DBError
+    })?;
     // Create the user first
     let user = user::ActiveModel {
-        id: NotSet,
+        id: Set(uuid::Uuid::new_v4().to_string()),
         email: Set(opts.email),
         name: Set(opts.name),
         created_at: NotSet,
         updated_at: NotSet,
+        role: Set(Role::Member.to_string()),
     };
     let user_model = user::Entity::insert(user)
-        .exec_with_returning(conn)
+        .exec_with_returning(&txn)
         .await
-        .expect("Failed to create user");
+        .map_err(|sea_err| {
+            error!("Error creating user: {:?}", sea_err);
+            DBError
+        })?;
     let user_id = user_model.id.clone();
+    let account_id = next_account_id(&txn).await?;
+    let sealed = seal_tokens(account_id, &opts.access_token, &opts.refresh_token, None)?;
     let account = account::ActiveModel {
-        id: NotSet,
+        id: Set(account_id),
         user_id: Set(user_id),
-        access_token: Set(opts.access_token),
-        refresh_token: Set(opts.refresh_token),
+        access_token: Set(sealed.access_token),
+        refresh_token: Set(sealed.refresh_token),
         provider: Set(opts.provider),
         provider_id: Set(opts.provider_id),
+        key_id: Set(sealed.key.key_id),
+        data_key: Set(sealed.key.data_key),
+        disabled_at: NotSet,
+        last_collection_at: NotSet,
+        last_collected_at: NotSet,
+        last_collection_error: NotSet,
     };
     let account_model = account::Entity::insert(account)
-        .exec_with_returning(conn)
+        .exec_with_returning(&txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error creating account: {:?}", sea_err);
+            DBError
+        })?;
+    txn.commit().await.map_err(|sea_err| {
+        error!("Error committing transaction for user: {:?}", sea_err);
+        DBError
+    })?;
+
+    Ok((user_model, open_account(account_model)?))
+}
+
+/// Create a user with an account, or if the account already exists, update its tokens
+/// Returns the user the account belongs to along with the account, or None if an admin disabled the account
+pub async fn upsert_user_with_account(
+    conn: &DatabaseConnection,
+    opts: CreateUserOptions,
+) -> Result<Option<(user::Model, account::Model)>, DBError> {
+    let existing = account::Entity::find()
+        .filter(account::Column::Provider.eq(&opts.provider))
+        .filter(account::Column::ProviderId.eq(&opts.provider_id))
+        .find_also_related(user::Entity)
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up account: {:?}", sea_err);
+            DBError
+        })?;
+    match existing {
+        // Disabled accounts can't be used to log in, so their tokens are left as they are
+        Some((account_model, Some(_))) if account_model.disabled_at.is_some() => Ok(None),
+        Some((account_model, Some(user_model))) => {
+            let account_model = update_account_tokens(
+                conn,
+                open_account(account_model)?,
+                opts.access_token,
+                Some(opts.refresh_token),
+            )
+            .await?;
+            Ok(Some((user_model, account_model)))
+        }
+        _ => create_user_with_account(conn, opts).await.map(Some),
+    }
+}
+
+/// Get a user by their ID
+pub async fn get_user(
+    conn: &DatabaseConnection,
+    user_id: &str,
+) -> Result<Option<user::Model>, DBError> {
+    user::Entity::find_by_id(user_id)
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up user: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// Get all the accounts connected with a provider, leaving out the ones that were disabled
+pub async fn get_accounts_by_provider(
+    conn: &DatabaseConnection,
+    provider: &str,
+) -> Result<Vec<account::Model>, DBError> {
+    account::Entity::find()
+        .filter(account::Column::Provider.eq(provider))
+        .filter(account::Column::DisabledAt.is_null())
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up accounts: {:?}", sea_err);
+            DBError
+        })?
+        .into_iter()
+        .map(open_account)
+        .collect()
+}
+
+/// Get the first account a user has connected with a provider
+pub async fn get_user_account(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    provider: &str,
+) -> Result<Option<account::Model>, DBError> {
+    account::Entity::find()
+        .filter(account::Column::UserId.eq(user_id))
+        .filter(account::Column::Provider.eq(provider))
+        .order_by_asc(account::Column::Id)
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up account: {:?}", sea_err);
+            DBError
+        })?
+        .map(open_account)
+        .transpose()
+}
+
+/// Get all the accounts a user has linked, in the order they were linked
+pub async fn get_user_accounts(
+    conn: &DatabaseConnection,
+    user_id: &str,
+) -> Result<Vec<account::Model>, DBError> {
+    account::Entity::find()
+        .filter(account::Column::UserId.eq(user_id))
+        .order_by_asc(account::Column::Id)
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up accounts: {:?}", sea_err);
+            DBError
+        })?
+        .into_iter()
+        .map(open_account)
+        .collect()
+}
+
+/// Get an account by its ID, as long as it belongs to the user
+pub async fn get_user_account_by_id(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    account_id: i32,
+) -> Result<Option<account::Model>, DBError> {
+    account::Entity::find_by_id(account_id)
+        .filter(account::Column::UserId.eq(user_id))
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up account: {:?}", sea_err);
+            DBError
+        })?
+        .map(open_account)
+        .transpose()
+}
+
+/// Options when linking another account to a user
+pub struct LinkAccountOptions {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub provider: String,
+    pub provider_id: String,
+}
+
+/// Link an account to a user, or if the user already linked it, update its tokens
+/// Returns `None` when the account is linked to another user, since an account can only belong to one
+pub async fn link_account(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    opts: LinkAccountOptions,
+) -> Result<Option<account::Model>, DBError> {
+    let existing = account::Entity::find()
+        .filter(account::Column::Provider.eq(&opts.provider))
+        .filter(account::Column::ProviderId.eq(&opts.provider_id))
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up account: {:?}", sea_err);
+            DBError
+        })?;
+    match existing {
+        Some(account_model) if account_model.user_id != user_id => Ok(None),
+        Some(account_model) => update_account_tokens(
+            conn,
+            open_account(account_model)?,
+            opts.access_token,
+            Some(opts.refresh_token),
+        )
+        .await
+        .map(Some),
+        None => {
+            let account_id = next_account_id(conn).await?;
+            let sealed = seal_tokens(account_id, &opts.access_token, &opts.refresh_token, None)?;
+            let account = account::ActiveModel {
+                id: Set(account_id),
+                user_id: Set(user_id.to_string()),
+                access_token: Set(sealed.access_token),
+                refresh_token: Set(sealed.refresh_token),
+                provider: Set(opts.provider),
+                provider_id: Set(opts.provider_id),
+                key_id: Set(sealed.key.key_id),
+                data_key: Set(sealed.key.data_key),
+                disabled_at: NotSet,
+                last_collection_at: NotSet,
+                last_collected_at: NotSet,
+                last_collection_error: NotSet,
+            };
+            account::Entity::insert(account)
+                .exec_with_returning(conn)
+                .await
+                .map_err(|sea_err| {
+                    error!("Error linking account: {:?}", sea_err);
+                    DBError
+                })
+                .and_then(open_account)
+                .map(Some)
+        }
+    }
+}
+
+/// What happened when unlinking an account
+#[derive(Debug)]
+pub enum UnlinkOutcome {
+    Unlinked,
+    /// The user has no account with that ID
+    NotFound,
+    /// The account is the only one the user has, which would leave them unable to log in
+    LastAccount,
+}
+
+/// Unlink an account from a user, deleting its tokens and the playlists generated on it
+/// The plays collected from the account stay in the user's history, without the account attributed
+pub async fn unlink_account(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    account_id: i32,
+) -> Result<UnlinkOutcome, DBError> {
+    let txn = conn.begin().await.map_err(|sea_err| {
+        error!(
+            "Error starting transaction for unlinking account: {:?}",
+            sea_err
+        );
+        DBError
+    })?;
+    // The accounts are locked, so two accounts can't be unlinked at once leaving the user with none
+    let accounts = account::Entity::find()
+        .filter(account::Column::UserId.eq(user_id))
+        .lock_exclusive()
+        .all(&txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up accounts: {:?}", sea_err);
+            DBError
+        })?;
+    if !accounts.iter().any(|account| account.id == account_id) {
+        return Ok(UnlinkOutcome::NotFound);
+    }
+    if accounts.len() == 1 {
+        return Ok(UnlinkOutcome::LastAccount);
+    }
+    account::Entity::delete_by_id(account_id)
+        .exec(&txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error unlinking account: {:?}", sea_err);
+            DBError
+        })?;
+    txn.commit().await.map_err(|sea_err| {
+        error!(
+            "Error committing transaction for unlinking account: {:?}",
+            sea_err
+        );
+        DBError
+    })?;
+    Ok(UnlinkOutcome::Unlinked)
+}
+
+/// Set the role of a user, returning the updated user if they exist
+pub async fn set_user_role(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    role: Role,
+) -> Result<Option<user::Model>, DBError> {
+    let Some(user) = get_user(conn, user_id).await? else {
+        return Ok(None);
+    };
+    let mut user: user::ActiveModel = user.into();
+    user.role = Set(role.to_string());
+    user.updated_at = Set(Some(Utc::now().naive_utc()));
+    user::Entity::update(user)
+        .exec(conn)
+        .await
+        .map(Some)
+        .map_err(|sea_err| {
+            error!("Error updating user role: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// Get any account by its ID, no matter which user it belongs to
+pub async fn get_account(
+    conn: &DatabaseConnection,
+    account_id: i32,
+) -> Result<Option<account::Model>, DBError> {
+    account::Entity::find_by_id(account_id)
+        .one(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up account: {:?}", sea_err);
+            DBError
+        })?
+        .map(open_account)
+        .transpose()
+}
+
+/// Disable an account so nothing is collected from it anymore, or enable it again
+/// Returns whether there was an account to update
+pub async fn set_account_disabled(
+    conn: &DatabaseConnection,
+    account_id: i32,
+    disabled: bool,
+) -> Result<bool, DBError> {
+    let disabled_at = disabled.then(|| Utc::now().naive_utc());
+    account::Entity::update_many()
+        .col_expr(account::Column::DisabledAt, disabled_at.into())
+        .filter(account::Column::Id.eq(account_id))
+        .exec(conn)
         .await
-        .expect("Failed to create account");
+        .map(|res| res.rows_affected > 0)
+        .map_err(|sea_err| {
+            error!("Error disabling account: {:?}", sea_err);
+            DBError
+        })
+}
+
+/// Save how collecting an account went, with the error if it failed
+pub async fn record_collection(
+    conn: &DatabaseConnection,
+    account_id: i32,
+    collection_error: Option<String>,
+) -> Result<(), DBError> {
+    let now = Utc::now().naive_utc();
+    let mut update = account::Entity::update_many()
+        .col_expr(account::Column::LastCollectionAt, now.into())
+        .col_expr(
+            account::Column::LastCollectionError,
+            collection_error.clone().into(),
+        )
+        .filter(account::Column::Id.eq(account_id));
+    if collection_error.is_none() {
+        update = update.col_expr(account::Column::LastCollectedAt, now.into());
+    }
+    update.exec(conn).await.map(|_| ()).map_err(|sea_err| {
+        error!("Error recording collection: {:?}", sea_err);
+        DBError
+    })
+}
+
+/// Save new tokens for an account, keeping the existing refresh token if a new one isn't given
+pub async fn update_account_tokens(
+    conn: &DatabaseConnection,
+    account: account::Model,
+    access_token: String,
+    refresh_token: Option<String>,
+) -> Result<account::Model, DBError> {
+    let refresh_token = refresh_token.unwrap_or(account.refresh_token.clone());
+    let sealed = seal_tokens(
+        account.id,
+        &access_token,
+        &refresh_token,
+        Some(WrappedKey {
+            key_id: account.key_id.clone(),
+            data_key: account.data_key.clone(),
+        }),
+    )?;
+    let mut account: account::ActiveModel = account.into();
+    account.access_token = Set(sealed.access_token);
+    account.refresh_token = Set(sealed.refresh_token);
+    account::Entity::update(account)
+        .exec(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error updating account tokens: {:?}", sea_err);
+            DBError
+        })
+        .and_then(open_account)
+}
+
+/// Wrap the data keys of accounts again with the active master key, so old master keys can be removed
+/// The tokens themselves are left as they are, since their data keys don't change
+/// Returns how many accounts were rewrapped
+pub async fn rotate_token_keys(conn: &DatabaseConnection) -> Result<u64, DBError> {
+    let keyring = keyring()?;
+    let stale = account::Entity::find()
+        .filter(account::Column::KeyId.ne(keyring.active_key_id()))
+        .all(conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error looking up accounts to rotate: {:?}", sea_err);
+            DBError
+        })?;
+    let mut rotated = 0;
+    for account in stale {
+        let key = keyring
+            .rewrap(&WrappedKey {
+                key_id: account.key_id.clone(),
+                data_key: account.data_key.clone(),
+            })
+            .map_err(|crypto_err| {
+                error!(
+                    "Error rewrapping key of account {}: {}",
+                    account.id, crypto_err
+                );
+                DBError
+            })?;
+        let mut account: account::ActiveModel = account.into();
+        account.key_id = Set(key.key_id);
+        account.data_key = Set(key.data_key);
+        account::Entity::update(account)
+            .exec(conn)
+            .await
+            .map_err(|sea_err| {
+                error!("Error saving rotated account key: {:?}", sea_err);
+                DBError
+            })?;
+        rotated += 1;
+    }
+    if rotated > 0 {
+        info!("Rotated the token keys of {} accounts", rotated);
+    }
+    Ok(rotated)
+}
 
-    (user_model, account_model)
+/// The number of rows removed when a user was deleted
+#[derive(Serialize, Debug)]
+pub struct DeletedUser {
+    pub plays: u64,
+    pub play_sessions: u64,
+    pub sessions: u64,
+    pub api_keys: u64,
+    pub accounts: u64,
+    pub playlists: u64,
+    pub reports: u64,
+    pub settings: u64,
+    /// The catalog rows removed after the user was deleted, if the catalog was pruned
+    pub catalog: Option<PrunedCatalog>,
+}
+
+/// Delete a user along with everything that belongs to them, in a single transaction
+/// Deleting the accounts removes the provider tokens, so nothing can be collected for the user anymore
+/// When pruning, the catalog rows the user played that no other user has are removed as well
+pub async fn delete_user(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    prune_catalog: bool,
+) -> Result<DeletedUser, DBError> {
+    let txn = conn.begin().await.map_err(|sea_err| {
+        error!(
+            "Error starting transaction for deleting user: {:?}",
+            sea_err
+        );
+        DBError
+    })?;
+    // Only the catalog rows the user played are pruned, which has to be looked up before their plays are gone
+    let played = match prune_catalog {
+        true => Some(catalog::get_played_catalog(&txn, user_id).await?),
+        false => None,
+    };
+    // Most of these cascade from the user, but they're deleted explicitly to count what was removed
+    let plays = play_log::Entity::delete_many()
+        .filter(play_log::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error deleting plays: {:?}", sea_err);
+            DBError
+        })?
+        .rows_affected;
+    let play_sessions = play_session::Entity::delete_many()
+        .filter(play_session::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error deleting play sessions: {:?}", sea_err);
+            DBError
+        })?
+        .rows_affected;
+    let sessions = session::Entity::delete_many()
+        .filter(session::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error deleting sessions: {:?}", sea_err);
+            DBError
+        })?
+        .rows_affected;
+    let api_keys = api_key::Entity::delete_many()
+        .filter(api_key::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error deleting API keys: {:?}", sea_err);
+            DBError
+        })?
+        .rows_affected;
+    let playlists = playlist::Entity::delete_many()
+        .filter(playlist::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error deleting playlists: {:?}", sea_err);
+            DBError
+        })?
+        .rows_affected;
+    let accounts = account::Entity::delete_many()
+        .filter(account::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error deleting accounts: {:?}", sea_err);
+            DBError
+        })?
+        .rows_affected;
+    let reports = report::Entity::delete_many()
+        .filter(report::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error deleting reports: {:?}", sea_err);
+            DBError
+        })?
+        .rows_affected;
+    let settings = user_settings::Entity::delete_many()
+        .filter(user_settings::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error deleting settings: {:?}", sea_err);
+            DBError
+        })?
+        .rows_affected;
+    user::Entity::delete_by_id(user_id)
+        .exec(&txn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error deleting user: {:?}", sea_err);
+            DBError
+        })?;
+    let pruned = match played {
+        Some(played) => Some(catalog::prune_catalog(&txn, played).await?),
+        None => None,
+    };
+    txn.commit().await.map_err(|sea_err| {
+        error!(
+            "Error committing transaction for deleting user: {:?}",
+            sea_err
+        );
+        DBError
+    })?;
+    Ok(DeletedUser {
+        plays,
+        play_sessions,
+        sessions,
+        api_keys,
+        accounts,
+        playlists,
+        reports,
+        settings,
+        catalog: pruned,
+    })
 }
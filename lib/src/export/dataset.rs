@@ -0,0 +1,253 @@
+use arrow::{
+    array::{ArrayRef, Int32Builder, ListBuilder, StringBuilder, TimestampMicrosecondBuilder},
+    datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+    ipc::writer::FileWriter,
+    record_batch::RecordBatch,
+};
+use chrono::Datelike;
+use futures::TryStreamExt;
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use sea_orm::DatabaseConnection;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{debug, error};
+
+use crate::export::{stream_plays, ExportedPlay};
+
+/// The most plays that are buffered before they're written to a file
+pub const BATCH_SIZE: usize = 8192;
+
+/// The columnar formats a listening history dataset can be written in
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DatasetFormat {
+    #[default]
+    Parquet,
+    /// The Arrow IPC file format, also known as Feather
+    Arrow,
+}
+
+impl DatasetFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DatasetFormat::Parquet => "parquet",
+            DatasetFormat::Arrow => "arrow",
+        }
+    }
+}
+
+impl std::str::FromStr for DatasetFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "parquet" => Ok(DatasetFormat::Parquet),
+            "arrow" => Ok(DatasetFormat::Arrow),
+            _ => Err(format!("Unknown dataset format {}", format)),
+        }
+    }
+}
+
+/// The schema of the exported plays, matching the fields of `ExportedPlay`
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("play_id", DataType::Int32, false),
+        Field::new(
+            "played_at",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("track_id", DataType::Int32, false),
+        Field::new("track_title", DataType::Utf8, false),
+        Field::new("track_uri", DataType::Utf8, true),
+        Field::new("duration_ms", DataType::Int32, true),
+        Field::new("album_id", DataType::Int32, true),
+        Field::new("album_title", DataType::Utf8, true),
+        Field::new(
+            "artists",
+            DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+            false,
+        ),
+    ]))
+}
+
+/// The columns of the plays buffered for the next record batch
+struct PlayColumns {
+    play_id: Int32Builder,
+    played_at: TimestampMicrosecondBuilder,
+    track_id: Int32Builder,
+    track_title: StringBuilder,
+    track_uri: StringBuilder,
+    duration_ms: Int32Builder,
+    album_id: Int32Builder,
+    album_title: StringBuilder,
+    artists: ListBuilder<StringBuilder>,
+    len: usize,
+}
+
+impl PlayColumns {
+    fn new() -> Self {
+        PlayColumns {
+            play_id: Int32Builder::new(),
+            played_at: TimestampMicrosecondBuilder::new().with_timezone("UTC"),
+            track_id: Int32Builder::new(),
+            track_title: StringBuilder::new(),
+            track_uri: StringBuilder::new(),
+            duration_ms: Int32Builder::new(),
+            album_id: Int32Builder::new(),
+            album_title: StringBuilder::new(),
+            artists: ListBuilder::new(StringBuilder::new()),
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, play: &ExportedPlay) {
+        self.play_id.append_value(play.play_id);
+        self.played_at
+            .append_value(play.played_at.and_utc().timestamp_micros());
+        self.track_id.append_value(play.track_id);
+        self.track_title.append_value(&play.track_title);
+        self.track_uri.append_option(play.track_uri.as_deref());
+        self.duration_ms.append_option(play.duration_ms);
+        self.album_id.append_option(play.album_id);
+        self.album_title.append_option(play.album_title.as_deref());
+        for artist in &play.artists {
+            self.artists.values().append_value(artist);
+        }
+        self.artists.append(true);
+        self.len += 1;
+    }
+
+    /// Take the buffered plays as a record batch, leaving the columns empty
+    fn finish(&mut self, schema: SchemaRef) -> Result<RecordBatch, String> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.play_id.finish()),
+            Arc::new(self.played_at.finish()),
+            Arc::new(self.track_id.finish()),
+            Arc::new(self.track_title.finish()),
+            Arc::new(self.track_uri.finish()),
+            Arc::new(self.duration_ms.finish()),
+            Arc::new(self.album_id.finish()),
+            Arc::new(self.album_title.finish()),
+            Arc::new(self.artists.finish()),
+        ];
+        self.len = 0;
+        RecordBatch::try_new(schema, columns).map_err(|arrow_err| {
+            error!("Error building record batch: {:?}", arrow_err);
+            "Failed to build record batch".to_string()
+        })
+    }
+}
+
+/// A file being written for a single partition
+enum PartitionWriter {
+    Parquet(ArrowWriter<File>),
+    Arrow(FileWriter<File>),
+}
+
+impl PartitionWriter {
+    fn create(path: &Path, format: DatasetFormat, schema: SchemaRef) -> Result<Self, String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|io_err| format!("Failed to create {}: {}", dir.display(), io_err))?;
+        }
+        let file = File::create(path)
+            .map_err(|io_err| format!("Failed to create {}: {}", path.display(), io_err))?;
+        match format {
+            DatasetFormat::Parquet => {
+                let props = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                ArrowWriter::try_new(file, schema, Some(props))
+                    .map(PartitionWriter::Parquet)
+                    .map_err(|parquet_err| {
+                        format!("Failed to write {}: {}", path.display(), parquet_err)
+                    })
+            }
+            DatasetFormat::Arrow => FileWriter::try_new(file, &schema)
+                .map(PartitionWriter::Arrow)
+                .map_err(|arrow_err| format!("Failed to write {}: {}", path.display(), arrow_err)),
+        }
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<(), String> {
+        match self {
+            PartitionWriter::Parquet(writer) => writer
+                .write(batch)
+                .map_err(|parquet_err| format!("Failed to write plays: {}", parquet_err)),
+            PartitionWriter::Arrow(writer) => writer
+                .write(batch)
+                .map_err(|arrow_err| format!("Failed to write plays: {}", arrow_err)),
+        }
+    }
+
+    fn close(self) -> Result<(), String> {
+        match self {
+            PartitionWriter::Parquet(writer) => writer
+                .close()
+                .map(|_| ())
+                .map_err(|parquet_err| format!("Failed to finish file: {}", parquet_err)),
+            PartitionWriter::Arrow(mut writer) => writer
+                .finish()
+                .map_err(|arrow_err| format!("Failed to finish file: {}", arrow_err)),
+        }
+    }
+}
+
+/// The partition a play is written to, by the year and month it was played in UTC
+fn partition_path(dir: &Path, play: &ExportedPlay, format: DatasetFormat) -> PathBuf {
+    // The directories are named like Hive partitions, so DuckDB and Polars read them as columns
+    dir.join(format!("year={}", play.played_at.year()))
+        .join(format!("month={:02}", play.played_at.month()))
+        .join(format!("plays.{}", format.extension()))
+}
+
+/// Write every play of a user to a directory, with a file for each month that has any plays
+/// Returns the paths of the files that were written
+/// The plays are streamed and written in batches, so only one batch is held in memory at a time
+pub async fn write_dataset(
+    conn: DatabaseConnection,
+    user_id: String,
+    dir: &Path,
+    format: DatasetFormat,
+) -> Result<Vec<PathBuf>, String> {
+    let schema = schema();
+    let mut plays = Box::pin(stream_plays(conn, user_id));
+    let mut columns = PlayColumns::new();
+    let mut current: Option<(PathBuf, PartitionWriter)> = None;
+    let mut files = vec![];
+    while let Some(play) = plays
+        .try_next()
+        .await
+        .map_err(|db_err| db_err.to_string())?
+    {
+        let path = partition_path(dir, &play, format);
+        // The plays are ordered by when they were played, so a new partition means the last one is done
+        if current.as_ref().map(|(current_path, _)| current_path) != Some(&path) {
+            if let Some((done_path, mut writer)) = current.take() {
+                writer.write(&columns.finish(schema.clone())?)?;
+                writer.close()?;
+                debug!("Finished writing {}", done_path.display());
+                files.push(done_path);
+            }
+            let writer = PartitionWriter::create(&path, format, schema.clone())?;
+            current = Some((path, writer));
+        }
+        columns.push(&play);
+        if columns.len >= BATCH_SIZE {
+            if let Some((_, writer)) = current.as_mut() {
+                writer.write(&columns.finish(schema.clone())?)?;
+            }
+        }
+    }
+    if let Some((done_path, mut writer)) = current.take() {
+        writer.write(&columns.finish(schema.clone())?)?;
+        writer.close()?;
+        files.push(done_path);
+    }
+    Ok(files)
+}
@@ -0,0 +1,210 @@
+pub mod dataset;
+
+use async_stream::try_stream;
+use chrono::{DateTime, Utc};
+use futures::{Stream, TryStreamExt};
+use sea_orm::{DatabaseConnection, DbBackend, FromQueryResult, Statement};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::db::DBError;
+
+/// The formats a user's listening history can be exported in
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// A single JSON array of plays
+    Json,
+    /// One JSON play per line
+    Jsonl,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Json => "application/json",
+            ExportFormat::Jsonl => "application/jsonl",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            _ => Err(format!("Unknown export format {}", format)),
+        }
+    }
+}
+
+/// A single play with the details of what was played
+#[derive(FromQueryResult, Serialize, Debug, Clone)]
+pub struct ExportedPlay {
+    pub play_id: i32,
+    #[serde(serialize_with = "serialize_utc")]
+    pub played_at: sea_orm::prelude::DateTime,
+    pub track_id: i32,
+    pub track_title: String,
+    pub track_uri: Option<String>,
+    pub duration_ms: Option<i32>,
+    /// Missing for tracks that aren't on any album
+    pub album_id: Option<i32>,
+    pub album_title: Option<String>,
+    pub artists: Vec<String>,
+}
+
+/// Plays are stored as naive UTC, so they're marked as UTC when exported
+fn serialize_utc<S: serde::Serializer>(
+    played_at: &sea_orm::prelude::DateTime,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    DateTime::<Utc>::from_naive_utc_and_offset(*played_at, Utc).serialize(serializer)
+}
+
+/// A play flattened into a CSV row, since CSV can't hold a list of artists
+#[derive(Serialize, Debug)]
+struct CsvPlay<'a> {
+    play_id: i32,
+    played_at: DateTime<Utc>,
+    track_id: i32,
+    track_title: &'a str,
+    track_uri: Option<&'a str>,
+    duration_ms: Option<i32>,
+    album_id: Option<i32>,
+    album_title: Option<&'a str>,
+    /// The names of the artists, separated by semicolons
+    artists: String,
+}
+
+impl<'a> From<&'a ExportedPlay> for CsvPlay<'a> {
+    fn from(play: &'a ExportedPlay) -> Self {
+        CsvPlay {
+            play_id: play.play_id,
+            played_at: DateTime::<Utc>::from_naive_utc_and_offset(play.played_at, Utc),
+            track_id: play.track_id,
+            track_title: &play.track_title,
+            track_uri: play.track_uri.as_deref(),
+            duration_ms: play.duration_ms,
+            album_id: play.album_id,
+            album_title: play.album_title.as_deref(),
+            artists: play.artists.join("; "),
+        }
+    }
+}
+
+/// Write a single play in the format, including whatever separates it from the play before it
+fn write_play(format: ExportFormat, play: &ExportedPlay, first: bool) -> Result<String, DBError> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(first)
+                .from_writer(vec![]);
+            writer.serialize(CsvPlay::from(play)).map_err(|csv_err| {
+                error!("Error writing play as CSV: {:?}", csv_err);
+                DBError
+            })?;
+            let row = writer.into_inner().map_err(|csv_err| {
+                error!("Error flushing CSV row: {:?}", csv_err);
+                DBError
+            })?;
+            Ok(String::from_utf8_lossy(&row).into_owned())
+        }
+        ExportFormat::Json | ExportFormat::Jsonl => {
+            let json = serde_json::to_string(play).map_err(|json_err| {
+                error!("Error writing play as JSON: {:?}", json_err);
+                DBError
+            })?;
+            Ok(match (format, first) {
+                (ExportFormat::Json, true) => format!("[\n{}", json),
+                (ExportFormat::Json, false) => format!(",\n{}", json),
+                _ => format!("{}\n", json),
+            })
+        }
+    }
+}
+
+/// Stream every play of a user with the details of what was played, oldest first
+/// The plays are streamed from the database, so the whole history is never loaded at once
+pub fn stream_plays(
+    conn: DatabaseConnection,
+    user_id: String,
+) -> impl Stream<Item = Result<ExportedPlay, DBError>> + Send {
+    // A track can be on multiple albums, so only its first album is exported
+    try_stream! {
+        let mut plays = ExportedPlay::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"SELECT play_log.id AS play_id, play_log.played_at,
+                track.id AS track_id, track.title AS track_title, track.uri AS track_uri,
+                track.duration_ms, album.id AS album_id, album.title AS album_title,
+                ARRAY(
+                    SELECT artist.name
+                    FROM album_artist
+                    INNER JOIN artist ON artist.id = album_artist.artist_id
+                    WHERE album_artist.album_id = album.id
+                    ORDER BY artist.name
+                ) AS artists
+            FROM play_log
+            INNER JOIN track ON track.id = play_log.track_id
+            LEFT JOIN LATERAL (
+                SELECT album.id, album.title
+                FROM album_track
+                INNER JOIN album ON album.id = album_track.album_id
+                WHERE album_track.track_id = track.id
+                ORDER BY album.id
+                LIMIT 1
+            ) AS album ON TRUE
+            WHERE play_log.user_id = $1
+            ORDER BY play_log.played_at, play_log.id"#,
+            [user_id.into()],
+        ))
+        .stream(&conn)
+        .await
+        .map_err(|sea_err| {
+            error!("Error streaming plays for export: {:?}", sea_err);
+            DBError
+        })?;
+        while let Some(play) = plays.try_next().await.map_err(|sea_err| {
+            error!("Error reading play for export: {:?}", sea_err);
+            DBError
+        })? {
+            yield play;
+        }
+    }
+}
+
+/// Stream every play of a user written in the format, in chunks of text
+pub fn export_plays(
+    conn: DatabaseConnection,
+    user_id: String,
+    format: ExportFormat,
+) -> impl Stream<Item = Result<String, DBError>> + Send {
+    try_stream! {
+        let mut plays = Box::pin(stream_plays(conn, user_id));
+        let mut first = true;
+        while let Some(play) = plays.try_next().await? {
+            yield write_play(format, &play, first)?;
+            first = false;
+        }
+        // The JSON array is closed even if there weren't any plays
+        match (format, first) {
+            (ExportFormat::Json, true) => yield "[]\n".to_string(),
+            (ExportFormat::Json, false) => yield "\n]\n".to_string(),
+            _ => {}
+        }
+    }
+}
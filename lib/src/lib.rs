@@ -1,2 +1,6 @@
+pub mod analytics;
+pub mod config;
 pub mod db;
+pub mod export;
 pub mod music;
+pub mod recommend;
@@ -0,0 +1,314 @@
+use chrono::{DateTime, Utc};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use surf::Url;
+use tracing::{debug, error};
+
+use crate::music::scrobble::{self, Scrobble};
+
+/// The Last.fm API, every method is called on this one endpoint
+const API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+
+/// The page users are sent to to allow access to their Last.fm account
+const AUTH_URL: &str = "https://www.last.fm/api/auth/";
+
+/// The most recent tracks Last.fm returns in one page
+const RECENT_TRACKS_LIMIT: u32 = 200;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LastfmError {
+    pub status: u16,
+    pub message: String,
+}
+
+/// The credentials of the Last.fm API account, needed to sign every authenticated call
+#[derive(Debug, Clone)]
+pub struct LastfmCredentials {
+    pub api_key: String,
+    pub shared_secret: String,
+}
+
+/// The session of a Last.fm user, which doesn't expire until the user revokes it
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LastfmSession {
+    /// The name of the user, which identifies them on Last.fm
+    pub name: String,
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SessionResponse {
+    session: Option<LastfmSession>,
+    error: Option<u32>,
+    message: Option<String>,
+}
+
+/// A name along with the MusicBrainz ID Last.fm has for it, which is empty when it doesn't have one
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NamedMbid {
+    #[serde(default)]
+    pub mbid: String,
+    #[serde(rename = "#text")]
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScrobbleDate {
+    /// When the track was played, as a unix timestamp in a string
+    pub uts: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecentTrack {
+    pub name: String,
+    #[serde(default)]
+    pub mbid: String,
+    pub artist: NamedMbid,
+    pub album: NamedMbid,
+    /// Missing for the track that is playing right now, which is only scrobbled once it's played
+    pub date: Option<ScrobbleDate>,
+}
+
+impl RecentTrack {
+    /// The track as a scrobble, unless it's still playing
+    pub fn scrobble(&self) -> Option<Scrobble> {
+        let played_at = self.date.as_ref()?.uts.parse().ok()?;
+        Some(Scrobble {
+            track: self.name.clone(),
+            artist: self.artist.name.clone(),
+            album: Some(self.album.name.clone()).filter(|album| !album.is_empty()),
+            track_uri: scrobble::recording_uri(&self.mbid),
+            album_uri: scrobble::release_uri(&self.album.mbid),
+            duration_ms: None,
+            played_at: DateTime::<Utc>::from_timestamp(played_at, 0)?.naive_utc(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RecentTracks {
+    #[serde(default)]
+    track: Vec<RecentTrack>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RecentTracksResponse {
+    recenttracks: Option<RecentTracks>,
+    error: Option<u32>,
+    message: Option<String>,
+}
+
+/// Turn an error sent back by Last.fm into a `LastfmError`
+fn api_error(code: u32, message: Option<String>) -> LastfmError {
+    LastfmError {
+        // Invalid and expired session keys mean the user revoked access
+        status: match code {
+            4 | 9 | 14 | 15 => 401,
+            _ => 502,
+        },
+        message: format!(
+            "Last.fm error {}: {}",
+            code,
+            message.unwrap_or("no message".to_string())
+        ),
+    }
+}
+
+/// The primary client for interacting with the Last.fm API
+pub struct LastfmClient {
+    pub credentials: LastfmCredentials,
+}
+
+impl LastfmClient {
+    pub fn new(credentials: LastfmCredentials) -> Self {
+        LastfmClient { credentials }
+    }
+
+    /// Build the page a user is sent to to allow access, Last.fm then redirects to the callback with a token
+    pub fn auth_url(&self, callback_url: &str) -> Result<Url, LastfmError> {
+        Url::parse_with_params(
+            AUTH_URL,
+            &[
+                ("api_key", self.credentials.api_key.as_str()),
+                ("cb", callback_url),
+            ],
+        )
+        .map_err(|url_err| {
+            error!("Failed to construct Last.fm auth URL: {}", url_err);
+            LastfmError {
+                status: 500,
+                message: "Internal error building the Last.fm auth URL".to_string(),
+            }
+        })
+    }
+
+    /// Sign the parameters of a call, by hashing them in order along with the shared secret
+    fn sign(&self, params: &mut Vec<(&str, String)>) {
+        params.sort_by_key(|(name, _)| *name);
+        let mut signature = String::new();
+        for (name, value) in params.iter() {
+            signature.push_str(name);
+            signature.push_str(value);
+        }
+        signature.push_str(&self.credentials.shared_secret);
+        let api_sig = format!("{:x}", Md5::digest(signature.as_bytes()));
+        params.push(("api_sig", api_sig));
+        // The format isn't part of the signature
+        params.push(("format", "json".to_string()));
+    }
+
+    /// Exchange the token Last.fm sent to the callback for the session of the user
+    pub async fn get_session(&self, token: &str) -> Result<LastfmSession, LastfmError> {
+        let mut params = vec![
+            ("method", "auth.getSession".to_string()),
+            ("api_key", self.credentials.api_key.clone()),
+            ("token", token.to_string()),
+        ];
+        self.sign(&mut params);
+        let res: SessionResponse = surf::get(API_URL)
+            .query(&params)
+            .map_err(|err| {
+                error!("Failed to build session query {:?}", err);
+                LastfmError {
+                    status: 500,
+                    message: "Internal error requesting a session from Last.fm".to_string(),
+                }
+            })?
+            .recv_json()
+            .await
+            .map_err(|err| {
+                error!("Failed to fetch json from last.fm {:?}", err);
+                LastfmError {
+                    status: 500,
+                    message: "Internal error requesting a session from Last.fm".to_string(),
+                }
+            })?;
+        if let Some(code) = res.error {
+            return Err(api_error(code, res.message));
+        }
+        res.session.ok_or(LastfmError {
+            status: 500,
+            message: "Last.fm returned an empty session".to_string(),
+        })
+    }
+
+    /// Fetch the tracks a user scrobbled most recently, using their session so private histories can be read
+    pub async fn get_recent_tracks(
+        &self,
+        user: &str,
+        session_key: &str,
+    ) -> Result<Vec<RecentTrack>, LastfmError> {
+        debug!("Fetching recent tracks of {} from Last.fm", user);
+        let mut params = vec![
+            ("method", "user.getRecentTracks".to_string()),
+            ("api_key", self.credentials.api_key.clone()),
+            ("user", user.to_string()),
+            ("limit", RECENT_TRACKS_LIMIT.to_string()),
+            ("sk", session_key.to_string()),
+        ];
+        self.sign(&mut params);
+        let res: RecentTracksResponse = surf::get(API_URL)
+            .query(&params)
+            .map_err(|err| {
+                error!("Failed to build recent tracks query {:?}", err);
+                LastfmError {
+                    status: 500,
+                    message: "Internal error requesting recent tracks from Last.fm".to_string(),
+                }
+            })?
+            .recv_json()
+            .await
+            .map_err(|err| {
+                error!("Failed to fetch json from last.fm {:?}", err);
+                LastfmError {
+                    status: 500,
+                    message: "Internal error requesting recent tracks from Last.fm".to_string(),
+                }
+            })?;
+        if let Some(code) = res.error {
+            return Err(api_error(code, res.message));
+        }
+        Ok(res
+            .recenttracks
+            .map(|recent| recent.track)
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_parameters_in_order() {
+        let client = LastfmClient::new(LastfmCredentials {
+            api_key: "key".to_string(),
+            shared_secret: "secret".to_string(),
+        });
+        let mut params = vec![
+            ("token", "tok".to_string()),
+            ("method", "auth.getSession".to_string()),
+            ("api_key", "key".to_string()),
+        ];
+        client.sign(&mut params);
+        // md5("api_keykeymethodauth.getSessiontokentoksecret")
+        assert_eq!(
+            params,
+            vec![
+                ("api_key", "key".to_string()),
+                ("method", "auth.getSession".to_string()),
+                ("token", "tok".to_string()),
+                ("api_sig", "04e870be4bb79756721b7bc1937fe83d".to_string()),
+                ("format", "json".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_the_track_playing_now() {
+        let recent: RecentTracks = serde_json::from_value(serde_json::json!({
+            "track": [
+                {
+                    "name": "Hunter",
+                    "mbid": "",
+                    "artist": { "mbid": "", "#text": "Bjork" },
+                    "album": { "mbid": "", "#text": "Homogenic" },
+                    "@attr": { "nowplaying": "true" }
+                },
+                {
+                    "name": "Joga",
+                    "mbid": "rec-joga",
+                    "artist": { "mbid": "", "#text": "Bjork" },
+                    "album": { "mbid": "rel-homogenic", "#text": "Homogenic" },
+                    "date": { "uts": "1760000000", "#text": "09 Oct 2025, 08:53" }
+                },
+                {
+                    "name": "Single",
+                    "artist": { "mbid": "", "#text": "Bjork" },
+                    "album": { "mbid": "", "#text": "" },
+                    "date": { "uts": "1760000300", "#text": "09 Oct 2025, 08:58" }
+                }
+            ]
+        }))
+        .unwrap();
+        let scrobbles: Vec<Scrobble> = recent
+            .track
+            .iter()
+            .filter_map(RecentTrack::scrobble)
+            .collect();
+        assert_eq!(scrobbles.len(), 2);
+        assert_eq!(
+            scrobbles[0].track_uri.as_deref(),
+            Some("musicbrainz:recording:rec-joga")
+        );
+        assert_eq!(
+            scrobbles[0].album_uri.as_deref(),
+            Some("musicbrainz:release:rel-homogenic")
+        );
+        assert_eq!(scrobbles[0].played_at.and_utc().timestamp(), 1760000000);
+        // Tracks without an album are saved on one named after them
+        assert_eq!(scrobbles[1].album, None);
+        assert_eq!(scrobbles[1].album_title(), "Single");
+        assert_eq!(scrobbles[1].track_uri, None);
+    }
+}
@@ -0,0 +1,266 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::{debug, error};
+
+use crate::music::scrobble::{self, Scrobble};
+
+/// The most recent listens ListenBrainz returns in one page
+const LISTENS_COUNT: u32 = 100;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListenBrainzError {
+    pub status: u16,
+    pub message: String,
+}
+
+/// Whether a user token is valid, along with the user it belongs to
+#[derive(Serialize, Deserialize, Debug)]
+struct TokenValidation {
+    valid: Option<bool>,
+    user_name: Option<String>,
+    message: Option<String>,
+    code: Option<u16>,
+    error: Option<String>,
+}
+
+/// The MusicBrainz IDs ListenBrainz mapped a listen to
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MbidMapping {
+    pub recording_mbid: Option<String>,
+    pub release_mbid: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrackMetadata {
+    pub artist_name: String,
+    pub track_name: String,
+    pub release_name: Option<String>,
+    /// Whatever the player that submitted the listen sent along, so every value is optional and loosely typed
+    #[serde(default)]
+    pub additional_info: Map<String, Value>,
+    pub mbid_mapping: Option<MbidMapping>,
+}
+
+impl TrackMetadata {
+    /// Get a string from the additional info, ignoring it if it isn't one
+    fn info(&self, key: &str) -> Option<&str> {
+        self.additional_info
+            .get(key)
+            .and_then(Value::as_str)
+            .filter(|value| !value.trim().is_empty())
+    }
+
+    /// The Spotify URI of something the listen links to, from the Spotify URL players send along
+    fn spotify_uri(&self, key: &str, kind: &str) -> Option<String> {
+        let url = self.info(key)?;
+        let id = url.rsplit('/').next()?.split('?').next()?;
+        Some(format!("spotify:{}:{}", kind, id)).filter(|_| !id.is_empty())
+    }
+
+    /// The length of the track, players send it in either milliseconds or seconds
+    fn duration_ms(&self) -> Option<i32> {
+        let info = |key: &str| self.additional_info.get(key).and_then(Value::as_i64);
+        info("duration_ms")
+            .or(info("duration").map(|seconds| seconds * 1000))
+            .and_then(|duration_ms| i32::try_from(duration_ms).ok())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Listen {
+    /// When the track was played, as a unix timestamp
+    pub listened_at: i64,
+    pub track_metadata: TrackMetadata,
+}
+
+impl Listen {
+    /// The listen as a scrobble, matching it to Spotify tracks when the player was Spotify
+    pub fn scrobble(&self) -> Option<Scrobble> {
+        let metadata = &self.track_metadata;
+        let mapping = metadata.mbid_mapping.as_ref();
+        let recording_mbid = mapping
+            .and_then(|mapping| mapping.recording_mbid.as_deref())
+            .or(metadata.info("recording_mbid"));
+        let release_mbid = mapping
+            .and_then(|mapping| mapping.release_mbid.as_deref())
+            .or(metadata.info("release_mbid"));
+        Some(Scrobble {
+            track: metadata.track_name.clone(),
+            artist: metadata.artist_name.clone(),
+            album: metadata
+                .release_name
+                .clone()
+                .filter(|album| !album.is_empty()),
+            track_uri: metadata
+                .spotify_uri("spotify_id", "track")
+                .or(recording_mbid.and_then(scrobble::recording_uri)),
+            album_uri: metadata
+                .spotify_uri("spotify_album_id", "album")
+                .or(release_mbid.and_then(scrobble::release_uri)),
+            duration_ms: metadata.duration_ms(),
+            played_at: DateTime::<Utc>::from_timestamp(self.listened_at, 0)?.naive_utc(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ListensPayload {
+    #[serde(default)]
+    listens: Vec<Listen>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ListensResponse {
+    payload: Option<ListensPayload>,
+    code: Option<u16>,
+    error: Option<String>,
+}
+
+/// The primary client for interacting with the ListenBrainz API, as the user the token belongs to
+pub struct ListenBrainzClient {
+    /// Where the API is hosted, without a trailing slash
+    pub api_url: String,
+    pub token: String,
+}
+
+impl ListenBrainzClient {
+    pub fn new(api_url: &str, token: String) -> Self {
+        ListenBrainzClient {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    /// Check the user token is valid, returning the name of the user it belongs to
+    pub async fn validate_token(&self) -> Result<String, ListenBrainzError> {
+        let res: TokenValidation = surf::get(format!("{}/1/validate-token", self.api_url))
+            .header("Authorization", format!("Token {}", self.token))
+            .recv_json()
+            .await
+            .map_err(|err| {
+                error!("Failed to fetch json from listenbrainz {:?}", err);
+                ListenBrainzError {
+                    status: 500,
+                    message: "Internal error validating the token with ListenBrainz".to_string(),
+                }
+            })?;
+        if let Some(error) = res.error {
+            return Err(ListenBrainzError {
+                status: res.code.unwrap_or(502),
+                message: error,
+            });
+        }
+        match (res.valid, res.user_name) {
+            (Some(true), Some(user_name)) => Ok(user_name),
+            _ => Err(ListenBrainzError {
+                status: 401,
+                message: res.message.unwrap_or("Token invalid.".to_string()),
+            }),
+        }
+    }
+
+    /// Fetch the most recent listens of a user
+    pub async fn get_listens(&self, user_name: &str) -> Result<Vec<Listen>, ListenBrainzError> {
+        debug!("Fetching listens of {} from ListenBrainz", user_name);
+        let res: ListensResponse =
+            surf::get(format!("{}/1/user/{}/listens", self.api_url, user_name))
+                .query(&[("count", LISTENS_COUNT)])
+                .map_err(|err| {
+                    error!("Failed to build listens query {:?}", err);
+                    ListenBrainzError {
+                        status: 500,
+                        message: "Internal error requesting listens from ListenBrainz".to_string(),
+                    }
+                })?
+                .header("Authorization", format!("Token {}", self.token))
+                .recv_json()
+                .await
+                .map_err(|err| {
+                    error!("Failed to fetch json from listenbrainz {:?}", err);
+                    ListenBrainzError {
+                        status: 500,
+                        message: "Internal error requesting listens from ListenBrainz".to_string(),
+                    }
+                })?;
+        if let Some(error) = res.error {
+            return Err(ListenBrainzError {
+                status: res.code.unwrap_or(502),
+                message: error,
+            });
+        }
+        Ok(res
+            .payload
+            .map(|payload| payload.listens)
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listen(track_metadata: Value) -> Listen {
+        serde_json::from_value(serde_json::json!({
+            "listened_at": 1760000000,
+            "track_metadata": track_metadata,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn prefers_spotify_uris() {
+        let scrobble = listen(serde_json::json!({
+            "artist_name": "Radiohead",
+            "track_name": "Airbag",
+            "release_name": "OK Computer",
+            "additional_info": {
+                "spotify_id": "https://open.spotify.com/track/AIR",
+                "spotify_album_id": "https://open.spotify.com/album/OKC?si=share",
+                "duration_ms": 284000
+            },
+            "mbid_mapping": { "recording_mbid": "rec-airbag", "release_mbid": "rel-okc" }
+        }))
+        .scrobble()
+        .unwrap();
+        assert_eq!(scrobble.track_uri.as_deref(), Some("spotify:track:AIR"));
+        assert_eq!(scrobble.album_uri.as_deref(), Some("spotify:album:OKC"));
+        assert_eq!(scrobble.duration_ms, Some(284000));
+    }
+
+    #[test]
+    fn falls_back_to_musicbrainz_ids() {
+        let scrobble = listen(serde_json::json!({
+            "artist_name": "Bjork",
+            "track_name": "Hunter",
+            "release_name": "Homogenic",
+            "additional_info": { "recording_mbid": "rec-hunter", "duration": 255 },
+            "mbid_mapping": { "release_mbid": "rel-homogenic" }
+        }))
+        .scrobble()
+        .unwrap();
+        assert_eq!(
+            scrobble.track_uri.as_deref(),
+            Some("musicbrainz:recording:rec-hunter")
+        );
+        assert_eq!(
+            scrobble.album_uri.as_deref(),
+            Some("musicbrainz:release:rel-homogenic")
+        );
+        assert_eq!(scrobble.duration_ms, Some(255000));
+    }
+
+    #[test]
+    fn ignores_info_it_cant_read() {
+        let scrobble = listen(serde_json::json!({
+            "artist_name": "Unknown Artist",
+            "track_name": "Bootleg",
+            "additional_info": { "duration_ms": "long", "spotify_id": 7 }
+        }))
+        .scrobble()
+        .unwrap();
+        assert_eq!(scrobble.track_uri, None);
+        assert_eq!(scrobble.album, None);
+        assert_eq!(scrobble.duration_ms, None);
+    }
+}
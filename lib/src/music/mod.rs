@@ -1 +1,5 @@
+pub mod lastfm;
+pub mod listenbrainz;
+pub mod playback;
+pub mod scrobble;
 pub mod spotify;
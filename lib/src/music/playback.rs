@@ -0,0 +1,259 @@
+use crate::music::spotify::{CurrentlyPlaying, Track};
+use chrono::Duration;
+use sea_orm::prelude::DateTime;
+
+/// Plays shorter than this are considered skips
+pub const SKIP_THRESHOLD_MS: u64 = 30_000;
+
+/// A finished play of a single track, built from consecutive currently-playing observations
+#[derive(Debug, Clone)]
+pub struct PlaySession {
+    pub track: Track,
+    pub started_at: DateTime,
+    pub ended_at: DateTime,
+    pub ms_played: u64,
+    pub skipped: bool,
+}
+
+/// The play that is still in progress
+#[derive(Debug)]
+struct ActiveSession {
+    track: Track,
+    started_at: DateTime,
+    last_seen: DateTime,
+    last_progress_ms: u64,
+    ms_played: u64,
+    is_playing: bool,
+}
+
+impl ActiveSession {
+    fn start(now: DateTime, track: Track, progress_ms: u64, is_playing: bool) -> Self {
+        Self {
+            track,
+            started_at: now - Duration::milliseconds(progress_ms as i64),
+            last_seen: now,
+            last_progress_ms: progress_ms,
+            ms_played: progress_ms,
+            is_playing,
+        }
+    }
+
+    fn is_same_track(&self, track: &Track) -> bool {
+        match (&self.track.id, &track.id) {
+            (Some(active_id), Some(id)) => active_id == id,
+            // Local files don't have an ID, so fall back to the name
+            _ => self.track.name == track.name,
+        }
+    }
+
+    fn finish(self) -> PlaySession {
+        // Tracks shorter than the threshold can't be skipped by playing them fully
+        let threshold = SKIP_THRESHOLD_MS.min(self.track.duration_ms);
+        PlaySession {
+            skipped: self.ms_played < threshold,
+            started_at: self.started_at,
+            ended_at: self.last_seen,
+            ms_played: self.ms_played.min(self.track.duration_ms),
+            track: self.track,
+        }
+    }
+}
+
+/// Turns periodic currently-playing observations into play sessions
+/// One tracker should be kept per account, and fed every observation in order
+#[derive(Debug, Default)]
+pub struct PlaybackTracker {
+    active: Option<ActiveSession>,
+}
+
+impl PlaybackTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an observation made at `now`, returning the previous session if it just finished
+    pub fn observe(
+        &mut self,
+        now: DateTime,
+        current: Option<&CurrentlyPlaying>,
+    ) -> Option<PlaySession> {
+        // Anything other than a track (no playback, ads, episodes) ends the active session
+        let (track, progress_ms, is_playing) = match current {
+            Some(CurrentlyPlaying {
+                item: Some(track),
+                progress_ms,
+                is_playing,
+                ..
+            }) => (track, progress_ms.unwrap_or_default(), *is_playing),
+            _ => return self.active.take().map(ActiveSession::finish),
+        };
+        match self.active.as_mut() {
+            Some(active) if active.is_same_track(track) => {
+                // The track starting over (repeat, or going back to it) is a new play
+                if progress_ms < active.last_progress_ms && progress_ms < SKIP_THRESHOLD_MS {
+                    let finished = self.active.replace(ActiveSession::start(
+                        now,
+                        track.clone(),
+                        progress_ms,
+                        is_playing,
+                    ));
+                    return finished.map(ActiveSession::finish);
+                }
+                // Only count progress that could have happened in the time between observations,
+                // seeking forward shouldn't count as listening
+                if active.is_playing || is_playing {
+                    let elapsed = (now - active.last_seen).num_milliseconds().max(0) as u64;
+                    let progressed = progress_ms.saturating_sub(active.last_progress_ms);
+                    active.ms_played += progressed.min(elapsed);
+                }
+                active.last_progress_ms = progress_ms;
+                active.last_seen = now;
+                active.is_playing = is_playing;
+                None
+            }
+            _ => {
+                let next = ActiveSession::start(now, track.clone(), progress_ms, is_playing);
+                let mut finished = self.active.replace(next)?;
+                // The previous track kept playing until the new one started
+                if finished.is_playing {
+                    let switched_at = now - Duration::milliseconds(progress_ms as i64);
+                    let extra = (switched_at - finished.last_seen).num_milliseconds().max(0) as u64;
+                    let remaining = finished
+                        .track
+                        .duration_ms
+                        .saturating_sub(finished.last_progress_ms);
+                    finished.ms_played += extra.min(remaining);
+                    finished.last_seen = finished.last_seen.max(switched_at);
+                }
+                Some(finished.finish())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn track(id: &str, duration_ms: u64) -> Track {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "name": id,
+            "duration_ms": duration_ms,
+            "external_urls": { "spotify": "" },
+            "album": {
+                "id": "album",
+                "images": [],
+                "name": "Album",
+                "release_date": "2024-01-01",
+                "album_type": "album",
+                "external_urls": { "spotify": "" },
+                "artists": [],
+            },
+        }))
+        .unwrap()
+    }
+
+    fn playing(track: &Track, progress_ms: u64, is_playing: bool) -> CurrentlyPlaying {
+        CurrentlyPlaying {
+            is_playing,
+            progress_ms: Some(progress_ms),
+            currently_playing_type: "track".to_string(),
+            item: Some(track.clone()),
+        }
+    }
+
+    /// The time a number of seconds into the test
+    fn at(seconds: i64) -> DateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            + Duration::seconds(seconds)
+    }
+
+    #[test]
+    fn track_change_finishes_the_previous_track() {
+        let first = track("first", 200_000);
+        let second = track("second", 200_000);
+        let mut tracker = PlaybackTracker::new();
+        assert!(tracker
+            .observe(at(0), Some(&playing(&first, 0, true)))
+            .is_none());
+        assert!(tracker
+            .observe(at(10), Some(&playing(&first, 10_000, true)))
+            .is_none());
+        let finished = tracker
+            .observe(at(60), Some(&playing(&second, 5_000, true)))
+            .unwrap();
+        // The first track kept playing until the second one started, 5 seconds before the observation
+        assert_eq!(finished.track.name, "first");
+        assert_eq!(finished.started_at, at(0));
+        assert_eq!(finished.ended_at, at(55));
+        assert_eq!(finished.ms_played, 55_000);
+        assert!(!finished.skipped);
+    }
+
+    #[test]
+    fn repeat_restart_starts_a_new_session() {
+        let song = track("song", 120_000);
+        let mut tracker = PlaybackTracker::new();
+        tracker.observe(at(0), Some(&playing(&song, 0, true)));
+        tracker.observe(at(100), Some(&playing(&song, 100_000, true)));
+        let finished = tracker
+            .observe(at(125), Some(&playing(&song, 3_000, true)))
+            .unwrap();
+        assert_eq!(finished.ms_played, 100_000);
+        assert!(!finished.skipped);
+        // The repeat is a session of its own
+        let repeat = tracker.observe(at(130), None).unwrap();
+        assert_eq!(repeat.started_at, at(122));
+        assert_eq!(repeat.ms_played, 3_000);
+    }
+
+    #[test]
+    fn paused_time_is_not_listening() {
+        let song = track("song", 200_000);
+        let mut tracker = PlaybackTracker::new();
+        tracker.observe(at(0), Some(&playing(&song, 0, true)));
+        tracker.observe(at(40), Some(&playing(&song, 40_000, false)));
+        tracker.observe(at(300), Some(&playing(&song, 40_000, false)));
+        tracker.observe(at(310), Some(&playing(&song, 50_000, true)));
+        let finished = tracker.observe(at(320), None).unwrap();
+        assert_eq!(finished.ms_played, 50_000);
+    }
+
+    #[test]
+    fn seeking_forward_is_not_listening() {
+        let song = track("song", 200_000);
+        let mut tracker = PlaybackTracker::new();
+        tracker.observe(at(0), Some(&playing(&song, 0, true)));
+        tracker.observe(at(10), Some(&playing(&song, 150_000, true)));
+        let finished = tracker.observe(at(15), None).unwrap();
+        assert_eq!(finished.ms_played, 10_000);
+        assert!(finished.skipped);
+    }
+
+    #[test]
+    fn plays_shorter_than_the_threshold_are_skips() {
+        let song = track("song", 200_000);
+        let mut tracker = PlaybackTracker::new();
+        tracker.observe(at(0), Some(&playing(&song, 0, true)));
+        tracker.observe(at(29), Some(&playing(&song, 29_000, true)));
+        let finished = tracker.observe(at(30), None).unwrap();
+        assert_eq!(finished.ms_played, 29_000);
+        assert!(finished.skipped);
+    }
+
+    #[test]
+    fn short_tracks_played_in_full_are_not_skips() {
+        let jingle = track("jingle", 20_000);
+        let mut tracker = PlaybackTracker::new();
+        tracker.observe(at(0), Some(&playing(&jingle, 0, true)));
+        tracker.observe(at(20), Some(&playing(&jingle, 20_000, true)));
+        let finished = tracker.observe(at(25), None).unwrap();
+        assert_eq!(finished.ms_played, 20_000);
+        assert!(!finished.skipped);
+    }
+}
@@ -0,0 +1,71 @@
+use entity::{album, artist, track};
+use sea_orm::{prelude::DateTime, ActiveValue, NotSet};
+
+/// A play as it's reported by providers that only keep a listening history, like Last.fm and ListenBrainz
+/// Unlike Spotify they only name what was played, so it's matched to the catalog on whatever IDs they send along
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scrobble {
+    pub track: String,
+    pub artist: String,
+    /// The album the track was played from, missing when the provider doesn't know it
+    pub album: Option<String>,
+    /// The URI the track is matched on, a Spotify URI or MusicBrainz recording when the provider knows either
+    pub track_uri: Option<String>,
+    /// The URI the album is matched on, a Spotify URI or MusicBrainz release when the provider knows either
+    pub album_uri: Option<String>,
+    pub duration_ms: Option<i32>,
+    pub played_at: DateTime,
+}
+
+/// The URI of a MusicBrainz recording, which tracks without a Spotify URI are matched on
+pub fn recording_uri(mbid: &str) -> Option<String> {
+    Some(mbid.trim())
+        .filter(|mbid| !mbid.is_empty())
+        .map(|mbid| format!("musicbrainz:recording:{}", mbid))
+}
+
+/// The URI of a MusicBrainz release, which albums without a Spotify URI are matched on
+pub fn release_uri(mbid: &str) -> Option<String> {
+    Some(mbid.trim())
+        .filter(|mbid| !mbid.is_empty())
+        .map(|mbid| format!("musicbrainz:release:{}", mbid))
+}
+
+impl Scrobble {
+    /// The title of the album the track is saved on, tracks without an album are saved on one named
+    /// after themselves, the way singles are
+    pub fn album_title(&self) -> &str {
+        self.album.as_deref().unwrap_or(&self.track)
+    }
+
+    pub fn artist_model(&self) -> artist::ActiveModel {
+        artist::ActiveModel {
+            id: NotSet,
+            name: ActiveValue::set(self.artist.clone()),
+            created_at: NotSet,
+            updated_at: NotSet,
+        }
+    }
+
+    pub fn album_model(&self) -> album::ActiveModel {
+        album::ActiveModel {
+            id: NotSet,
+            title: ActiveValue::set(self.album_title().to_string()),
+            release_date: ActiveValue::set(None),
+            created_at: NotSet,
+            updated_at: NotSet,
+            uri: ActiveValue::set(self.album_uri.clone()),
+        }
+    }
+
+    pub fn track_model(&self) -> track::ActiveModel {
+        track::ActiveModel {
+            id: NotSet,
+            title: ActiveValue::set(self.track.clone()),
+            created_at: NotSet,
+            updated_at: NotSet,
+            duration_ms: ActiveValue::set(self.duration_ms),
+            uri: ActiveValue::set(self.track_uri.clone()),
+        }
+    }
+}
@@ -11,21 +11,26 @@ pub struct SpotifyError {
     pub message: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Track {
+    /// The Spotify ID of the track, missing for local files
+    pub id: Option<String>,
     pub name: String,
     pub album: Album,
+    pub duration_ms: u64,
     external_urls: ExternalUrls,
 }
 
-impl Into<entity::track::Entity> for Track {
-    fn into(self) -> entity::prelude::Track {
+impl From<Track> for entity::track::Entity {
+    fn from(_: Track) -> Self {
         entity::prelude::Track {}
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Artist {
+    /// The Spotify ID of the artist, missing for local files
+    pub id: Option<String>,
     pub name: String,
     external_urls: ExternalUrls,
 }
@@ -41,26 +46,67 @@ impl Artist {
     }
 }
 
+/// An artist with their full details, as opposed to the simplified artist on albums
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FullArtist {
+    pub id: String,
+    pub name: String,
+    pub genres: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArtistsResponse {
+    pub artists: Option<Vec<Option<FullArtist>>>,
+    pub error: Option<SpotifyError>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Album {
+    /// The Spotify ID of the album, missing for local files
+    #[serde(default)]
+    pub id: Option<String>,
     images: Vec<AlbumImage>,
     pub name: String,
     release_date: String,
+    /// How much of the release date is known, either `year`, `month` or `day`
+    #[serde(default)]
+    release_date_precision: Option<String>,
     album_type: String,
     external_urls: ExternalUrls,
     pub artists: Vec<Artist>,
 }
 
 impl Album {
+    /// The Spotify URI of the album, which it's matched on in the database
+    pub fn uri(&self) -> Option<String> {
+        self.id.as_ref().map(|id| format!("spotify:album:{}", id))
+    }
+
+    /// The release date of the album, on the first of the year or month when the day isn't known
+    pub fn release_date(&self) -> Option<Date> {
+        let release_date = match self.release_date_precision.as_deref() {
+            Some("year") => format!("{}-01-01", self.release_date),
+            Some("month") => format!("{}-01", self.release_date),
+            _ => self.release_date.clone(),
+        };
+        let release_date = Date::parse_from_str(&release_date, "%Y-%m-%d").ok();
+        if release_date.is_none() {
+            debug!(
+                "Unknown release date {} of album {}",
+                self.release_date, self.name
+            );
+        }
+        release_date
+    }
+
     pub fn model(&self) -> album::ActiveModel {
-        let release_date = Date::parse_from_str(&self.release_date, "%Y-%m-%d")
-            .expect("Failed to parse release date");
         album::ActiveModel {
             id: NotSet,
             title: ActiveValue::set(self.name.clone()),
-            release_date: ActiveValue::set(release_date),
+            release_date: ActiveValue::set(self.release_date()),
             created_at: NotSet,
             updated_at: NotSet,
+            uri: ActiveValue::set(self.uri()),
         }
     }
 }
@@ -84,12 +130,21 @@ pub struct RecentTrack {
 }
 
 impl Track {
+    /// The Spotify URI of the track, which it's matched on in the database
+    pub fn uri(&self) -> Option<String> {
+        self.id
+            .as_ref()
+            .map(|id| format!("{}{}", TRACK_URI_PREFIX, id))
+    }
+
     pub fn model(&self) -> entity::track::ActiveModel {
         entity::track::ActiveModel {
             id: NotSet,
             title: ActiveValue::set(self.name.clone()),
             created_at: NotSet,
             updated_at: NotSet,
+            duration_ms: ActiveValue::set(Some(self.duration_ms as i32)),
+            uri: ActiveValue::set(self.uri()),
         }
     }
 }
@@ -103,8 +158,7 @@ impl RecentTrackExt for Vec<RecentTrack> {
     /// Gets all the artists from the recent tracks
     fn artists(&self) -> Vec<Artist> {
         self.iter()
-            .map(|track| track.track.album.artists.clone())
-            .flatten()
+            .flat_map(|track| track.track.album.artists.clone())
             .collect()
     }
     /// Gets all the albums from the recent tracks
@@ -113,10 +167,40 @@ impl RecentTrackExt for Vec<RecentTrack> {
     }
 }
 
+/// The scopes needed to write playlists, on top of the scopes for reading listening history
+pub const PLAYLIST_SCOPES: [&str; 2] = ["playlist-modify-private", "playlist-modify-public"];
+
+/// The start of the URIs of Spotify tracks, tracks scrobbled from other providers have their own URIs
+pub const TRACK_URI_PREFIX: &str = "spotify:track:";
+
+/// Pick out the URIs of tracks that can be added to a playlist, in order, along with the number left out
+/// Spotify rejects a whole request if any URI isn't one of its tracks
+pub fn playlist_uris(uris: Vec<Option<String>>) -> (Vec<String>, usize) {
+    let total = uris.len();
+    let playable: Vec<String> = uris
+        .into_iter()
+        .flatten()
+        .filter(|uri| uri.starts_with(TRACK_URI_PREFIX))
+        .collect();
+    let skipped = total - playable.len();
+    (playable, skipped)
+}
+
+/// The most items Spotify allows adding to a playlist in one request
+const PLAYLIST_ITEMS_CHUNK_SIZE: usize = 100;
+
+/// The credentials of the Spotify app, needed to refresh access tokens
+#[derive(Debug, Clone)]
+pub struct SpotifyCredentials {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
 /// The primary client for interacting with the Spotify API
 pub struct SpotifyClient {
     pub access_token: String,
     pub refresh_token: Option<String>,
+    pub credentials: Option<SpotifyCredentials>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -125,6 +209,66 @@ pub struct RecentTracksResponse {
     pub error: Option<SpotifyError>,
 }
 
+/// The track currently playing on the user's active device
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CurrentlyPlaying {
+    pub is_playing: bool,
+    pub progress_ms: Option<u64>,
+    pub currently_playing_type: String,
+    /// The playing track, missing when an ad or episode is playing
+    pub item: Option<Track>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CurrentlyPlayingResponse {
+    #[serde(flatten)]
+    pub currently_playing: Option<CurrentlyPlaying>,
+    pub error: Option<SpotifyError>,
+}
+
+/// The profile of the user the access token belongs to
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SpotifyProfile {
+    pub id: String,
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SpotifyProfileResponse {
+    #[serde(flatten)]
+    pub profile: Option<SpotifyProfile>,
+    pub error: Option<SpotifyError>,
+}
+
+/// A playlist owned by the user
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpotifyPlaylist {
+    pub id: String,
+    pub name: String,
+    external_urls: ExternalUrls,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SpotifyPlaylistResponse {
+    #[serde(flatten)]
+    pub playlist: Option<SpotifyPlaylist>,
+    pub error: Option<SpotifyError>,
+}
+
+/// The response to changing the items of a playlist
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlaylistSnapshotResponse {
+    pub snapshot_id: Option<String>,
+    pub error: Option<SpotifyError>,
+}
+
+/// The response of endpoints that have no body when they succeed
+#[derive(Serialize, Deserialize, Debug)]
+struct ErrorResponse {
+    error: Option<SpotifyError>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RefreshTokenResponse {
     pub access_token: String,
@@ -139,8 +283,14 @@ impl SpotifyClient {
         SpotifyClient {
             access_token,
             refresh_token: None,
+            credentials: None,
         }
     }
+    /// Set the app credentials used to refresh the access token
+    pub fn set_credentials(mut self, credentials: SpotifyCredentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
     /// Set the refresh token for the client
     pub fn set_refresh_token(mut self, refresh_token: Option<String>) -> Self {
         self.refresh_token = refresh_token;
@@ -164,7 +314,11 @@ impl SpotifyClient {
             }
         };
         // Get new token from Spotify
-        let new_token = Self::request_access_token(refresh_token.clone())
+        let credentials = self.credentials.as_ref().ok_or(SpotifyError {
+            status: 500,
+            message: "Missing Spotify credentials".to_string(),
+        })?;
+        let new_token = Self::request_access_token(credentials, refresh_token.clone())
             .await
             .map_err(|err| {
                 error!("Failed to fetch new access token from Spotify {:?}", err);
@@ -182,7 +336,7 @@ impl SpotifyClient {
             "Fetching recent tracks from Spotify using access token {}",
             self.access_token
         );
-        const ENDPOINT: &'static str = "https://api.spotify.com/v1/me/player/recently-played";
+        const ENDPOINT: &str = "https://api.spotify.com/v1/me/player/recently-played";
         let tracks: RecentTracksResponse = surf::get(ENDPOINT)
             .header("Authorization", format!("Bearer {}", self.access_token))
             .recv_json()
@@ -201,20 +355,232 @@ impl SpotifyClient {
         }
         Ok(tracks)
     }
-    /// Send request to Spotify to refresh the access token
-    pub(crate) async fn request_access_token(
-        refresh_token: String,
-    ) -> Result<RefreshTokenResponse, SpotifyError> {
-        const ENDPOINT: &'static str = "https://accounts.spotify.com/api/token";
-        let client_id = std::env::var("SPOTIFY_ID").map_err(|_| SpotifyError {
+    /// Fetch the track currently playing for the user
+    /// Returns None if nothing is playing, which Spotify signals with a 204 No Content
+    pub async fn get_currently_playing(&self) -> Result<Option<CurrentlyPlaying>, SpotifyError> {
+        const ENDPOINT: &str = "https://api.spotify.com/v1/me/player/currently-playing";
+        let mut res = surf::get(ENDPOINT)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .await
+            .map_err(|err| {
+                error!("Failed to fetch currently playing from spotify {:?}", err);
+                SpotifyError {
+                    status: 500,
+                    message: "Internal error requesting currently playing from Spotify".to_string(),
+                }
+            })?;
+        if res.status() == surf::StatusCode::NoContent {
+            return Ok(None);
+        }
+        let current: CurrentlyPlayingResponse = res.body_json().await.map_err(|err| {
+            error!("Failed to parse json from spotify {:?}", err);
+            SpotifyError {
+                status: 500,
+                message: "Internal error parsing currently playing from Spotify".to_string(),
+            }
+        })?;
+        if let Some(error) = current.error {
+            return Err(error);
+        }
+        Ok(current.currently_playing)
+    }
+    /// Fetch the full details of artists, including their genres
+    pub async fn get_artists(&self, ids: &[String]) -> Result<Vec<FullArtist>, SpotifyError> {
+        // Spotify only allows fetching 50 artists at a time
+        const ENDPOINT: &str = "https://api.spotify.com/v1/artists";
+        let mut artists = vec![];
+        for chunk in ids.chunks(50) {
+            let res: ArtistsResponse = surf::get(ENDPOINT)
+                .query(&[("ids", chunk.join(","))])
+                .map_err(|err| {
+                    error!("Failed to build artists query {:?}", err);
+                    SpotifyError {
+                        status: 500,
+                        message: "Internal error requesting artists from Spotify".to_string(),
+                    }
+                })?
+                .header("Authorization", format!("Bearer {}", self.access_token))
+                .recv_json()
+                .await
+                .map_err(|err| {
+                    error!("Failed to fetch json from spotify {:?}", err);
+                    SpotifyError {
+                        status: 500,
+                        message: "Internal error requesting artists from Spotify".to_string(),
+                    }
+                })?;
+            if let Some(error) = res.error {
+                return Err(error);
+            }
+            // Artists that couldn't be found are returned as null
+            artists.extend(res.artists.unwrap_or_default().into_iter().flatten());
+        }
+        Ok(artists)
+    }
+    /// Fetch the profile of the user the access token belongs to
+    pub async fn get_current_user(&self) -> Result<SpotifyProfile, SpotifyError> {
+        const ENDPOINT: &str = "https://api.spotify.com/v1/me";
+        let res: SpotifyProfileResponse = surf::get(ENDPOINT)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .recv_json()
+            .await
+            .map_err(|err| {
+                error!("Failed to fetch json from spotify {:?}", err);
+                SpotifyError {
+                    status: 500,
+                    message: "Internal error requesting profile from Spotify".to_string(),
+                }
+            })?;
+        if let Some(error) = res.error {
+            return Err(error);
+        }
+        res.profile.ok_or(SpotifyError {
             status: 500,
-            message: "Missing Spotify Client ID".to_string(),
+            message: "Spotify returned an empty profile".to_string(),
+        })
+    }
+    /// Create a private playlist for a Spotify user, which has to be the user the access token belongs to
+    pub async fn create_playlist(
+        &self,
+        spotify_user_id: &str,
+        name: &str,
+        description: &str,
+    ) -> Result<SpotifyPlaylist, SpotifyError> {
+        const ENDPOINT: &str = "https://api.spotify.com/v1/users";
+        let body = surf::Body::from_json(&serde_json::json!({
+            "name": name,
+            "description": description,
+            "public": false,
+        }))
+        .map_err(|err| {
+            error!("Failed to serialize playlist {:?}", err);
+            SpotifyError {
+                status: 500,
+                message: "Internal error creating playlist on Spotify".to_string(),
+            }
         })?;
-        let client_secret = std::env::var("SPOTIFY_SECRET").map_err(|_| SpotifyError {
+        let res: SpotifyPlaylistResponse =
+            surf::post(format!("{}/{}/playlists", ENDPOINT, spotify_user_id))
+                .header("Authorization", format!("Bearer {}", self.access_token))
+                .body(body)
+                .recv_json()
+                .await
+                .map_err(|err| {
+                    error!("Failed to fetch json from spotify {:?}", err);
+                    SpotifyError {
+                        status: 500,
+                        message: "Internal error creating playlist on Spotify".to_string(),
+                    }
+                })?;
+        if let Some(error) = res.error {
+            return Err(error);
+        }
+        res.playlist.ok_or(SpotifyError {
             status: 500,
-            message: "Missing Spotify Client Secret".to_string(),
+            message: "Spotify returned an empty playlist".to_string(),
+        })
+    }
+    /// Change the name and description of a playlist
+    pub async fn update_playlist_details(
+        &self,
+        playlist_id: &str,
+        name: &str,
+        description: &str,
+    ) -> Result<(), SpotifyError> {
+        const ENDPOINT: &str = "https://api.spotify.com/v1/playlists";
+        let body = surf::Body::from_json(&serde_json::json!({
+            "name": name,
+            "description": description,
+        }))
+        .map_err(|err| {
+            error!("Failed to serialize playlist details {:?}", err);
+            SpotifyError {
+                status: 500,
+                message: "Internal error updating playlist on Spotify".to_string(),
+            }
+        })?;
+        let mut res = surf::put(format!("{}/{}", ENDPOINT, playlist_id))
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .body(body)
+            .await
+            .map_err(|err| {
+                error!("Failed to update playlist on spotify {:?}", err);
+                SpotifyError {
+                    status: 500,
+                    message: "Internal error updating playlist on Spotify".to_string(),
+                }
+            })?;
+        if res.status().is_success() {
+            return Ok(());
+        }
+        // Spotify only sends a body when the update failed
+        let status = res.status() as u16;
+        let res: ErrorResponse = res
+            .body_json()
+            .await
+            .unwrap_or(ErrorResponse { error: None });
+        Err(res.error.unwrap_or(SpotifyError {
+            status,
+            message: "Spotify failed to update the playlist".to_string(),
+        }))
+    }
+    /// Replace all the items of a playlist with the tracks of the URIs, in order
+    pub async fn replace_playlist_items(
+        &self,
+        playlist_id: &str,
+        uris: &[String],
+    ) -> Result<(), SpotifyError> {
+        // Only the first chunk replaces the items, the rest are added after it
+        const ENDPOINT: &str = "https://api.spotify.com/v1/playlists";
+        let url = format!("{}/{}/items", ENDPOINT, playlist_id);
+        let mut chunks = uris.chunks(PLAYLIST_ITEMS_CHUNK_SIZE);
+        let first = chunks.next().unwrap_or_default();
+        self.send_playlist_items(surf::put(&url), first).await?;
+        for chunk in chunks {
+            self.send_playlist_items(surf::post(&url), chunk).await?;
+        }
+        Ok(())
+    }
+    /// Send a request changing the items of a playlist
+    async fn send_playlist_items(
+        &self,
+        request: surf::RequestBuilder,
+        uris: &[String],
+    ) -> Result<(), SpotifyError> {
+        let body = surf::Body::from_json(&serde_json::json!({ "uris": uris })).map_err(|err| {
+            error!("Failed to serialize playlist items {:?}", err);
+            SpotifyError {
+                status: 500,
+                message: "Internal error updating playlist items on Spotify".to_string(),
+            }
         })?;
-        let auth = BASE64_STANDARD.encode(format!("{}:{}", client_id, client_secret));
+        let res: PlaylistSnapshotResponse = request
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .body(body)
+            .recv_json()
+            .await
+            .map_err(|err| {
+                error!("Failed to fetch json from spotify {:?}", err);
+                SpotifyError {
+                    status: 500,
+                    message: "Internal error updating playlist items on Spotify".to_string(),
+                }
+            })?;
+        if let Some(error) = res.error {
+            return Err(error);
+        }
+        Ok(())
+    }
+    /// Send request to Spotify to refresh the access token
+    pub(crate) async fn request_access_token(
+        credentials: &SpotifyCredentials,
+        refresh_token: String,
+    ) -> Result<RefreshTokenResponse, SpotifyError> {
+        const ENDPOINT: &str = "https://accounts.spotify.com/api/token";
+        let auth = BASE64_STANDARD.encode(format!(
+            "{}:{}",
+            credentials.client_id, credentials.client_secret
+        ));
         let body = format!("grant_type=refresh_token&refresh_token={}", refresh_token);
         let token: RefreshTokenResponse = surf::post(ENDPOINT)
             .header("Authorization", format!("Basic {}", auth))
@@ -236,3 +602,60 @@ impl SpotifyClient {
         Ok(token)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn album(release_date: &str, precision: Option<&str>) -> Album {
+        serde_json::from_value(serde_json::json!({
+            "id": "album",
+            "images": [],
+            "name": "Album",
+            "release_date": release_date,
+            "release_date_precision": precision,
+            "album_type": "album",
+            "external_urls": { "spotify": "" },
+            "artists": [],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn playlists_only_get_spotify_tracks() {
+        let (uris, skipped) = playlist_uris(vec![
+            Some("spotify:track:AIR".to_string()),
+            Some("musicbrainz:recording:rec-hunter".to_string()),
+            None,
+            Some("spotify:album:OKC".to_string()),
+            Some("spotify:track:JOGA".to_string()),
+        ]);
+        assert_eq!(uris, vec!["spotify:track:AIR", "spotify:track:JOGA"]);
+        assert_eq!(skipped, 3);
+    }
+
+    #[test]
+    fn release_dates_are_padded_to_their_precision() {
+        assert_eq!(
+            album("1997-05-21", Some("day")).release_date(),
+            NaiveDate::from_ymd_opt(1997, 5, 21)
+        );
+        assert_eq!(
+            album("1997-05", Some("month")).release_date(),
+            NaiveDate::from_ymd_opt(1997, 5, 1)
+        );
+        assert_eq!(
+            album("1997", Some("year")).release_date(),
+            NaiveDate::from_ymd_opt(1997, 1, 1)
+        );
+    }
+
+    #[test]
+    fn unknown_release_dates_are_left_out() {
+        assert_eq!(album("1997", None).release_date(), None);
+        assert_eq!(album("1997-13", Some("month")).release_date(), None);
+        assert_eq!(album("", Some("day")).release_date(), None);
+        assert_eq!(album("1997", None).model().release_date.unwrap(), None);
+    }
+}
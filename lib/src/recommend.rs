@@ -0,0 +1,373 @@
+use chrono::Utc;
+use sea_orm::{prelude::DateTime, DatabaseConnection, DbBackend, FromQueryResult, Statement};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use tracing::error;
+
+use crate::{
+    analytics::{
+        compare::CatalogItem, listening::DEFAULT_SESSION_GAP_MINUTES, top::Grouping, TimeWindow,
+    },
+    db::{
+        catalog::{self, ArtistSummary, TrackDetails},
+        DBError,
+    },
+};
+
+/// The number of the user's most played tracks and artists that recommendations are based on
+pub const SEED_SIZE: u64 = 20;
+/// How much co-listening counts towards the score of a recommendation, the rest is from artist affinity
+const CO_LISTENING_WEIGHT: f64 = 0.6;
+/// The number of days after which a play counts half as much towards the affinity for its artists
+const AFFINITY_HALF_LIFE_DAYS: f64 = 90.0;
+/// The most candidates that are looked at from each source before scoring
+const CANDIDATE_LIMIT: u64 = 500;
+
+/// Where recommendations can come from
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Things the user has played before, but not within the window
+    Own,
+    /// Things other users of the instance played that the user never has
+    Instance,
+    /// Both of the above
+    #[default]
+    All,
+}
+
+impl Scope {
+    /// Whether something can be recommended, given when the user last played it
+    fn allows(&self, last_played_at: Option<&DateTime>, window: TimeWindow) -> bool {
+        match (self, last_played_at) {
+            (_, Some(played_at)) if *played_at >= window.from => false,
+            (Scope::Own, None) | (Scope::Instance, Some(_)) => false,
+            _ => true,
+        }
+    }
+}
+
+/// Something recommended to the user
+#[derive(Serialize, Debug)]
+pub struct Recommendation<T> {
+    #[serde(flatten)]
+    pub item: T,
+    /// The combined score, between 0 and 1
+    pub score: f64,
+    /// How often it was played in the same sessions as the user's favorites, between 0 and 1
+    pub co_listening: f64,
+    /// How much the user has been listening to its artists lately, between 0 and 1
+    pub affinity: f64,
+}
+
+/// The tracks and artists recommended to the user
+#[derive(Serialize, Debug)]
+pub struct Recommendations {
+    pub tracks: Vec<Recommendation<TrackDetails>>,
+    pub artists: Vec<Recommendation<ArtistSummary>>,
+}
+
+#[derive(FromQueryResult, Debug)]
+struct Scored {
+    id: i32,
+    score: f64,
+}
+
+#[derive(FromQueryResult, Debug)]
+struct LastPlay {
+    id: i32,
+    last_played_at: DateTime,
+}
+
+/// Score everything in a grouping by how many listening sessions it shares with the user's most played
+/// in the window, over the sessions of every user of the instance
+/// Sessions are split wherever plays are further apart than the session gap
+async fn co_listened(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    grouping: Grouping,
+    window: TimeWindow,
+) -> Result<Vec<Scored>, DBError> {
+    let sql = format!(
+        r#"WITH plays AS (
+            SELECT DISTINCT play_log.id, play_log.user_id, play_log.played_at, {group_by} AS group_id
+            FROM play_log
+            INNER JOIN album_track ON album_track.track_id = play_log.track_id
+            INNER JOIN album_artist ON album_artist.album_id = album_track.album_id
+        ),
+        gaps AS (
+            SELECT id, user_id, played_at,
+                CASE WHEN played_at - LAG(played_at) OVER (PARTITION BY user_id ORDER BY played_at, id)
+                    <= make_interval(mins => $5) THEN 0 ELSE 1 END AS starts_session
+            FROM play_log
+        ),
+        sessions AS (
+            SELECT id, user_id,
+                SUM(starts_session) OVER (PARTITION BY user_id ORDER BY played_at, id) AS session
+            FROM gaps
+        ),
+        seeds AS (
+            SELECT group_id
+            FROM plays
+            WHERE user_id = $1 AND played_at >= $2 AND played_at < $3
+            GROUP BY group_id
+            ORDER BY COUNT(*) DESC, group_id
+            LIMIT $4
+        ),
+        seed_sessions AS (
+            SELECT DISTINCT sessions.user_id, sessions.session
+            FROM sessions
+            INNER JOIN plays ON plays.id = sessions.id
+            WHERE plays.group_id IN (SELECT group_id FROM seeds)
+        )
+        SELECT plays.group_id AS id,
+            CAST(COUNT(DISTINCT (sessions.user_id, sessions.session)) AS DOUBLE PRECISION) AS score
+        FROM sessions
+        INNER JOIN seed_sessions ON seed_sessions.user_id = sessions.user_id
+            AND seed_sessions.session = sessions.session
+        INNER JOIN plays ON plays.id = sessions.id
+        WHERE plays.group_id NOT IN (SELECT group_id FROM seeds)
+        GROUP BY plays.group_id
+        ORDER BY score DESC, id
+        LIMIT $6"#,
+        group_by = grouping.column(),
+    );
+    Scored::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        sql,
+        [
+            user_id.into(),
+            window.from.into(),
+            window.to.into(),
+            (SEED_SIZE as i64).into(),
+            (DEFAULT_SESSION_GAP_MINUTES as i32).into(),
+            (CANDIDATE_LIMIT as i64).into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!(
+            "Error scoring co-listening by {:?}: {:?}",
+            grouping, sea_err
+        );
+        DBError
+    })
+}
+
+/// Score every artist the user has played by their plays, with older plays counting for less
+async fn artist_affinities(
+    conn: &DatabaseConnection,
+    user_id: &str,
+) -> Result<HashMap<i32, f64>, DBError> {
+    let affinities = Scored::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT artist_id AS id,
+            CAST(SUM(POWER(0.5, EXTRACT(EPOCH FROM ($2 - played_at)) / 86400 / $3)) AS DOUBLE PRECISION)
+                AS score
+        FROM (
+            SELECT DISTINCT play_log.id, play_log.played_at, album_artist.artist_id
+            FROM play_log
+            INNER JOIN album_track ON album_track.track_id = play_log.track_id
+            INNER JOIN album_artist ON album_artist.album_id = album_track.album_id
+            WHERE play_log.user_id = $1
+        ) AS plays
+        GROUP BY artist_id"#,
+        [
+            user_id.into(),
+            Utc::now().naive_utc().into(),
+            AFFINITY_HALF_LIFE_DAYS.into(),
+        ],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error scoring artist affinity: {:?}", sea_err);
+        DBError
+    })?;
+    Ok(affinities
+        .into_iter()
+        .map(|affinity| (affinity.id, affinity.score))
+        .collect())
+}
+
+/// Find the tracks in the instance catalog by the artists the user has the most affinity for
+async fn affinity_tracks(
+    conn: &DatabaseConnection,
+    affinities: &HashMap<i32, f64>,
+) -> Result<Vec<i32>, DBError> {
+    let mut artists: Vec<(&i32, &f64)> = affinities.iter().collect();
+    artists.sort_by(|a, b| b.1.total_cmp(a.1).then(a.0.cmp(b.0)));
+    let artist_ids: Vec<String> = artists
+        .into_iter()
+        .take(SEED_SIZE as usize)
+        .map(|(id, _)| id.to_string())
+        .collect();
+    if artist_ids.is_empty() {
+        return Ok(vec![]);
+    }
+    // The IDs are integers, so they can safely be put in the query
+    let sql = format!(
+        r#"SELECT DISTINCT album_track.track_id AS id, CAST(0 AS DOUBLE PRECISION) AS score
+        FROM album_artist
+        INNER JOIN album_track ON album_track.album_id = album_artist.album_id
+        WHERE album_artist.artist_id IN ({artist_ids})
+        ORDER BY id
+        LIMIT $1"#,
+        artist_ids = artist_ids.join(", "),
+    );
+    let tracks = Scored::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        sql,
+        [(CANDIDATE_LIMIT as i64).into()],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!("Error finding tracks by favorite artists: {:?}", sea_err);
+        DBError
+    })?;
+    Ok(tracks.into_iter().map(|track| track.id).collect())
+}
+
+/// Get when the user last played everything in a grouping
+async fn last_plays(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    grouping: Grouping,
+) -> Result<HashMap<i32, DateTime>, DBError> {
+    let sql = format!(
+        r#"SELECT {group_by} AS id, MAX(play_log.played_at) AS last_played_at
+        FROM play_log
+        INNER JOIN album_track ON album_track.track_id = play_log.track_id
+        INNER JOIN album_artist ON album_artist.album_id = album_track.album_id
+        WHERE play_log.user_id = $1
+        GROUP BY {group_by}"#,
+        group_by = grouping.column(),
+    );
+    let plays = LastPlay::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        sql,
+        [user_id.into()],
+    ))
+    .all(conn)
+    .await
+    .map_err(|sea_err| {
+        error!(
+            "Error looking up last plays by {:?}: {:?}",
+            grouping, sea_err
+        );
+        DBError
+    })?;
+    Ok(plays
+        .into_iter()
+        .map(|play| (play.id, play.last_played_at))
+        .collect())
+}
+
+/// Combine the co-listening and affinity of the candidates into scores, ordered by the best score
+/// Both are scaled by their highest value, so they weigh the same no matter how much was played
+fn rank<T: CatalogItem>(
+    candidates: Vec<(T, f64, f64)>,
+    max_co_listening: f64,
+    max_affinity: f64,
+    limit: u64,
+) -> Vec<Recommendation<T>> {
+    let scale = |value: f64, max: f64| if max > 0.0 { value / max } else { 0.0 };
+    let mut ranked: Vec<Recommendation<T>> = candidates
+        .into_iter()
+        .map(|(item, co_listening, affinity)| {
+            let co_listening = scale(co_listening, max_co_listening);
+            let affinity = scale(affinity, max_affinity);
+            Recommendation {
+                item,
+                score: CO_LISTENING_WEIGHT * co_listening + (1.0 - CO_LISTENING_WEIGHT) * affinity,
+                co_listening,
+                affinity,
+            }
+        })
+        .collect();
+    ranked.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(a.item.catalog_id().cmp(&b.item.catalog_id()))
+    });
+    ranked.truncate(limit as usize);
+    ranked
+}
+
+/// Recommend tracks and artists to a user, based on what they played most in the window
+/// Nothing played by the user within the window is recommended
+pub async fn recommend(
+    conn: &DatabaseConnection,
+    user_id: &str,
+    window: TimeWindow,
+    scope: Scope,
+    limit: u64,
+) -> Result<Recommendations, DBError> {
+    let (co_tracks, co_artists, affinities, last_track_plays, last_artist_plays) = tokio::try_join!(
+        co_listened(conn, user_id, Grouping::Track, window),
+        co_listened(conn, user_id, Grouping::Artist, window),
+        artist_affinities(conn, user_id),
+        last_plays(conn, user_id, Grouping::Track),
+        last_plays(conn, user_id, Grouping::Artist),
+    )?;
+    let max_affinity = affinities.values().copied().fold(0.0, f64::max);
+    // Tracks come from co-listening, and from the catalog of the artists with the most affinity
+    let co_track_scores: HashMap<i32, f64> = co_tracks
+        .iter()
+        .map(|scored| (scored.id, scored.score))
+        .collect();
+    let mut track_ids: Vec<i32> = co_tracks.iter().map(|scored| scored.id).collect();
+    track_ids.extend(affinity_tracks(conn, &affinities).await?);
+    let mut seen = HashSet::new();
+    track_ids.retain(|id| seen.insert(*id) && scope.allows(last_track_plays.get(id), window));
+    let mut tracks = catalog::get_track_details(conn, track_ids).await?;
+    let track_candidates = tracks
+        .drain()
+        .map(|(id, track)| {
+            let affinity = track
+                .artists
+                .iter()
+                .filter_map(|artist| affinities.get(&artist.id))
+                .copied()
+                .fold(0.0, f64::max);
+            let co_listening = co_track_scores.get(&id).copied().unwrap_or_default();
+            (track, co_listening, affinity)
+        })
+        .collect();
+    let max_co_track = co_tracks
+        .first()
+        .map(|scored| scored.score)
+        .unwrap_or_default();
+    // Artists come from co-listening, and from the artists the user has the most affinity for
+    let co_artist_scores: HashMap<i32, f64> = co_artists
+        .iter()
+        .map(|scored| (scored.id, scored.score))
+        .collect();
+    let artist_ids: Vec<i32> = co_artist_scores
+        .keys()
+        .chain(affinities.keys())
+        .copied()
+        .collect::<HashSet<i32>>()
+        .into_iter()
+        .filter(|id| scope.allows(last_artist_plays.get(id), window))
+        .collect();
+    let mut artists = catalog::get_artists_by_ids(conn, artist_ids).await?;
+    let artist_candidates = artists
+        .drain()
+        .map(|(id, artist)| {
+            let co_listening = co_artist_scores.get(&id).copied().unwrap_or_default();
+            let affinity = affinities.get(&id).copied().unwrap_or_default();
+            (artist, co_listening, affinity)
+        })
+        .collect();
+    let max_co_artist = co_artists
+        .first()
+        .map(|scored| scored.score)
+        .unwrap_or_default();
+    Ok(Recommendations {
+        tracks: rank(track_candidates, max_co_track, max_affinity, limit),
+        artists: rank(artist_candidates, max_co_artist, max_affinity, limit),
+    })
+}
@@ -6,6 +6,22 @@ mod m20240813_170819_init_tracks;
 mod m20240813_170827_init_playlog;
 mod m20240820_031732_init_users;
 mod m20240820_031738_init_accounts;
+mod m20241018_140000_init_play_sessions;
+mod m20241018_150000_init_sessions;
+mod m20241018_150100_add_play_log_user;
+mod m20241018_160000_add_track_duration;
+mod m20241018_170000_init_genres;
+mod m20241018_170100_init_reports;
+mod m20241018_180000_init_user_settings;
+mod m20241018_190000_add_track_uri;
+mod m20241018_190100_init_playlists;
+mod m20241018_200000_encrypt_account_tokens;
+mod m20241018_210000_init_api_keys;
+mod m20241018_220000_add_play_log_account;
+mod m20241018_230000_add_roles_and_collection_status;
+mod m20241018_240000_dedupe_catalog;
+mod m20241018_250000_add_allow_comparisons;
+mod m20241018_260000_optional_album_release_date;
 
 pub struct Migrator;
 
@@ -19,6 +35,22 @@ impl MigratorTrait for Migrator {
             Box::new(m20240813_170827_init_playlog::Migration),
             Box::new(m20240820_031732_init_users::Migration),
             Box::new(m20240820_031738_init_accounts::Migration),
+            Box::new(m20241018_140000_init_play_sessions::Migration),
+            Box::new(m20241018_150000_init_sessions::Migration),
+            Box::new(m20241018_150100_add_play_log_user::Migration),
+            Box::new(m20241018_160000_add_track_duration::Migration),
+            Box::new(m20241018_170000_init_genres::Migration),
+            Box::new(m20241018_170100_init_reports::Migration),
+            Box::new(m20241018_180000_init_user_settings::Migration),
+            Box::new(m20241018_190000_add_track_uri::Migration),
+            Box::new(m20241018_190100_init_playlists::Migration),
+            Box::new(m20241018_200000_encrypt_account_tokens::Migration),
+            Box::new(m20241018_210000_init_api_keys::Migration),
+            Box::new(m20241018_220000_add_play_log_account::Migration),
+            Box::new(m20241018_230000_add_roles_and_collection_status::Migration),
+            Box::new(m20241018_240000_dedupe_catalog::Migration),
+            Box::new(m20241018_250000_add_allow_comparisons::Migration),
+            Box::new(m20241018_260000_optional_album_release_date::Migration),
         ]
     }
 }
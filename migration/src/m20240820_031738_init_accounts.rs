@@ -39,7 +39,7 @@ impl MigrationTrait for Migration {
 }
 
 #[derive(DeriveIden)]
-enum Account {
+pub enum Account {
     Table,
     Id,
     Provider,
@@ -0,0 +1,77 @@
+use crate::m20240813_170819_init_tracks::Track;
+use crate::m20240820_031732_init_users::User;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // A play session is a single play of a track built from the currently playing track,
+        // unlike the play log it knows how long the track was actually listened to
+        manager
+            .create_table(
+                Table::create()
+                    .table(PlaySession::Table)
+                    .if_not_exists()
+                    .col(pk_auto(PlaySession::Id))
+                    .col(ColumnDef::new(PlaySession::UserId).string().not_null())
+                    .col(ColumnDef::new(PlaySession::TrackId).integer().not_null())
+                    .col(
+                        ColumnDef::new(PlaySession::StartedAt)
+                            .timestamp()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(PlaySession::EndedAt).timestamp().not_null())
+                    .col(big_integer(PlaySession::MsPlayed))
+                    .col(boolean(PlaySession::Skipped))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_play_session_user_id")
+                            .from(PlaySession::Table, PlaySession::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_play_session_track_id")
+                            .from(PlaySession::Table, PlaySession::TrackId)
+                            .to(Track::Table, Track::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // A user can only start one play at a time
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_play_session_user_id_started_at")
+                    .table(PlaySession::Table)
+                    .col(PlaySession::UserId)
+                    .col(PlaySession::StartedAt)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PlaySession::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum PlaySession {
+    Table,
+    Id,
+    UserId,
+    TrackId,
+    StartedAt,
+    EndedAt,
+    MsPlayed,
+    Skipped,
+}
@@ -0,0 +1,90 @@
+use crate::m20240820_031732_init_users::User;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Play logs belong to the user that played them
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PlayLog::Table)
+                    .add_column(ColumnDef::new(PlayLog::UserId).string().null())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk_play_log_user_id")
+                            .from_tbl(PlayLog::Table)
+                            .from_col(PlayLog::UserId)
+                            .to_tbl(User::Table)
+                            .to_col(User::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // Existing play logs were collected before users existed, if there is only one user
+        // they can only belong to them
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"UPDATE play_log SET user_id = (SELECT id FROM "user")
+                WHERE user_id IS NULL AND (SELECT COUNT(*) FROM "user") = 1"#,
+            )
+            .await?;
+        // Two users can play a track at the same time, so played_at is only unique per user
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "ALTER TABLE play_log DROP CONSTRAINT IF EXISTS play_log_played_at_key",
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_play_log_user_id_played_at")
+                    .table(PlayLog::Table)
+                    .col(PlayLog::UserId)
+                    .col(PlayLog::PlayedAt)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_play_log_user_id_played_at")
+                    .table(PlayLog::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "ALTER TABLE play_log ADD CONSTRAINT play_log_played_at_key UNIQUE (played_at)",
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PlayLog::Table)
+                    .drop_foreign_key(Alias::new("fk_play_log_user_id"))
+                    .drop_column(PlayLog::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PlayLog {
+    Table,
+    UserId,
+    PlayedAt,
+}
@@ -0,0 +1,80 @@
+use crate::m20240813_164238_init_artists::Artist;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // First, create the `genre` table
+        manager
+            .create_table(
+                Table::create()
+                    .table(Genre::Table)
+                    .if_not_exists()
+                    .col(pk_auto(Genre::Id))
+                    .col(string(Genre::Name).unique_key())
+                    .to_owned(),
+            )
+            .await?;
+        // Next, create the junctions table between `artists` and `genres`
+        manager
+            .create_table(
+                Table::create()
+                    .table(ArtistGenre::Table)
+                    .if_not_exists()
+                    .primary_key(
+                        Index::create()
+                            .name("pk_artist_genre")
+                            .col(ArtistGenre::ArtistId)
+                            .col(ArtistGenre::GenreId),
+                    )
+                    .col(ColumnDef::new(ArtistGenre::ArtistId).integer().not_null())
+                    .col(ColumnDef::new(ArtistGenre::GenreId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_artist_genre_artist_id")
+                            .from(ArtistGenre::Table, ArtistGenre::ArtistId)
+                            .to(Artist::Table, Artist::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_artist_genre_genre_id")
+                            .from(ArtistGenre::Table, ArtistGenre::GenreId)
+                            .to(Genre::Table, Genre::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(
+                Table::drop()
+                    .table(ArtistGenre::Table)
+                    .table(Genre::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum Genre {
+    Table,
+    Id,
+    Name,
+}
+
+#[derive(DeriveIden)]
+enum ArtistGenre {
+    Table,
+    ArtistId,
+    GenreId,
+}
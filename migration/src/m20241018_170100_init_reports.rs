@@ -0,0 +1,66 @@
+use crate::m20240820_031732_init_users::User;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // A report is a snapshot of a user's year in review, stored as JSON
+        manager
+            .create_table(
+                Table::create()
+                    .table(Report::Table)
+                    .if_not_exists()
+                    .col(pk_auto(Report::Id))
+                    .col(ColumnDef::new(Report::UserId).string().not_null())
+                    .col(integer(Report::Year))
+                    .col(json_binary(Report::Data))
+                    .col(
+                        ColumnDef::new(Report::CreatedAt)
+                            .not_null()
+                            .timestamp()
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_report_user_id")
+                            .from(Report::Table, Report::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // Each user has a single report per year
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_report_user_id_year")
+                    .table(Report::Table)
+                    .col(Report::UserId)
+                    .col(Report::Year)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Report::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum Report {
+    Table,
+    Id,
+    UserId,
+    Year,
+    Data,
+    CreatedAt,
+}
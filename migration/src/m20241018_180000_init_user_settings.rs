@@ -0,0 +1,54 @@
+use crate::m20240820_031732_init_users::User;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Each user has a single row of preferences, used to bucket their plays where they live
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserSettings::Table)
+                    .if_not_exists()
+                    .col(string(UserSettings::UserId).primary_key())
+                    .col(string(UserSettings::Timezone).default("UTC"))
+                    .col(string(UserSettings::WeekStart).default("monday"))
+                    .col(string(UserSettings::Locale).default("en-US"))
+                    .col(
+                        ColumnDef::new(UserSettings::UpdatedAt)
+                            .not_null()
+                            .timestamp()
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_user_settings_user_id")
+                            .from(UserSettings::Table, UserSettings::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserSettings::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum UserSettings {
+    Table,
+    UserId,
+    Timezone,
+    WeekStart,
+    Locale,
+    UpdatedAt,
+}
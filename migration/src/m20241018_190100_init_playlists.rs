@@ -0,0 +1,87 @@
+use crate::{m20240820_031732_init_users::User, m20240820_031738_init_accounts::Account};
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // A playlist generated from analytics and saved to the provider, so it can be updated in place
+        manager
+            .create_table(
+                Table::create()
+                    .table(Playlist::Table)
+                    .if_not_exists()
+                    .col(pk_auto(Playlist::Id))
+                    .col(ColumnDef::new(Playlist::UserId).string().not_null())
+                    .col(integer(Playlist::AccountId))
+                    .col(string(Playlist::Kind))
+                    .col(string(Playlist::ProviderId))
+                    .col(string(Playlist::Name))
+                    .col(integer(Playlist::TrackCount))
+                    .col(
+                        ColumnDef::new(Playlist::CreatedAt)
+                            .not_null()
+                            .timestamp()
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .col(
+                        ColumnDef::new(Playlist::UpdatedAt)
+                            .not_null()
+                            .timestamp()
+                            .default(SimpleExpr::Keyword(Keyword::CurrentTimestamp)),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_playlist_user_id")
+                            .from(Playlist::Table, Playlist::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_playlist_account_id")
+                            .from(Playlist::Table, Playlist::AccountId)
+                            .to(Account::Table, Account::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // Each account has a single generated playlist of each kind, which is updated when regenerated
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_playlist_account_id_kind")
+                    .table(Playlist::Table)
+                    .col(Playlist::AccountId)
+                    .col(Playlist::Kind)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Playlist::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum Playlist {
+    Table,
+    Id,
+    UserId,
+    AccountId,
+    Kind,
+    ProviderId,
+    Name,
+    TrackCount,
+    CreatedAt,
+    UpdatedAt,
+}
@@ -0,0 +1,124 @@
+use crypto::{TokenColumn, WrappedKey};
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, Statement},
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // The columns start out nullable, so the existing rows can be encrypted before requiring them
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Account::Table)
+                    .add_column(ColumnDef::new(Account::KeyId).string().null())
+                    .add_column(ColumnDef::new(Account::DataKey).string().null())
+                    .to_owned(),
+            )
+            .await?;
+        let conn = manager.get_connection();
+        let backend = manager.get_database_backend();
+        let rows = conn
+            .query_all(Statement::from_string(
+                backend,
+                "SELECT id, access_token, refresh_token FROM account",
+            ))
+            .await?;
+        if !rows.is_empty() {
+            let keyring = crypto::keyring().map_err(|err| DbErr::Custom(err.to_string()))?;
+            for row in rows {
+                let id: i32 = row.try_get("", "id")?;
+                let access_token: String = row.try_get("", "access_token")?;
+                let refresh_token: String = row.try_get("", "refresh_token")?;
+                let (key, wrapped) = keyring
+                    .new_data_key()
+                    .map_err(|err| DbErr::Custom(err.to_string()))?;
+                let encrypt = |column: TokenColumn, token: &str| {
+                    key.encrypt(id, column, token)
+                        .map_err(|err| DbErr::Custom(err.to_string()))
+                };
+                conn.execute(Statement::from_sql_and_values(
+                    backend,
+                    r#"UPDATE account
+                    SET access_token = $1, refresh_token = $2, key_id = $3, data_key = $4
+                    WHERE id = $5"#,
+                    [
+                        encrypt(TokenColumn::AccessToken, &access_token)?.into(),
+                        encrypt(TokenColumn::RefreshToken, &refresh_token)?.into(),
+                        wrapped.key_id.into(),
+                        wrapped.data_key.into(),
+                        id.into(),
+                    ],
+                ))
+                .await?;
+            }
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Account::Table)
+                    .modify_column(ColumnDef::new(Account::KeyId).string().not_null())
+                    .modify_column(ColumnDef::new(Account::DataKey).string().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let conn = manager.get_connection();
+        let backend = manager.get_database_backend();
+        let rows = conn
+            .query_all(Statement::from_string(
+                backend,
+                "SELECT id, access_token, refresh_token, key_id, data_key FROM account",
+            ))
+            .await?;
+        if !rows.is_empty() {
+            let keyring = crypto::keyring().map_err(|err| DbErr::Custom(err.to_string()))?;
+            for row in rows {
+                let id: i32 = row.try_get("", "id")?;
+                let key = keyring
+                    .unwrap(&WrappedKey {
+                        key_id: row.try_get("", "key_id")?,
+                        data_key: row.try_get("", "data_key")?,
+                    })
+                    .map_err(|err| DbErr::Custom(err.to_string()))?;
+                let decrypt = |column: TokenColumn, name: &str| -> Result<String, DbErr> {
+                    let sealed: String = row.try_get("", name)?;
+                    key.decrypt(id, column, &sealed)
+                        .map_err(|err| DbErr::Custom(err.to_string()))
+                };
+                conn.execute(Statement::from_sql_and_values(
+                    backend,
+                    "UPDATE account SET access_token = $1, refresh_token = $2 WHERE id = $3",
+                    [
+                        decrypt(TokenColumn::AccessToken, "access_token")?.into(),
+                        decrypt(TokenColumn::RefreshToken, "refresh_token")?.into(),
+                        id.into(),
+                    ],
+                ))
+                .await?;
+            }
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Account::Table)
+                    .drop_column(Account::KeyId)
+                    .drop_column(Account::DataKey)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Account {
+    Table,
+    KeyId,
+    DataKey,
+}
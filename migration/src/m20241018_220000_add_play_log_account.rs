@@ -0,0 +1,101 @@
+use crate::m20240820_031738_init_accounts::Account;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // A user can link several accounts, so plays keep track of the account they were collected from
+        // Unlinking an account keeps its plays in the user's history, only the attribution is lost
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PlayLog::Table)
+                    .add_column(ColumnDef::new(PlayLog::AccountId).integer().null())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk_play_log_account_id")
+                            .from_tbl(PlayLog::Table)
+                            .from_col(PlayLog::AccountId)
+                            .to_tbl(Account::Table)
+                            .to_col(Account::Id)
+                            .on_delete(ForeignKeyAction::SetNull)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_play_log_account_id")
+                    .table(PlayLog::Table)
+                    .col(PlayLog::AccountId)
+                    .to_owned(),
+            )
+            .await?;
+        // Existing plays were collected from the only account users could have, their Spotify account
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "UPDATE play_log SET account_id = (
+                    SELECT account.id FROM account
+                    WHERE account.user_id = play_log.user_id AND account.provider = 'spotify'
+                    ORDER BY account.id LIMIT 1
+                ) WHERE account_id IS NULL",
+            )
+            .await?;
+        // Account IDs are only unique within their provider, once there's more than one provider
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "ALTER TABLE account DROP CONSTRAINT IF EXISTS account_provider_id_key",
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_account_provider_provider_id")
+                    .table(Account::Table)
+                    .col(Account::Provider)
+                    .col(Account::ProviderId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_account_provider_provider_id")
+                    .table(Account::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "ALTER TABLE account ADD CONSTRAINT account_provider_id_key UNIQUE (provider_id)",
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PlayLog::Table)
+                    .drop_foreign_key(Alias::new("fk_play_log_account_id"))
+                    .drop_column(PlayLog::AccountId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PlayLog {
+    Table,
+    AccountId,
+}
@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Users are members unless they're made admins, who can manage the whole instance
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column(
+                        ColumnDef::new(User::Role)
+                            .string()
+                            .not_null()
+                            .default("member"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // Admins can disable an account so nothing is collected from it, and see how collecting it last went
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Account::Table)
+                    .add_column(ColumnDef::new(Account::DisabledAt).timestamp().null())
+                    .add_column(ColumnDef::new(Account::LastCollectionAt).timestamp().null())
+                    .add_column(ColumnDef::new(Account::LastCollectedAt).timestamp().null())
+                    .add_column(ColumnDef::new(Account::LastCollectionError).string().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Account::Table)
+                    .drop_column(Account::DisabledAt)
+                    .drop_column(Account::LastCollectionAt)
+                    .drop_column(Account::LastCollectedAt)
+                    .drop_column(Account::LastCollectionError)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .drop_column(User::Role)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    Role,
+}
+
+#[derive(DeriveIden)]
+enum Account {
+    Table,
+    DisabledAt,
+    /// When collecting the account was last attempted
+    LastCollectionAt,
+    /// When collecting the account last succeeded
+    LastCollectedAt,
+    /// Why collecting the account last failed, cleared once it succeeds
+    LastCollectionError,
+}
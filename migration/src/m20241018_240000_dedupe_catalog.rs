@@ -0,0 +1,154 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Albums are matched on their Spotify URI from now on, like tracks,
+        // albums saved before this was added and those of local files won't have one
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Album::Table)
+                    .add_column(ColumnDef::new(Album::Uri).string().null())
+                    .to_owned(),
+            )
+            .await?;
+        let db = manager.get_connection();
+        // Every collect used to save the albums and tracks it saw again, so a song played ten times was ten tracks
+        // The copies are merged into the oldest of them, which is what plays and album tracks point at from now on
+        // Existing albums have no URI yet, so they're the same album when their title, release date and artists are
+        db.execute_unprepared(
+            "CREATE TEMPORARY TABLE album_merge ON COMMIT DROP AS
+            WITH keyed AS (
+                SELECT album.id, album.title, album.release_date,
+                    COALESCE((
+                        SELECT string_agg(album_artist.artist_id::text, ',' ORDER BY album_artist.artist_id)
+                        FROM album_artist WHERE album_artist.album_id = album.id
+                    ), '') AS artists
+                FROM album
+            )
+            SELECT id, MIN(id) OVER (PARTITION BY title, release_date, artists) AS keep_id FROM keyed",
+        )
+        .await?;
+        db.execute_unprepared(
+            "DELETE FROM album_merge WHERE id = keep_id;
+            INSERT INTO album_track (album_id, track_id)
+                SELECT album_merge.keep_id, album_track.track_id FROM album_track
+                JOIN album_merge ON album_merge.id = album_track.album_id
+                ON CONFLICT DO NOTHING;
+            DELETE FROM album_track USING album_merge WHERE album_track.album_id = album_merge.id;
+            DELETE FROM album_artist USING album_merge WHERE album_artist.album_id = album_merge.id;
+            DELETE FROM album USING album_merge WHERE album.id = album_merge.id",
+        )
+        .await?;
+        // Tracks with a URI are the same track when their URI is
+        db.execute_unprepared(
+            "CREATE TEMPORARY TABLE track_merge ON COMMIT DROP AS
+            SELECT id, MIN(id) OVER (PARTITION BY uri) AS keep_id FROM track WHERE uri IS NOT NULL",
+        )
+        .await?;
+        // Tracks saved before URIs were are merged into a track with a URI on the same album with the same title,
+        // or into the oldest of themselves when there is none. Their duration is missing if they're even older
+        db.execute_unprepared(
+            "WITH keyed AS (
+                SELECT track.id, track.uri, track.title, track.duration_ms,
+                    (SELECT MIN(album_track.album_id) FROM album_track WHERE album_track.track_id = track.id) AS album_id
+                FROM track
+            ),
+            legacy AS (
+                SELECT keyed.id,
+                    (
+                        SELECT MIN(with_uri.id) FROM keyed with_uri
+                        WHERE with_uri.uri IS NOT NULL
+                            AND with_uri.title = keyed.title
+                            AND with_uri.album_id IS NOT DISTINCT FROM keyed.album_id
+                            AND (keyed.duration_ms IS NULL OR with_uri.duration_ms = keyed.duration_ms)
+                    ) AS match_id,
+                    MIN(keyed.id) OVER (PARTITION BY keyed.title, keyed.album_id, keyed.duration_ms) AS oldest_id
+                FROM keyed WHERE keyed.uri IS NULL
+            )
+            INSERT INTO track_merge (id, keep_id)
+                SELECT legacy.id, COALESCE(track_merge.keep_id, legacy.oldest_id) FROM legacy
+                LEFT JOIN track_merge ON track_merge.id = legacy.match_id",
+        )
+        .await?;
+        db.execute_unprepared(
+            "DELETE FROM track_merge WHERE id = keep_id;
+            UPDATE play_log SET track_id = track_merge.keep_id
+                FROM track_merge WHERE play_log.track_id = track_merge.id;
+            UPDATE play_session SET track_id = track_merge.keep_id
+                FROM track_merge WHERE play_session.track_id = track_merge.id;
+            INSERT INTO album_track (album_id, track_id)
+                SELECT album_track.album_id, track_merge.keep_id FROM album_track
+                JOIN track_merge ON track_merge.id = album_track.track_id
+                ON CONFLICT DO NOTHING;
+            DELETE FROM album_track USING track_merge WHERE album_track.track_id = track_merge.id;
+            DELETE FROM track USING track_merge WHERE track.id = track_merge.id",
+        )
+        .await?;
+        // Now that there are no copies left, make sure there won't be any again
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_track_uri")
+                    .table(Track::Table)
+                    .col(Track::Uri)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_album_uri")
+                    .table(Album::Table)
+                    .col(Album::Uri)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // The merged copies are gone for good, only the constraints are undone
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_track_uri")
+                    .table(Track::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_album_uri")
+                    .table(Album::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Album::Table)
+                    .drop_column(Album::Uri)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Album {
+    Table,
+    Uri,
+}
+
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    Uri,
+}
@@ -0,0 +1,43 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Plays from Last.fm and ListenBrainz name the album, but not when it was released
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Album::Table)
+                    .modify_column(ColumnDef::new(Album::ReleaseDate).date().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Albums without a release date get the earliest one, so the column can be required again
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "UPDATE album SET release_date = '0001-01-01' WHERE release_date IS NULL",
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Album::Table)
+                    .modify_column(ColumnDef::new(Album::ReleaseDate).date().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Album {
+    Table,
+    ReleaseDate,
+}
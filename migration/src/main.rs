@@ -1,6 +1,12 @@
+use crypto::Keyring;
 use sea_orm_migration::prelude::*;
 
 #[async_std::main]
 async fn main() {
+    // Migrations encrypting account tokens need the master keys, which are only required when there are accounts
+    if std::env::var(crypto::KEYS_VAR).is_ok() {
+        let keyring = Keyring::from_env().expect("Invalid token encryption keys");
+        crypto::install_keyring(keyring);
+    }
     cli::run_cli(migration::Migrator).await;
 }